@@ -0,0 +1,689 @@
+use std::{fs, path::PathBuf};
+
+use crate::{
+    TrackAnalysis,
+    error::{AppError, Result},
+    loudness::{OUTLIER_LU, format_loudness, measure_segments, total_loudness},
+    silence::{Segment, write_segment, write_segment_clips},
+};
+
+/// Shortest clip a split is allowed to produce, in seconds
+const MIN_SPLIT_PART: f64 = 0.05;
+
+/// A manual fix for a clip that silence detection got wrong
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClipEdit {
+    /// Join the clip with the one after it
+    MergeWithNext(usize),
+    /// Cut the clip in two, `offset` seconds after its start
+    Split { index: usize, offset: f64 },
+    /// Throw the clip away
+    Drop(usize),
+    /// Move the clip's edges in the source recording, in seconds
+    Resize { index: usize, start: f64, end: f64 },
+}
+
+/// A change to the entries of a track. Entries and clips are paired by
+/// position, so moves and removals take the entry's clip along.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EntryEdit {
+    /// Replace the text of an entry
+    SetText { index: usize, text: String },
+    /// Swap the entry and its clip with the ones before them
+    MoveUp(usize),
+    /// Swap the entry and its clip with the ones after them
+    MoveDown(usize),
+    /// Remove the entry and its clip
+    Remove(usize),
+    /// Add an empty entry at this position. It gets a clip covering the
+    /// silence after the previous clip, which can then be adjusted in the
+    /// waveform.
+    Insert(usize),
+    /// Remove the entries that are empty or only whitespace
+    RemoveBlank,
+}
+
+/// Any edit to an analyzed track
+#[derive(Debug, Clone, PartialEq)]
+pub enum TrackEdit {
+    Clip(ClipEdit),
+    Entry(EntryEdit),
+}
+
+impl TrackEdit {
+    /// Short description for the undo history, with 1-based numbers
+    pub fn describe(&self) -> String {
+        match self {
+            TrackEdit::Clip(ClipEdit::MergeWithNext(index)) => {
+                format!("Merge clips {} and {}", index + 1, index + 2)
+            }
+            TrackEdit::Clip(ClipEdit::Split { index, .. }) => format!("Split clip {}", index + 1),
+            TrackEdit::Clip(ClipEdit::Drop(index)) => format!("Drop clip {}", index + 1),
+            TrackEdit::Clip(ClipEdit::Resize { index, .. }) => format!("Resize clip {}", index + 1),
+            TrackEdit::Entry(EntryEdit::SetText { index, .. }) => {
+                format!("Edit entry {}", index + 1)
+            }
+            TrackEdit::Entry(EntryEdit::MoveUp(index)) => format!("Move entry {} up", index + 1),
+            TrackEdit::Entry(EntryEdit::MoveDown(index)) => {
+                format!("Move entry {} down", index + 1)
+            }
+            TrackEdit::Entry(EntryEdit::Remove(index)) => format!("Remove entry {}", index + 1),
+            TrackEdit::Entry(EntryEdit::Insert(index)) => format!("Insert entry {}", index + 1),
+            TrackEdit::Entry(EntryEdit::RemoveBlank) => "Ignore blank lines".to_string(),
+        }
+    }
+}
+
+/// Entries and clips of a track, as saved in the undo history
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlignmentState {
+    text_entries: Vec<String>,
+    segments: Vec<Segment>,
+    audio_clips: Vec<PathBuf>,
+}
+
+impl TrackAnalysis {
+    /// Whether this track has one clip per text line
+    pub fn is_aligned(&self) -> bool {
+        self.text_entries.len() == self.audio_clips.len()
+    }
+
+    /// Applies a clip edit to the segments and re-cuts the clip files
+    pub fn apply_clip_edit(&mut self, edit: ClipEdit) -> Result<()> {
+        let segments = &mut self.segments;
+        match edit {
+            ClipEdit::MergeWithNext(index) if index + 1 < segments.len() => {
+                // Reordered entries can leave the next clip earlier in the recording
+                let next = segments.remove(index + 1);
+                let segment = &mut segments[index];
+                segment.start = segment.start.min(next.start);
+                segment.end = segment.end.max(next.end);
+            }
+            ClipEdit::Split { index, offset } if index < segments.len() => {
+                let segment = segments[index];
+                let at = segment.start + offset;
+                if at - segment.start < MIN_SPLIT_PART || segment.end - at < MIN_SPLIT_PART {
+                    return Ok(());
+                }
+
+                segments[index].end = at;
+                segments.insert(
+                    index + 1,
+                    Segment {
+                        start: at,
+                        end: segment.end,
+                    },
+                );
+            }
+            ClipEdit::Drop(index) if index < segments.len() => {
+                segments.remove(index);
+            }
+            // Only this clip changes, so there is no need to re-cut the others
+            ClipEdit::Resize { index, start, end } if index < segments.len() && start < end => {
+                segments[index] = Segment { start, end };
+                return self.recut_clip(index);
+            }
+            _ => return Ok(()),
+        }
+
+        self.recut_clips()
+    }
+
+    pub fn apply_edit(&mut self, edit: TrackEdit) -> Result<()> {
+        match edit {
+            TrackEdit::Clip(edit) => self.apply_clip_edit(edit),
+            TrackEdit::Entry(edit) => self.apply_entry_edit(edit),
+        }
+    }
+
+    /// Applies an entry edit to the text and the clips paired with it
+    pub fn apply_entry_edit(&mut self, edit: EntryEdit) -> Result<()> {
+        let len = self.text_entries.len();
+        match edit {
+            EntryEdit::SetText { index, text } if index < len => self.text_entries[index] = text,
+            EntryEdit::MoveUp(index) if index > 0 && index < len => self.swap_entries(index - 1),
+            EntryEdit::MoveDown(index) if index + 1 < len => self.swap_entries(index),
+            EntryEdit::Remove(index) if index < len => {
+                self.text_entries.remove(index);
+                if index < self.segments.len() {
+                    self.segments.remove(index);
+                }
+                if index < self.audio_clips.len() {
+                    self.audio_clips.remove(index);
+                }
+            }
+            EntryEdit::Insert(index) if index <= len => {
+                self.text_entries.insert(index, String::new());
+
+                // Entries after the new one keep their clips only if it gets
+                // one too; when there is no room it is left without, and the
+                // alignment view shows the mismatch
+                if index <= self.segments.len()
+                    && let Some(segment) = self.free_segment_after(index.checked_sub(1))
+                {
+                    self.segments.insert(index, segment);
+                    self.recut_clips()?;
+                }
+            }
+            EntryEdit::RemoveBlank => self.drop_blank_lines(),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Swaps entry `index` with the next one, along with their clips
+    fn swap_entries(&mut self, index: usize) {
+        self.text_entries.swap(index, index + 1);
+        if index + 1 < self.segments.len() {
+            self.segments.swap(index, index + 1);
+        }
+        if index + 1 < self.audio_clips.len() {
+            self.audio_clips.swap(index, index + 1);
+        }
+    }
+
+    /// The silence between the end of segment `after` (or the start of the
+    /// recording) and the next segment in time, if it is long enough for a clip
+    fn free_segment_after(&self, after: Option<usize>) -> Option<Segment> {
+        let source = self.source.as_ref()?;
+        let start = match after {
+            Some(index) => self.segments.get(index)?.end,
+            None => 0.0,
+        };
+        let end = self
+            .segments
+            .iter()
+            .map(|segment| segment.start)
+            .filter(|&time| time >= start)
+            .fold(source.duration(), f64::min);
+        (end - start >= MIN_SPLIT_PART).then_some(Segment { start, end })
+    }
+
+    /// Text that is still being typed into an entry, as the edit that applies it
+    pub fn take_entry_text(&mut self) -> Option<TrackEdit> {
+        let (index, text) = self.editing_entry.take()?;
+        Some(TrackEdit::Entry(EntryEdit::SetText { index, text }))
+    }
+
+    pub fn alignment_state(&self) -> AlignmentState {
+        AlignmentState {
+            text_entries: self.text_entries.clone(),
+            segments: self.segments.clone(),
+            audio_clips: self.audio_clips.clone(),
+        }
+    }
+
+    /// Goes back or forward to a state from the undo history
+    pub fn restore_alignment(&mut self, state: &AlignmentState) -> Result<()> {
+        self.editing_entry = None;
+        self.pending_split = None;
+        self.text_entries = state.text_entries.clone();
+        self.segments = state.segments.clone();
+        if self.source.is_some() {
+            // Clip files are numbered by position and may have been re-cut since
+            self.recut_clips()
+        } else {
+            self.audio_clips = state.audio_clips.clone();
+            Ok(())
+        }
+    }
+
+    /// Measures the clips whose segments changed since the last call
+    pub fn update_loudness(&mut self) {
+        let Some(source) = &self.source else {
+            self.loudness.clear();
+            return;
+        };
+        let stale = self.loudness.len() != self.segments.len()
+            || self
+                .loudness
+                .iter()
+                .zip(&self.segments)
+                .any(|(clip, segment)| clip.segment != *segment);
+        if stale {
+            self.loudness = measure_segments(source, &self.segments, &self.loudness);
+        }
+    }
+
+    /// Drops text lines that are empty or only whitespace
+    pub fn drop_blank_lines(&mut self) {
+        self.text_entries.retain(|entry| !entry.trim().is_empty());
+    }
+
+    /// Rewrites every clip file from the current segments
+    pub fn recut_clips(&mut self) -> Result<()> {
+        let Some(source) = &self.source else {
+            return Ok(());
+        };
+
+        // Clips are numbered by position, so stale files would shadow the new ones
+        if let Ok(entries) = fs::read_dir(&self.clips_dir) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.extension().and_then(|s| s.to_str()) == Some("wav") {
+                    fs::remove_file(&path).map_err(|e| {
+                        AppError::io(format!("Failed to remove {}", path.display()), e)
+                    })?;
+                }
+            }
+        }
+
+        self.audio_clips =
+            write_segment_clips(source, &self.segments, &self.clips_dir, &mut |_, _| Ok(()))?;
+        Ok(())
+    }
+
+    /// Rewrites the file of one clip from its segment
+    pub fn recut_clip(&mut self, index: usize) -> Result<()> {
+        let (Some(source), Some(segment), Some(path)) = (
+            &self.source,
+            self.segments.get(index),
+            self.audio_clips.get(index),
+        ) else {
+            return self.recut_clips();
+        };
+        write_segment(source, segment, path)
+    }
+}
+
+/// Shows the text lines next to the detected clips and their loudness,
+/// highlights rows without a counterpart and clips much louder or quieter
+/// than `median_loudness`, and offers tools to fix the clips. Returns the
+/// edit the user asked for, if any.
+pub(crate) fn render_alignment_view(
+    ui: &mut egui::Ui,
+    track: &mut TrackAnalysis,
+    median_loudness: Option<f64>,
+) -> Option<TrackEdit> {
+    let mut edit = None;
+    let rows = track.text_entries.len().max(track.audio_clips.len());
+    let error_color = ui.visuals().error_fg_color;
+    let warn_color = ui.visuals().warn_fg_color;
+
+    ui.horizontal(|ui| {
+        let counts = format!(
+            "{}: {} lines, {} clips",
+            track.label,
+            track.text_entries.len(),
+            track.audio_clips.len()
+        );
+        if track.is_aligned() {
+            ui.label(counts);
+        } else {
+            ui.colored_label(error_color, counts);
+        }
+        if !track.loudness.is_empty() {
+            let total = total_loudness(&track.loudness);
+            ui.weak(format!(
+                "{} overall, peak {:.1} dBFS",
+                format_loudness(total.integrated),
+                total.peak
+            ));
+        }
+
+        let blank_lines = track
+            .text_entries
+            .iter()
+            .filter(|entry| entry.trim().is_empty())
+            .count();
+        if blank_lines > 0
+            && ui
+                .button(format!("Ignore {} Blank Line(s)", blank_lines))
+                .clicked()
+        {
+            edit = Some(TrackEdit::Entry(EntryEdit::RemoveBlank));
+        }
+    });
+
+    egui::ScrollArea::vertical()
+        .id_source(("alignment", &track.label))
+        .max_height(300.0)
+        .show(ui, |ui| {
+            egui::Grid::new(("alignment_grid", &track.label))
+                .num_columns(4)
+                .striped(true)
+                .show(ui, |ui| {
+                    for row in 0..rows {
+                        let line = track.text_entries.get(row);
+                        let segment = track.segments.get(row).copied();
+                        let unmatched = line.is_none() || segment.is_none();
+
+                        let line_text = match line {
+                            Some(line) if line.trim().is_empty() => "(blank line)".to_string(),
+                            Some(line) => line.clone(),
+                            None => "(no text line)".to_string(),
+                        };
+                        let clip_text = match segment {
+                            Some(segment) => format!(
+                                "{:.2}s – {:.2}s ({:.2}s)",
+                                segment.start,
+                                segment.end,
+                                segment.duration()
+                            ),
+                            None => "(no clip)".to_string(),
+                        };
+
+                        ui.label(format!("{}.", row + 1));
+                        if unmatched {
+                            ui.colored_label(error_color, line_text);
+                        } else {
+                            ui.label(line_text);
+                        }
+
+                        match track.loudness.get(row) {
+                            Some(clip) => {
+                                let text = format_loudness(clip.loudness.integrated);
+                                let difference = clip
+                                    .loudness
+                                    .integrated
+                                    .zip(median_loudness)
+                                    .map(|(lufs, median)| lufs - median);
+                                match difference {
+                                    Some(lu) if lu.abs() > OUTLIER_LU => {
+                                        ui.colored_label(warn_color, text).on_hover_text(format!(
+                                            "{:.1} LU {} than the median clip; peak {:.1} dBFS",
+                                            lu.abs(),
+                                            if lu > 0.0 { "louder" } else { "quieter" },
+                                            clip.loudness.peak
+                                        ));
+                                    }
+                                    _ => {
+                                        ui.label(text).on_hover_text(format!(
+                                            "Peak {:.1} dBFS",
+                                            clip.loudness.peak
+                                        ));
+                                    }
+                                }
+                            }
+                            None => {
+                                ui.label("");
+                            }
+                        }
+
+                        ui.horizontal(|ui| {
+                            if unmatched {
+                                ui.colored_label(error_color, clip_text);
+                            } else {
+                                ui.label(clip_text);
+                            }
+
+                            let Some(segment) = segment else {
+                                return;
+                            };
+
+                            if row + 1 < track.segments.len()
+                                && ui
+                                    .small_button("Merge ↓")
+                                    .on_hover_text("Join with the next clip")
+                                    .clicked()
+                            {
+                                edit = Some(TrackEdit::Clip(ClipEdit::MergeWithNext(row)));
+                            }
+
+                            match &mut track.pending_split {
+                                Some((index, offset)) if *index == row => {
+                                    ui.add(
+                                        egui::DragValue::new(offset)
+                                            .speed(0.01)
+                                            .clamp_range(0.0..=segment.duration())
+                                            .suffix(" s"),
+                                    );
+                                    if ui.small_button("Split Here").clicked() {
+                                        edit = Some(TrackEdit::Clip(ClipEdit::Split {
+                                            index: row,
+                                            offset: *offset,
+                                        }));
+                                        track.pending_split = None;
+                                    } else if ui.small_button("Cancel").clicked() {
+                                        track.pending_split = None;
+                                    }
+                                }
+                                _ => {
+                                    if ui
+                                        .small_button("Split…")
+                                        .on_hover_text("Cut this clip in two")
+                                        .clicked()
+                                    {
+                                        track.pending_split = Some((row, segment.duration() / 2.0));
+                                    }
+                                }
+                            }
+
+                            if ui
+                                .small_button("Drop")
+                                .on_hover_text("Remove this clip")
+                                .clicked()
+                            {
+                                edit = Some(TrackEdit::Clip(ClipEdit::Drop(row)));
+                            }
+                        });
+                        ui.end_row();
+                    }
+                });
+        });
+
+    edit
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::decode::DecodedAudio;
+
+    /// A track over ten seconds of silent source audio, with its clips cut
+    fn track(name: &str, entries: &[&str], spans: &[(f64, f64)]) -> TrackAnalysis {
+        let clips_dir = std::env::temp_dir().join(format!(
+            "malti_prac_psm_gen_test_{}_{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&clips_dir);
+        fs::create_dir_all(&clips_dir).unwrap();
+
+        let mut track = TrackAnalysis {
+            text_entries: entries.iter().map(|entry| entry.to_string()).collect(),
+            source: Some(Arc::new(DecodedAudio {
+                samples: vec![0; 10_000],
+                sample_rate: 1000,
+                channels: 1,
+            })),
+            segments: spans
+                .iter()
+                .map(|&(start, end)| Segment { start, end })
+                .collect(),
+            clips_dir,
+            ..Default::default()
+        };
+        track.recut_clips().unwrap();
+        track
+    }
+
+    fn spans(track: &TrackAnalysis) -> Vec<(f64, f64)> {
+        track
+            .segments
+            .iter()
+            .map(|segment| (segment.start, segment.end))
+            .collect()
+    }
+
+    /// Frames in each clip file, which follow the segments after every edit
+    fn clip_frames(track: &TrackAnalysis) -> Vec<u32> {
+        track
+            .audio_clips
+            .iter()
+            .map(|path| hound::WavReader::open(path).unwrap().duration())
+            .collect()
+    }
+
+    fn remove(track: TrackAnalysis) {
+        fs::remove_dir_all(&track.clips_dir).unwrap();
+    }
+
+    #[test]
+    fn merge_joins_a_clip_with_the_next_one() {
+        let mut track = track(
+            "merge",
+            &["a", "b", "c"],
+            &[(1.0, 2.0), (3.0, 4.0), (5.0, 6.0)],
+        );
+        track.apply_clip_edit(ClipEdit::MergeWithNext(0)).unwrap();
+        assert_eq!(spans(&track), [(1.0, 4.0), (5.0, 6.0)]);
+        assert_eq!(clip_frames(&track), [3000, 1000]);
+        assert!(!track.clips_dir.join("2.wav").exists());
+
+        // The last clip has nothing to merge with
+        track.apply_clip_edit(ClipEdit::MergeWithNext(1)).unwrap();
+        assert_eq!(spans(&track), [(1.0, 4.0), (5.0, 6.0)]);
+        remove(track);
+    }
+
+    #[test]
+    fn merge_covers_reordered_clips() {
+        let mut track = track("merge_reordered", &["a", "b"], &[(3.0, 4.0), (1.0, 2.0)]);
+        track.apply_clip_edit(ClipEdit::MergeWithNext(0)).unwrap();
+        assert_eq!(spans(&track), [(1.0, 4.0)]);
+        remove(track);
+    }
+
+    #[test]
+    fn split_cuts_a_clip_in_two() {
+        let mut track = track("split", &["a", "b"], &[(1.0, 2.0), (3.0, 4.0)]);
+        track
+            .apply_clip_edit(ClipEdit::Split {
+                index: 1,
+                offset: 0.25,
+            })
+            .unwrap();
+        track
+            .apply_clip_edit(ClipEdit::Split {
+                index: 0,
+                offset: 0.5,
+            })
+            .unwrap();
+        assert_eq!(
+            spans(&track),
+            [(1.0, 1.5), (1.5, 2.0), (3.0, 3.25), (3.25, 4.0)]
+        );
+        assert_eq!(clip_frames(&track), [500, 500, 250, 750]);
+
+        // Too close to an edge, or past the last clip
+        for (index, offset) in [(0, 0.01), (0, 0.49), (4, 0.1)] {
+            track
+                .apply_clip_edit(ClipEdit::Split { index, offset })
+                .unwrap();
+        }
+        assert_eq!(track.segments.len(), 4);
+        remove(track);
+    }
+
+    #[test]
+    fn drop_and_resize_change_one_clip() {
+        let mut track = track(
+            "drop",
+            &["a", "b", "c"],
+            &[(1.0, 2.0), (3.0, 4.0), (5.0, 6.0)],
+        );
+        track.apply_clip_edit(ClipEdit::Drop(2)).unwrap();
+        track.apply_clip_edit(ClipEdit::Drop(0)).unwrap();
+        track.apply_clip_edit(ClipEdit::Drop(1)).unwrap();
+        assert_eq!(spans(&track), [(3.0, 4.0)]);
+
+        track
+            .apply_clip_edit(ClipEdit::Resize {
+                index: 0,
+                start: 2.5,
+                end: 4.5,
+            })
+            .unwrap();
+        // An empty range is ignored
+        track
+            .apply_clip_edit(ClipEdit::Resize {
+                index: 0,
+                start: 4.0,
+                end: 4.0,
+            })
+            .unwrap();
+        assert_eq!(spans(&track), [(2.5, 4.5)]);
+        assert_eq!(clip_frames(&track), [2000]);
+        remove(track);
+    }
+
+    #[test]
+    fn moved_and_removed_entries_take_their_clips() {
+        let mut track = track(
+            "move",
+            &["a", "b", "c"],
+            &[(1.0, 2.0), (3.0, 4.0), (5.0, 6.0)],
+        );
+        let clips = track.audio_clips.clone();
+
+        // Nothing is above the first entry or below the last
+        track.apply_entry_edit(EntryEdit::MoveUp(0)).unwrap();
+        track.apply_entry_edit(EntryEdit::MoveDown(2)).unwrap();
+        assert_eq!(track.text_entries, ["a", "b", "c"]);
+
+        track.apply_entry_edit(EntryEdit::MoveUp(2)).unwrap();
+        track.apply_entry_edit(EntryEdit::MoveDown(0)).unwrap();
+        assert_eq!(track.text_entries, ["c", "a", "b"]);
+        assert_eq!(spans(&track), [(5.0, 6.0), (1.0, 2.0), (3.0, 4.0)]);
+        assert_eq!(
+            track.audio_clips,
+            [clips[2].clone(), clips[0].clone(), clips[1].clone()]
+        );
+
+        track.apply_entry_edit(EntryEdit::Remove(0)).unwrap();
+        track.apply_entry_edit(EntryEdit::Remove(1)).unwrap();
+        track.apply_entry_edit(EntryEdit::Remove(1)).unwrap();
+        assert_eq!(track.text_entries, ["a"]);
+        assert_eq!(spans(&track), [(1.0, 2.0)]);
+        assert_eq!(track.audio_clips, [clips[0].clone()]);
+        remove(track);
+    }
+
+    #[test]
+    fn inserted_entries_get_the_silence_around_them() {
+        let mut track = track("insert", &["a", "b"], &[(1.0, 2.0), (2.0, 4.0)]);
+        track.apply_entry_edit(EntryEdit::Insert(2)).unwrap();
+        track.apply_entry_edit(EntryEdit::Insert(0)).unwrap();
+        assert_eq!(track.text_entries, ["", "a", "b", ""]);
+        assert_eq!(
+            spans(&track),
+            [(0.0, 1.0), (1.0, 2.0), (2.0, 4.0), (4.0, 10.0)]
+        );
+        assert_eq!(clip_frames(&track), [1000, 1000, 2000, 6000]);
+
+        // No silence between the first two clips, so the entry has no clip
+        track.apply_entry_edit(EntryEdit::Insert(2)).unwrap();
+        assert_eq!(track.text_entries.len(), 5);
+        assert_eq!(track.segments.len(), 4);
+        assert!(!track.is_aligned());
+
+        // Past the end is ignored
+        track.apply_entry_edit(EntryEdit::Insert(6)).unwrap();
+        assert_eq!(track.text_entries.len(), 5);
+        remove(track);
+    }
+
+    #[test]
+    fn text_edits_leave_the_clips_alone() {
+        let mut track = track("text", &["a", " ", "c"], &[(1.0, 2.0), (3.0, 4.0)]);
+        track
+            .apply_entry_edit(EntryEdit::SetText {
+                index: 2,
+                text: "changed".to_string(),
+            })
+            .unwrap();
+        track
+            .apply_entry_edit(EntryEdit::SetText {
+                index: 3,
+                text: "ignored".to_string(),
+            })
+            .unwrap();
+        track.apply_entry_edit(EntryEdit::RemoveBlank).unwrap();
+        assert_eq!(track.text_entries, ["a", "changed"]);
+        assert_eq!(spans(&track), [(1.0, 2.0), (3.0, 4.0)]);
+        remove(track);
+    }
+}
@@ -2,275 +2,678 @@ use std::{
     env::temp_dir,
     fs,
     path::{Path, PathBuf},
-    process::Command,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
 };
 
-use crate::{AppScreen, MyApp, combine::combine_clips_alternately};
+use crate::{
+    AnalysisData, AppScreen, MyApp, Track, TrackAnalysis,
+    alignment::{EntryEdit, TrackEdit, render_alignment_view},
+    decode::{DecodedAudio, decode_audio},
+    error::{AppError, Result},
+    job::{self, JobEvent, JobInput, Stage, TrackAudio, TrackInput, check_cancelled},
+    loudness::median_loudness,
+    player::{PlaybackId, render_transport},
+    sentences::split_sentences,
+    silence::{Segment, Segmentation, SilenceParams, detect_segments, write_segment_clips},
+    waveform::{WaveformAction, render_waveform},
+};
 
 impl MyApp {
     // Handler for the analyze text button
-    pub fn handle_analyze_text(&mut self) {
+    pub fn handle_analyze_text(&mut self, ctx: &egui::Context) {
+        self.player.stop();
+        self.history.forget_alignment();
+
+        // Cut the text areas into sentences
+        self.analysis_data.tracks = self
+            .tracks
+            .iter()
+            .map(|track| TrackAnalysis {
+                label: track.label.clone(),
+                text_entries: split_sentences(&track.text, &self.settings.sentence_split),
+                source_path: track.audio_file.clone(),
+                segmentation: self.detection_params(track),
+                ..Default::default()
+            })
+            .collect();
+
+        let input = self.job_input(
+            self.tracks
+                .iter()
+                .zip(&self.analysis_data.tracks)
+                .map(|(track, analysis)| TrackInput {
+                    label: track.label.clone(),
+                    entries: analysis.text_entries.clone(),
+                    audio: track.audio_file.clone().map(|file| match &track.labels {
+                        Some(labels) => TrackAudio::Segments {
+                            file,
+                            segments: labels.segments.clone(),
+                            params: None,
+                        },
+                        None => TrackAudio::Detect {
+                            params: self.settings.segmentation_for(&file).clone(),
+                            file,
+                        },
+                    }),
+                })
+                .collect(),
+        );
+
+        self.start_job(input, ctx);
+        self.current_screen = AppScreen::TextAnalyzer;
+    }
+
+    /// Combines the current clips, e.g. after the alignment has been fixed by hand
+    pub fn handle_combine(&mut self, ctx: &egui::Context) {
+        let input = self.job_input(
+            self.analysis_data
+                .tracks
+                .iter()
+                .map(|track| TrackInput {
+                    label: track.label.clone(),
+                    entries: track.text_entries.clone(),
+                    audio: Some(TrackAudio::Clips {
+                        clips: track.audio_clips.clone(),
+                        source: track.source_path.clone(),
+                        segments: track.segments.clone(),
+                        params: track.segmentation.clone(),
+                    }),
+                })
+                .collect(),
+        );
+
+        self.start_job(input, ctx);
+    }
+
+    /// Silence detection settings that cut the track's recording, unless
+    /// labels were imported for it
+    pub(crate) fn detection_params(&self, track: &Track) -> Option<SilenceParams> {
+        match (&track.audio_file, &track.labels) {
+            (Some(file), None) => Some(self.settings.segmentation_for(file).clone()),
+            _ => None,
+        }
+    }
+
+    /// Pipeline input that combines into the preview files in the scratch
+    /// directory; the Export panel copies them to where the user wants them
+    pub(crate) fn job_input(&self, tracks: Vec<TrackInput>) -> JobInput {
+        let work_dir = job_dir();
+        JobInput {
+            tracks,
+            lesson: self.settings.lesson.clone(),
+            output_audio: work_dir.join("preview.wav"),
+            output_stops: work_dir.join("preview.json"),
+            work_dir,
+        }
+    }
+
+    pub(crate) fn start_job(&mut self, input: JobInput, ctx: &egui::Context) {
+        if let Some(job) = &self.analysis_data.job {
+            job.cancel();
+        }
+
         self.analysis_data.is_processing = true;
+        self.analysis_data.progress = 0.0;
+        self.analysis_data.error = None;
+        self.analysis_data.combined = None;
+        self.analysis_data.lesson_audio = None;
+        self.analysis_data.exported = Default::default();
         self.analysis_data.processing_status = "Starting audio processing...".to_string();
+        self.analysis_data.job_dirs.push(input.work_dir.clone());
+        self.analysis_data.job = Some(job::spawn(input, ctx.clone()));
+    }
 
-        // Convert text areas to line vectors
-        self.analysis_data.text_entries_1 = self
-            .text_area_1
-            .split('\n')
-            .map(|s| s.to_string())
-            .collect();
+    /// Forgets the analysis, but keeps track of the scratch directories so
+    /// they are still deleted
+    pub(crate) fn reset_analysis(&mut self) {
+        self.analysis_data = AnalysisData {
+            job_dirs: std::mem::take(&mut self.analysis_data.job_dirs),
+            ..Default::default()
+        };
+    }
 
-        self.analysis_data.text_entries_2 = self
-            .text_area_2
-            .split('\n')
-            .map(|s| s.to_string())
-            .collect();
+    /// Deletes the scratch directories of earlier jobs, except the ones the
+    /// analyzed tracks still take their clips from
+    fn remove_unused_job_dirs(&mut self) {
+        let Some(last) = self.analysis_data.job_dirs.pop() else {
+            return;
+        };
+        let tracks = &self.analysis_data.tracks;
+        self.analysis_data.job_dirs.retain(|dir| {
+            let in_use = tracks.iter().any(|track| {
+                track.clips_dir.starts_with(dir)
+                    || track.audio_clips.iter().any(|clip| clip.starts_with(dir))
+            });
+            if !in_use {
+                let _ = fs::remove_dir_all(dir);
+            }
+            in_use
+        });
+        self.analysis_data.job_dirs.push(last);
+    }
 
-        // Process MP3 files if they exist
-        if let Some(mp3_path) = &self.mp3_file_1 {
-            match self.process_mp3_file(mp3_path, 1) {
-                Ok(clips) => {
-                    println!("{:?}", clips);
-                    self.analysis_data.audio_clips_1 = clips;
-                    println!("{:?}", self.analysis_data.audio_clips_1);
+    /// Applies whatever the background job has reported since the last frame
+    pub fn poll_analysis_job(&mut self) {
+        while let Some(event) = self.analysis_data.job.as_ref().and_then(|job| job.poll()) {
+            match event {
+                JobEvent::Progress { stage, fraction } => {
+                    self.analysis_data.processing_status = stage.to_string();
+                    self.analysis_data.progress = fraction;
                 }
-                Err(e) => {
-                    self.analysis_data.processing_status =
-                        format!("Error processing MP3 file 1: {}", e);
-                    self.analysis_data.is_processing = false;
-                    return;
+                JobEvent::TrackDone { track, processed } => {
+                    if let Some(analysis) = self.analysis_data.tracks.get_mut(track) {
+                        analysis.source = Some(processed.source);
+                        analysis.segments = processed.segments;
+                        analysis.clips_dir = processed.clips_dir;
+                        analysis.audio_clips = processed.clips;
+                    }
                 }
-            }
-        }
-
-        if let Some(mp3_path) = &self.mp3_file_2 {
-            match self.process_mp3_file(mp3_path, 2) {
-                Ok(clips) => self.analysis_data.audio_clips_2 = clips,
-                Err(e) => {
-                    self.analysis_data.processing_status =
-                        format!("Error processing MP3 file 2: {}", e);
+                JobEvent::Finished(result) => {
+                    match result {
+                        Ok(lesson) => {
+                            self.analysis_data.processing_status =
+                                "Processing completed! Export the lesson below.".to_string();
+                            self.analysis_data.combined = Some(lesson);
+                            self.analysis_data.lesson_audio = None;
+                        }
+                        Err(AppError::Cancelled) => {
+                            self.analysis_data.processing_status =
+                                "Processing cancelled".to_string();
+                        }
+                        Err(e) => {
+                            self.analysis_data.processing_status = "Processing failed".to_string();
+                            self.analysis_data.error = Some(e);
+                        }
+                    }
                     self.analysis_data.is_processing = false;
-                    return;
+                    self.analysis_data.job = None;
+                    self.remove_unused_job_dirs();
                 }
             }
         }
+    }
+}
 
-        self.analysis_data.is_processing = false;
-        self.analysis_data.processing_status = "Processing completed!".to_string();
-        let a = combine_clips_alternately(
-            &self.analysis_data.audio_clips_1,
-            &self.analysis_data.audio_clips_2,
-            &self.analysis_data.text_entries_1,
-            &self.analysis_data.text_entries_2,
-            &temp_dir().join("audio.wav"),
-            &temp_dir().join("stops.json"),
-        )
-        .unwrap();
+/// Per-process directory for decoded sources, clips and the combined preview.
+/// The GUI deletes it on exit.
+pub fn scratch_dir() -> PathBuf {
+    temp_dir().join(format!("malti_prac_psm_gen_{}", std::process::id()))
+}
 
-        println!("{:?}", a);
+/// Fresh subdirectory of the scratch directory for one job, so a cancelled
+/// job that is still winding down cannot overwrite the files of the next one
+fn job_dir() -> PathBuf {
+    static NEXT_JOB: AtomicUsize = AtomicUsize::new(0);
+    scratch_dir().join(format!("job_{}", NEXT_JOB.fetch_add(1, Ordering::Relaxed)))
+}
 
-        self.current_screen = AppScreen::TextAnalyzer;
-    }
+/// A decoded source together with the clips cut from it
+#[derive(Debug, Clone)]
+pub struct ProcessedTrack {
+    pub source: Arc<DecodedAudio>,
+    pub segments: Vec<Segment>,
+    pub clips_dir: PathBuf,
+    pub clips: Vec<PathBuf>,
+}
 
-    // Convert MP3 to WAV and split by 2-second gaps
-    fn process_mp3_file(&self, mp3_path: &Path, file_id: u8) -> Result<Vec<PathBuf>, String> {
-        let temp_dir = std::env::temp_dir().join(format!("audio_analysis_{}", file_id));
-        let _ = fs::remove_dir_all(&temp_dir);
-        fs::create_dir_all(&temp_dir)
-            .map_err(|e| format!("Failed to create temp directory: {}", e))?;
-
-        // Step 1: Convert MP3 to WAV
-        let wav_path = temp_dir.join("converted.wav");
-        let mp3_to_wav_result = Command::new("ffmpeg")
-            .args([
-                "-i",
-                mp3_path.to_str().unwrap(),
-                "-acodec",
-                "pcm_s16le",
-                "-ar",
-                "44100",
-                "-ac",
-                "2",
-                wav_path.to_str().unwrap(),
-                "-y", // Overwrite output file
-            ])
-            .output();
-
-        match mp3_to_wav_result {
-            Ok(output) => {
-                if !output.status.success() {
-                    return Err(format!(
-                        "FFmpeg conversion failed: {}",
-                        String::from_utf8_lossy(&output.stderr)
-                    ));
-                }
+// Decode the source audio and split it into clips at the configured silences
+// (or at the given boundaries)
+pub fn process_mp3_file(
+    mp3_path: &Path,
+    work_dir: &Path,
+    file_id: usize,
+    label: &str,
+    segmentation: Segmentation,
+    on_stage: &mut dyn FnMut(Stage),
+    cancel: &AtomicBool,
+) -> Result<ProcessedTrack> {
+    let temp_dir = work_dir.join(format!("audio_analysis_{}", file_id));
+    let _ = fs::remove_dir_all(&temp_dir);
+    fs::create_dir_all(&temp_dir)
+        .map_err(|e| AppError::io("Failed to create temp directory", e))?;
+
+    // Step 1: Decode the source in-process and store it as WAV
+    on_stage(Stage::Decoding {
+        track: label.to_string(),
+    });
+    let wav_path = temp_dir.join("converted.wav");
+    let decoded = decode_audio(mp3_path, &temp_dir)?;
+    decoded.write_wav(&wav_path)?;
+    check_cancelled(cancel)?;
+
+    // Step 2: Split by silence and write one WAV per segment
+    on_stage(Stage::Detecting {
+        track: label.to_string(),
+    });
+    let clips_dir = temp_dir.join("clips");
+    std::fs::create_dir_all(&clips_dir)
+        .map_err(|e| AppError::io("Failed to create clips directory", e))?;
+
+    let segments = match segmentation {
+        Segmentation::Detect(params) => detect_segments(&decoded, params),
+        Segmentation::Manual(segments) => segments.to_vec(),
+    };
+    let clips = write_segment_clips(&decoded, &segments, &clips_dir, &mut |index, total| {
+        check_cancelled(cancel)?;
+        on_stage(Stage::WritingClip {
+            track: label.to_string(),
+            index,
+            total,
+        });
+        Ok(())
+    })?;
+
+    Ok(ProcessedTrack {
+        source: Arc::new(decoded),
+        segments,
+        clips_dir,
+        clips,
+    })
+}
+
+impl MyApp {
+    // Modified analysis screen renderer
+    pub fn render_text_analyzer_screen(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Interactive Text & Audio Analyzer");
+        ui.separator();
+
+        if self.analysis_data.is_processing {
+            ui.horizontal(|ui| {
+                ui.spinner();
+                ui.label(&self.analysis_data.processing_status);
+            });
+            ui.add(egui::ProgressBar::new(self.analysis_data.progress).show_percentage());
+
+            if let Some(job) = &self.analysis_data.job
+                && ui.button("Cancel").clicked()
+            {
+                job.cancel();
             }
-            Err(e) => {
-                return Err(format!(
-                    "Failed to run FFmpeg: {}. Make sure FFmpeg is installed.",
-                    e
-                ));
+            return;
+        }
+
+        if !self.analysis_data.processing_status.is_empty() {
+            ui.label(&self.analysis_data.processing_status);
+
+            if let Some(error) = &self.analysis_data.error {
+                ui.colored_label(ui.visuals().error_fg_color, error.to_string());
+                if let Some(hint) = error.hint() {
+                    ui.weak(hint);
+                }
             }
+
+            ui.separator();
         }
 
-        // Step 2: Split WAV by 2-second gaps using silence detection
-        let clips_dir = temp_dir.join("clips");
-        std::fs::create_dir_all(&clips_dir)
-            .map_err(|e| format!("Failed to create clips directory: {}", e))?;
+        if self.analysis_data.tracks.is_empty() {
+            ui.label("Nothing analyzed yet.");
+            return;
+        }
 
-        let split_result = Command::new("/bin/sh")
-            .arg("-c")
-            .arg(format!(r#"ffmpeg -i {} -af silencedetect=d=2 -f null - |& awk '/silencedetect/ {{print $5}}'"#,wav_path.to_str().unwrap()))
-            .output().unwrap();
+        if let Err(e) = render_transport(ui, &mut self.player) {
+            self.analysis_data.error = Some(e);
+        }
+        if self.player.current_id() == Some(PlaybackId::Lesson)
+            && let Some(sentence) = self.current_lesson_sentence()
+        {
+            ui.label(format!("Now: {}", sentence));
+        }
+        ui.separator();
 
-        let res = split_result;
-        let stops_str = String::from_utf8_lossy(&res.stdout);
+        let aligned = self.ready_to_combine();
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(aligned, egui::Button::new("🎧 Combine Lesson"))
+                .on_disabled_hover_text("Every played track needs one clip per text line")
+                .clicked()
+            {
+                self.handle_combine(ui.ctx());
+            }
 
-        let mut stops: Vec<f64> = stops_str
-            .split("\n")
-            .map(|str| str.parse::<f64>().unwrap_or(0.0f64))
-            .collect();
+            if ui
+                .add_enabled(
+                    self.analysis_data.combined.is_some(),
+                    egui::Button::new("▶ Preview Lesson"),
+                )
+                .on_disabled_hover_text("Combine the lesson first")
+                .clicked()
+                && let Err(e) = self.preview_lesson()
+            {
+                self.analysis_data.error = Some(e);
+            }
 
-        stops.pop();
-        stops.pop();
-        stops.insert(0, 0.0f64);
-
-        stops.chunks(2).enumerate().for_each(|(i, stps)| {
-            let _ = Command::new("/bin/sh")
-                .arg("-c")
-                .arg(format!(
-                    "ffmpeg -i {} -ss {} -t {} {}",
-                    wav_path.to_str().unwrap(),
-                    stps[0],
-                    stps[1] - stps[0],
-                    clips_dir.join(format!("{}.wav", i)).to_str().unwrap()
-                ))
-                .output();
+            if !aligned {
+                ui.colored_label(
+                    ui.visuals().error_fg_color,
+                    "Lines and clips do not match — fix the alignment below.",
+                );
+            }
         });
 
-        // Collect all generated clip files
-        let mut clips = Vec::new();
-        if let Ok(entries) = std::fs::read_dir(&clips_dir) {
-            for entry in entries {
-                if let Ok(entry) = entry {
-                    let path = entry.path();
-                    if path.extension().and_then(|s| s.to_str()) == Some("wav") {
-                        let num = path
-                            .file_name()
-                            .unwrap()
-                            .to_str()
-                            .unwrap()
-                            .split_once(".")
-                            .unwrap()
-                            .0
-                            .parse::<u32>()
-                            .unwrap();
-                        clips.push((num, path));
+        // Edits are applied after drawing, so they can go into the history
+        let mut edit = None;
+        for track in &mut self.analysis_data.tracks {
+            track.update_loudness();
+        }
+        let median = median_loudness(
+            self.analysis_data
+                .tracks
+                .iter()
+                .flat_map(|track| &track.loudness),
+        );
+        egui::CollapsingHeader::new("Alignment")
+            .open((!aligned).then_some(true))
+            .show(ui, |ui| {
+                for (i, track) in self.analysis_data.tracks.iter_mut().enumerate() {
+                    if let Some(track_edit) = render_alignment_view(ui, track, median) {
+                        edit = Some((i, track_edit));
+                    }
+                    ui.separator();
+                }
+            });
+
+        egui::CollapsingHeader::new("Waveforms")
+            .default_open(true)
+            .show(ui, |ui| {
+                for (i, track) in self.analysis_data.tracks.iter_mut().enumerate() {
+                    let playhead = track
+                        .source
+                        .as_ref()
+                        .filter(|source| self.player.is_playing_from(source))
+                        .and_then(|_| self.player.position());
+                    let action =
+                        ui.push_id(("waveform", i), |ui| render_waveform(ui, track, playhead));
+                    let result = match action.inner {
+                        Some(WaveformAction::Edit(clip_edit)) => {
+                            edit = Some((i, TrackEdit::Clip(clip_edit)));
+                            Ok(())
+                        }
+                        Some(WaveformAction::Play { from, to }) => match &track.source {
+                            Some(source) => self.player.play(
+                                PlaybackId::Source { track: i },
+                                source.clone(),
+                                from,
+                                to,
+                            ),
+                            None => Ok(()),
+                        },
+                        None => Ok(()),
+                    };
+                    if let Err(e) = result {
+                        self.analysis_data.error = Some(e);
                     }
+                    ui.add_space(5.0);
+                }
+            });
+
+        egui::CollapsingHeader::new("Export")
+            .default_open(true)
+            .show(ui, |ui| self.render_export_panel(ui));
+
+        // One column per track, each with its entries and audio
+        let mut action = None;
+        ui.columns(self.analysis_data.tracks.len(), |columns| {
+            for (i, (column, track)) in columns
+                .iter_mut()
+                .zip(self.analysis_data.tracks.iter_mut())
+                .enumerate()
+            {
+                let playing = match self.player.current_id() {
+                    Some(PlaybackId::Clip { track, index }) if track == i => Some(index),
+                    _ => None,
+                };
+                if let Some(entry_action) = render_interactive_text_column(column, track, playing) {
+                    action = Some((i, entry_action));
                 }
             }
-        }
+        });
 
-        clips.sort_by(|a, b| a.0.cmp(&b.0));
+        let result = match action {
+            Some((track, EntryAction::Play(index))) => {
+                if self.player.current_id() == Some(PlaybackId::Clip { track, index }) {
+                    self.player.stop();
+                    Ok(())
+                } else {
+                    self.play_clip(track, index)
+                }
+            }
+            Some((track, EntryAction::Edit(entry_edit))) => {
+                edit = Some((track, TrackEdit::Entry(entry_edit)));
+                Ok(())
+            }
+            None => Ok(()),
+        };
+        if let Err(e) = result {
+            self.analysis_data.error = Some(e);
+        }
 
-        Ok(clips.iter().map(|ele| ele.1.clone()).collect())
+        if let Some((track, edit)) = edit {
+            self.edit_track(track, edit, ui.ctx());
+        }
     }
 
-    // Modified analysis screen renderer
-    pub fn render_text_analyzer_screen(&mut self, ui: &mut egui::Ui) {
-        ui.heading("Interactive Text & Audio Analyzer");
-        ui.separator();
+    /// Applies an edit to an analyzed track and records it for undo
+    pub(crate) fn edit_track(&mut self, index: usize, edit: TrackEdit, ctx: &egui::Context) {
+        let Some(track) = self.analysis_data.tracks.get_mut(index) else {
+            return;
+        };
+        // Text still being typed into an entry is its own step, before this one
+        if let Some(typed) = track.take_entry_text()
+            && typed != edit
+        {
+            self.edit_track(index, typed, ctx);
+        }
 
-        if self.analysis_data.is_processing {
-            ui.label(&self.analysis_data.processing_status);
-            ui.spinner();
+        self.stop_clips_of(index);
+        let track = &mut self.analysis_data.tracks[index];
+        let before = track.alignment_state();
+        if let Err(e) = track.apply_edit(edit.clone()) {
+            self.analysis_data.error = Some(e);
+        }
+        let after = track.alignment_state();
+        if before != after {
+            self.history.record_alignment(index, &edit, before, after);
+            self.alignment_changed(ctx);
+        }
+    }
+
+    /// Brings the combined lesson in line with edited entries or clips: it is
+    /// combined again when every track is still aligned, and dropped if not
+    pub(crate) fn alignment_changed(&mut self, ctx: &egui::Context) {
+        if self.analysis_data.combined.take().is_none() {
             return;
         }
+        self.analysis_data.lesson_audio = None;
+        if self.player.current_id() == Some(PlaybackId::Lesson) {
+            self.player.stop();
+        }
 
-        if !self.analysis_data.processing_status.is_empty() {
-            ui.label(&self.analysis_data.processing_status);
-            ui.separator();
+        if self.ready_to_combine() {
+            self.handle_combine(ctx);
+        } else {
+            self.analysis_data.processing_status =
+                "The alignment changed. Fix it and combine the lesson again.".to_string();
         }
+    }
 
-        ui.columns(2, |columns| {
-            // Left column - Text Area 1 with audio
-            render_interactive_text_column(
-                &mut columns[0],
-                "Text Area 1",
-                &mut self.analysis_data.text_entries_1,
-                &self.analysis_data.audio_clips_1,
-                1,
-            );
-
-            // Render second column
-            render_interactive_text_column(
-                &mut columns[1],
-                "Text Area 2",
-                &mut self.analysis_data.text_entries_2,
-                &self.analysis_data.audio_clips_2,
-                2,
-            );
-        });
+    /// Whether every played track has one clip per text line; tracks the
+    /// pattern only shows as text need no clips
+    fn ready_to_combine(&self) -> bool {
+        let pattern = self.settings.lesson.interleave.pattern;
+        let count = self.analysis_data.tracks.len();
+        self.analysis_data
+            .tracks
+            .iter()
+            .enumerate()
+            .all(|(i, track)| track.is_aligned() || !pattern.plays(i, count))
+    }
+
+    /// Stops a clip of `track` from playing, as its position is about to change
+    pub(crate) fn stop_clips_of(&mut self, track: usize) {
+        if let Some(PlaybackId::Clip { track: playing, .. }) = self.player.current_id()
+            && playing == track
+        {
+            self.player.stop();
+        }
+    }
+
+    /// Plays one clip, straight from the source recording when its segment is known
+    fn play_clip(&mut self, track: usize, index: usize) -> Result<()> {
+        let Some(analysis) = self.analysis_data.tracks.get(track) else {
+            return Ok(());
+        };
+        let id = PlaybackId::Clip { track, index };
+        if let Some(source) = &analysis.source
+            && let Some(segment) = analysis.segments.get(index)
+        {
+            return self
+                .player
+                .play(id, source.clone(), segment.start, segment.end);
+        }
+
+        let Some(clip) = analysis.audio_clips.get(index) else {
+            return Ok(());
+        };
+        let audio = Arc::new(decode_audio(clip, &scratch_dir())?);
+        let duration = audio.duration();
+        self.player.play(id, audio, 0.0, duration)
+    }
+
+    /// Plays the combined lesson from the start
+    fn preview_lesson(&mut self) -> Result<()> {
+        let Some(lesson) = &self.analysis_data.combined else {
+            return Ok(());
+        };
+        let audio = match &self.analysis_data.lesson_audio {
+            Some(audio) => audio.clone(),
+            None => {
+                let audio = Arc::new(decode_audio(&lesson.audio_path, &scratch_dir())?);
+                self.analysis_data.lesson_audio = Some(audio.clone());
+                audio
+            }
+        };
+        let duration = audio.duration();
+        self.player.play(PlaybackId::Lesson, audio, 0.0, duration)
+    }
+
+    /// The sentence the lesson preview is at
+    fn current_lesson_sentence(&self) -> Option<&str> {
+        let position = self.player.position()?;
+        self.analysis_data
+            .combined
+            .as_ref()?
+            .stops
+            .elements
+            .iter()
+            .find(|stop| stop.start <= position && position < stop.end)
+            .map(|stop| stop.sentence.as_str())
     }
 }
 
+/// What the user did in an entry column
+enum EntryAction {
+    /// Play or stop the clip of an entry
+    Play(usize),
+    Edit(EntryEdit),
+}
+
 fn render_interactive_text_column(
     ui: &mut egui::Ui,
-    title: &str,
-    text_entries: &mut Vec<String>,
-    audio_clips: &[PathBuf],
-    _column_id: usize,
-) {
+    track: &mut TrackAnalysis,
+    playing: Option<usize>,
+) -> Option<EntryAction> {
+    let mut action = None;
     ui.group(|ui| {
         ui.vertical(|ui| {
-            ui.label(format!("{} - Interactive Entries:", title));
+            ui.label(format!("{} - Interactive Entries:", track.label));
             ui.add_space(5.0);
 
-            // Add new entry button
             if ui.button("+ Add Entry").clicked() {
-                text_entries.push(String::new());
+                action = Some(EntryAction::Edit(EntryEdit::Insert(
+                    track.text_entries.len(),
+                )));
             }
 
             ui.add_space(5.0);
 
             egui::ScrollArea::vertical()
+                .id_source(("entries", &track.label))
                 .max_height(400.0)
                 .show(ui, |ui| {
-                    let mut to_remove = None;
-                    let mut to_move_up = None;
-                    let mut to_move_down = None;
-
-                    // Collect UI actions without mutating text_entries
-                    for (i, entry) in text_entries.iter().enumerate() {
+                    let count = track.text_entries.len();
+                    for (i, entry) in track.text_entries.iter().enumerate() {
                         ui.group(|ui| {
                             ui.horizontal(|ui| {
                                 ui.label(format!("{}:", i + 1));
 
-                                // Move up button
-                                if i > 0 && ui.small_button("↑").clicked() {
-                                    to_move_up = Some(i);
+                                if i > 0
+                                    && ui
+                                        .small_button("↑")
+                                        .on_hover_text("Move up, with its clip")
+                                        .clicked()
+                                {
+                                    action = Some(EntryAction::Edit(EntryEdit::MoveUp(i)));
                                 }
-
-                                // Move down button
-                                if i < text_entries.len() - 1 && ui.small_button("↓").clicked() {
-                                    to_move_down = Some(i);
+                                if i + 1 < count
+                                    && ui
+                                        .small_button("↓")
+                                        .on_hover_text("Move down, with its clip")
+                                        .clicked()
+                                {
+                                    action = Some(EntryAction::Edit(EntryEdit::MoveDown(i)));
                                 }
-
-                                // Delete button
-                                if ui.small_button("🗑").clicked() {
-                                    to_remove = Some(i);
+                                if ui
+                                    .small_button("🗑")
+                                    .on_hover_text("Remove, with its clip")
+                                    .clicked()
+                                {
+                                    action = Some(EntryAction::Edit(EntryEdit::Remove(i)));
+                                }
+                                if ui
+                                    .small_button("+")
+                                    .on_hover_text("Insert an entry below")
+                                    .clicked()
+                                {
+                                    action = Some(EntryAction::Edit(EntryEdit::Insert(i + 1)));
                                 }
                             });
 
-                            // Text input (we can't mutate here yet)
-                            ui.label(entry);
+                            // Typing goes into a buffer that is applied when the
+                            // field loses focus, so one edit is one undo step
+                            let mut text = match &track.editing_entry {
+                                Some((index, text)) if *index == i => text.clone(),
+                                _ => entry.clone(),
+                            };
+                            let response = ui.add(
+                                egui::TextEdit::multiline(&mut text)
+                                    .desired_rows(1)
+                                    .desired_width(f32::INFINITY),
+                            );
+                            if response.changed() {
+                                track.editing_entry = Some((i, text));
+                            }
+                            // A button clicked in the same frame wins; the text is
+                            // then applied before its edit
+                            if response.lost_focus()
+                                && action.is_none()
+                                && let Some((index, text)) = track.editing_entry.take()
+                            {
+                                action =
+                                    Some(EntryAction::Edit(EntryEdit::SetText { index, text }));
+                            }
 
-                            // Audio player
-                            if let Some(audio_clip) = audio_clips.get(i) {
+                            if let Some(audio_clip) = track.audio_clips.get(i) {
                                 ui.horizontal(|ui| {
-                                    ui.label("🔊");
-                                    if ui.button("Play").clicked() {
-                                        play_audio_clip(audio_clip);
+                                    let label = if playing == Some(i) {
+                                        "⏹ Stop"
+                                    } else {
+                                        "▶ Play"
+                                    };
+                                    if ui.button(label).clicked() {
+                                        action = Some(EntryAction::Play(i));
                                     }
                                     ui.label(format!(
                                         "Clip: {}",
@@ -291,38 +694,9 @@ fn render_interactive_text_column(
 
             ui.add_space(10.0);
             ui.separator();
-            ui.label(format!("Total entries: {}", text_entries.len()));
-            ui.label(format!("Audio clips: {}", audio_clips.len()));
+            ui.label(format!("Total entries: {}", track.text_entries.len()));
+            ui.label(format!("Audio clips: {}", track.audio_clips.len()));
         });
     });
-}
-
-fn play_audio_clip(clip_path: &Path) {
-    // Platform-specific audio playback
-    #[cfg(target_os = "windows")]
-    {
-        let _ = Command::new("powershell")
-            .args([
-                "-c",
-                &format!(
-                    "(New-Object Media.SoundPlayer '{}').PlaySync()",
-                    clip_path.display()
-                ),
-            ])
-            .spawn();
-    }
-
-    #[cfg(target_os = "macos")]
-    {
-        let _ = Command::new("afplay")
-            .arg(clip_path.to_str().unwrap())
-            .spawn();
-    }
-
-    #[cfg(target_os = "linux")]
-    {
-        let _ = Command::new("aplay")
-            .arg(clip_path.to_str().unwrap())
-            .spawn();
-    }
+    action
 }
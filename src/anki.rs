@@ -0,0 +1,159 @@
+use std::{
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    encode::{EncodeSettings, OutputFormat, encode_wav},
+    error::{AppError, Result},
+};
+
+/// How sentence pairs are exported as Anki notes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AnkiSettings {
+    /// Deck the notes are imported into; the lesson name if empty
+    pub deck: String,
+    /// Format of the copied clips. Anki plays all of them, but the
+    /// compressed ones keep the collection small.
+    pub clip_format: OutputFormat,
+}
+
+impl Default for AnkiSettings {
+    fn default() -> Self {
+        Self {
+            deck: String::new(),
+            clip_format: OutputFormat::Wav,
+        }
+    }
+}
+
+/// The text and clips of one track, by sentence pair
+pub struct AnkiTrack<'a> {
+    pub label: &'a str,
+    pub entries: &'a [String],
+    pub clips: &'a [PathBuf],
+}
+
+/// Folder next to the notes file that the clips are copied into
+fn media_dir(notes_path: &Path) -> PathBuf {
+    let stem = notes_path
+        .file_stem()
+        .map_or("lesson".into(), |s| s.to_string_lossy());
+    notes_path.with_file_name(format!("{}.media", stem))
+}
+
+/// Quotes a field if a tab, line break or quote in it would otherwise
+/// break the row
+fn note_field(text: &str) -> String {
+    if text.contains(['\t', '\n', '\r', '"']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+/// Writes one note per sentence pair to `notes_path` as a tab-separated file
+/// Anki can import, with a text and an audio field per track. The clips are
+/// copied (or encoded) into the media folder under names that start with the
+/// notes file's name, so they do not clash with other lessons once they are
+/// moved into Anki's `collection.media`.
+pub fn export_anki(
+    tracks: &[AnkiTrack],
+    notes_path: &Path,
+    deck: &str,
+    settings: &AnkiSettings,
+    encoding: &EncodeSettings,
+) -> Result<Vec<PathBuf>> {
+    let media_dir = media_dir(notes_path);
+    fs::create_dir_all(&media_dir)
+        .map_err(|e| AppError::io(format!("Failed to create {}", media_dir.display()), e))?;
+
+    let prefix: String = notes_path
+        .file_stem()
+        .map_or("lesson".into(), |s| s.to_string_lossy())
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let encoding = EncodeSettings {
+        format: settings.clip_format,
+        ..encoding.clone()
+    };
+
+    let mut notes = String::new();
+    let columns: Vec<String> = tracks
+        .iter()
+        .flat_map(|track| [track.label.to_string(), format!("{} audio", track.label)])
+        .collect();
+    let _ = writeln!(notes, "#separator:tab");
+    let _ = writeln!(notes, "#html:false");
+    let _ = writeln!(notes, "#deck:{}", deck);
+    let _ = writeln!(notes, "#columns:{}", columns.join("\t"));
+
+    let pairs = tracks
+        .iter()
+        .map(|track| track.entries.len().max(track.clips.len()))
+        .max()
+        .unwrap_or(0);
+    let mut clip_count = 0;
+    for pair in 0..pairs {
+        let mut fields = Vec::with_capacity(tracks.len() * 2);
+        for (i, track) in tracks.iter().enumerate() {
+            let text = track.entries.get(pair).map_or("", |entry| entry.trim());
+            fields.push(note_field(text));
+
+            let Some(clip) = track.clips.get(pair) else {
+                fields.push(String::new());
+                continue;
+            };
+            let file_name = format!(
+                "{}_{:03}_{}.{}",
+                prefix,
+                pair + 1,
+                i + 1,
+                settings.clip_format.extension()
+            );
+            encode_wav(clip, &media_dir.join(&file_name), &encoding)?;
+            fields.push(format!("[sound:{}]", file_name));
+            clip_count += 1;
+        }
+
+        // Pairs with neither text nor audio would only make empty cards
+        if fields.iter().any(|field| !field.is_empty()) {
+            let _ = writeln!(notes, "{}", fields.join("\t"));
+        }
+    }
+
+    fs::write(notes_path, notes)
+        .map_err(|e| AppError::io(format!("Failed to write {}", notes_path.display()), e))?;
+
+    let mut files = vec![notes_path.to_path_buf()];
+    if clip_count > 0 {
+        files.push(media_dir);
+    }
+    Ok(files)
+}
+
+/// Deck name and clip format
+pub fn render_anki_controls(ui: &mut egui::Ui, settings: &mut AnkiSettings, lesson_name: &str) {
+    ui.horizontal(|ui| {
+        ui.label("Anki deck:");
+        ui.add(egui::TextEdit::singleline(&mut settings.deck).hint_text(lesson_name));
+        egui::ComboBox::from_id_source("anki_clip_format")
+            .selected_text(settings.clip_format.label())
+            .show_ui(ui, |ui| {
+                for format in OutputFormat::ALL {
+                    ui.selectable_value(&mut settings.clip_format, format, format.label());
+                }
+            });
+    });
+}
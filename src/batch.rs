@@ -0,0 +1,548 @@
+use std::{
+    env::temp_dir,
+    fs,
+    path::{Component, Path, PathBuf},
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    thread,
+    time::Instant,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    combine::LessonSettings,
+    encode::EncodeSettings,
+    error::{AppError, Result},
+    export::{ExportSettings, export_lesson, subtitle_path},
+    job::{JobInput, TrackAudio, TrackInput, run_pipeline},
+    labels::read_audacity_labels,
+    sentences::{SentenceSplit, split_sentences},
+    settings::Settings,
+    silence::SilenceParams,
+    stops::StopsFormat,
+};
+
+/// File in each lesson directory that records what the outputs were built from
+const INPUTS_HASH_FILE: &str = "inputs.hash";
+/// Summary written next to the lesson directories
+pub const REPORT_FILE: &str = "batch-report.json";
+
+/// A list of lessons to build in one go. Relative paths are resolved
+/// against the directory of the manifest.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Manifest {
+    /// Every lesson gets its own directory in here
+    pub output_dir: PathBuf,
+    pub lessons: Vec<ManifestLesson>,
+    /// Replaces the saved output format for every lesson
+    #[serde(default)]
+    pub encoding: Option<EncodeSettings>,
+    /// Replaces the saved stops file layout for every lesson
+    #[serde(default)]
+    pub stops_format: Option<StopsFormat>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestLesson {
+    /// Name of the lesson's output directory
+    pub name: String,
+    pub tracks: Vec<ManifestTrack>,
+    /// Replaces the saved segmentation for every track of this lesson
+    #[serde(default)]
+    pub segmentation: Option<SilenceParams>,
+    /// Replaces the saved gaps, clip order and normalization for this lesson
+    #[serde(default)]
+    pub lesson: Option<LessonSettings>,
+    /// Replaces the saved sentence splitting for this lesson
+    #[serde(default)]
+    pub sentence_split: Option<SentenceSplit>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestTrack {
+    #[serde(default)]
+    pub label: Option<String>,
+    pub text: PathBuf,
+    pub audio: PathBuf,
+    /// Audacity label file to cut the recording at instead of detecting silences
+    #[serde(default)]
+    pub labels: Option<PathBuf>,
+    /// Replaces the lesson's segmentation for this track only
+    #[serde(default)]
+    pub segmentation: Option<SilenceParams>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LessonStatus {
+    Built,
+    /// The inputs have not changed since the last build
+    Skipped,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LessonReport {
+    pub name: String,
+    pub status: LessonStatus,
+    pub output_dir: PathBuf,
+    pub sentences: usize,
+    pub seconds: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchReport {
+    pub lessons: Vec<LessonReport>,
+}
+
+impl BatchReport {
+    pub fn count(&self, status: LessonStatus) -> usize {
+        self.lessons.iter().filter(|l| l.status == status).count()
+    }
+}
+
+impl Manifest {
+    /// Reads a manifest and checks that every lesson name can be a directory
+    /// of its own; names differing only in case would share one on some systems
+    pub fn load(path: &Path) -> Result<Self> {
+        let json = fs::read_to_string(path)
+            .map_err(|e| AppError::io(format!("Failed to read {}", path.display()), e))?;
+        let mut manifest: Manifest = serde_json::from_str(&json)
+            .map_err(|e| AppError::json(format!("Failed to parse {}", path.display()), e))?;
+
+        let mut names: Vec<String> = Vec::with_capacity(manifest.lessons.len());
+        for lesson in &manifest.lessons {
+            // The name becomes a directory, so it has to be one plain path component
+            let mut name_parts = Path::new(&lesson.name).components();
+            let reason = if !matches!(
+                (name_parts.next(), name_parts.next()),
+                (Some(Component::Normal(_)), None)
+            ) {
+                format!("\"{}\" is not a valid lesson name", lesson.name)
+            } else if names.contains(&lesson.name.to_lowercase()) {
+                format!("more than one lesson is named \"{}\"", lesson.name)
+            } else {
+                names.push(lesson.name.to_lowercase());
+                continue;
+            };
+            return Err(AppError::InvalidManifest {
+                path: path.to_path_buf(),
+                reason,
+            });
+        }
+
+        let base = path.parent().unwrap_or(Path::new("."));
+        manifest.output_dir = base.join(&manifest.output_dir);
+        for lesson in &mut manifest.lessons {
+            for track in &mut lesson.tracks {
+                track.text = base.join(&track.text);
+                track.audio = base.join(&track.audio);
+                track.labels = track.labels.as_ref().map(|labels| base.join(labels));
+            }
+        }
+        Ok(manifest)
+    }
+}
+
+/// Builds every lesson of the manifest on up to `workers` threads and writes
+/// a summary report into the output directory. Lessons whose inputs and
+/// settings are unchanged since their last build are skipped unless `force`
+/// is set.
+pub fn run_batch(
+    manifest: &Manifest,
+    settings: &Settings,
+    workers: usize,
+    force: bool,
+    on_done: &(dyn Fn(&LessonReport) + Sync),
+) -> Result<BatchReport> {
+    fs::create_dir_all(&manifest.output_dir).map_err(|e| {
+        AppError::io(
+            format!("Failed to create {}", manifest.output_dir.display()),
+            e,
+        )
+    })?;
+
+    let next = AtomicUsize::new(0);
+    let reports = Mutex::new(Vec::with_capacity(manifest.lessons.len()));
+
+    thread::scope(|scope| {
+        for _ in 0..workers.clamp(1, manifest.lessons.len().max(1)) {
+            scope.spawn(|| {
+                loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(lesson) = manifest.lessons.get(index) else {
+                        break;
+                    };
+
+                    let report = build_lesson(lesson, index, manifest, settings, force);
+                    on_done(&report);
+                    if let Ok(mut reports) = reports.lock() {
+                        reports.push((index, report));
+                    }
+                }
+            });
+        }
+    });
+
+    // Report in manifest order, whatever order the workers finished in
+    let mut reports = reports.into_inner().unwrap_or_default();
+    reports.sort_by_key(|(index, _)| *index);
+    let report = BatchReport {
+        lessons: reports.into_iter().map(|(_, report)| report).collect(),
+    };
+
+    let report_path = manifest.output_dir.join(REPORT_FILE);
+    let json = serde_json::to_string_pretty(&report)
+        .map_err(|e| AppError::json("Failed to serialize batch report", e))?;
+    fs::write(&report_path, json)
+        .map_err(|e| AppError::io(format!("Failed to write {}", report_path.display()), e))?;
+
+    Ok(report)
+}
+
+fn build_lesson(
+    lesson: &ManifestLesson,
+    index: usize,
+    manifest: &Manifest,
+    settings: &Settings,
+    force: bool,
+) -> LessonReport {
+    let started = Instant::now();
+    let output_dir = manifest.output_dir.join(&lesson.name);
+    let mut report = LessonReport {
+        name: lesson.name.clone(),
+        status: LessonStatus::Failed,
+        output_dir: output_dir.clone(),
+        sentences: 0,
+        seconds: 0.0,
+        error: None,
+    };
+
+    let mut export = settings.export.clone();
+    if let Some(encoding) = &manifest.encoding {
+        export.encoding = encoding.clone();
+    }
+    if let Some(format) = manifest.stops_format {
+        export.stops_format = format;
+    }
+    match build_lesson_outputs(lesson, index, &output_dir, settings, &export, force) {
+        Ok(Some(sentences)) => {
+            report.status = LessonStatus::Built;
+            report.sentences = sentences;
+        }
+        Ok(None) => report.status = LessonStatus::Skipped,
+        Err(e) => {
+            report.error = Some(match e.hint() {
+                Some(hint) => format!("{} ({})", e, hint),
+                None => e.to_string(),
+            });
+        }
+    }
+
+    report.seconds = started.elapsed().as_secs_f64();
+    report
+}
+
+/// Returns the number of sentences written, or `None` if the lesson was up to date
+fn build_lesson_outputs(
+    lesson: &ManifestLesson,
+    index: usize,
+    output_dir: &Path,
+    settings: &Settings,
+    export: &ExportSettings,
+    force: bool,
+) -> Result<Option<usize>> {
+    let lesson_settings = lesson
+        .lesson
+        .clone()
+        .unwrap_or_else(|| settings.lesson.clone());
+    let sentence_split = lesson
+        .sentence_split
+        .clone()
+        .unwrap_or_else(|| settings.sentence_split.clone());
+    let mut tracks = Vec::with_capacity(lesson.tracks.len());
+    let mut hasher = Fnv64::new();
+    hasher.write(
+        &serde_json::to_vec(&lesson_settings)
+            .map_err(|e| AppError::json("Failed to serialize lesson settings", e))?,
+    );
+    hasher.write(
+        &serde_json::to_vec(&(&export.encoding, export.stops_format, &export.subtitles))
+            .map_err(|e| AppError::json("Failed to serialize export settings", e))?,
+    );
+
+    for (i, track) in lesson.tracks.iter().enumerate() {
+        let text = fs::read_to_string(&track.text)
+            .map_err(|e| AppError::io(format!("Failed to read {}", track.text.display()), e))?;
+        let audio = fs::read(&track.audio)
+            .map_err(|e| AppError::io(format!("Failed to read {}", track.audio.display()), e))?;
+        let params = track
+            .segmentation
+            .clone()
+            .or_else(|| lesson.segmentation.clone())
+            .unwrap_or_else(|| settings.segmentation_for(&track.audio).clone());
+
+        let entries = split_sentences(&text, &sentence_split);
+        hasher.write(entries.join("\n").as_bytes());
+        hasher.write(&audio);
+        hasher.write(
+            &serde_json::to_vec(&params)
+                .map_err(|e| AppError::json("Failed to serialize segmentation", e))?,
+        );
+
+        let audio = match &track.labels {
+            Some(labels) => {
+                let segments = read_audacity_labels(labels)?;
+                hasher.write(
+                    &serde_json::to_vec(&segments)
+                        .map_err(|e| AppError::json("Failed to serialize labels", e))?,
+                );
+                TrackAudio::Segments {
+                    file: track.audio.clone(),
+                    segments,
+                    params: None,
+                }
+            }
+            None => TrackAudio::Detect {
+                file: track.audio.clone(),
+                params,
+            },
+        };
+
+        tracks.push(TrackInput {
+            label: track
+                .label
+                .clone()
+                .unwrap_or_else(|| format!("Track {}", (b'A' + (i % 26) as u8) as char)),
+            entries,
+            audio: Some(audio),
+        });
+    }
+
+    let hash = format!("{:016x}", hasher.finish());
+    let hash_path = output_dir.join(INPUTS_HASH_FILE);
+    let output_audio = output_dir.join(format!("lesson.{}", export.encoding.format.extension()));
+    let output_stops = output_dir.join("stops.json");
+
+    let up_to_date = fs::read_to_string(&hash_path).is_ok_and(|saved| saved.trim() == hash)
+        && output_audio.exists()
+        && output_stops.exists()
+        && export
+            .subtitles
+            .formats()
+            .into_iter()
+            .all(|format| subtitle_path(&output_audio, format).exists());
+    if up_to_date && !force {
+        return Ok(None);
+    }
+
+    fs::create_dir_all(output_dir)
+        .map_err(|e| AppError::io(format!("Failed to create {}", output_dir.display()), e))?;
+    // A failed rebuild must not leave the old hash behind to vouch for stale outputs
+    let _ = fs::remove_file(&hash_path);
+
+    let work_dir = temp_dir().join(format!(
+        "malti_prac_psm_gen_batch_{}_{}",
+        std::process::id(),
+        index
+    ));
+    let input = JobInput {
+        tracks,
+        lesson: lesson_settings,
+        output_audio: work_dir.join("lesson.wav"),
+        output_stops: work_dir.join("stops.json"),
+        work_dir,
+    };
+
+    let result = run_pipeline(&input, &mut |_| {}, &AtomicBool::new(false)).and_then(|lesson| {
+        export_lesson(&lesson, &output_audio, &output_stops, export)?;
+        Ok(lesson)
+    });
+    let _ = fs::remove_dir_all(&input.work_dir);
+    let combined = result?;
+
+    fs::write(&hash_path, hash)
+        .map_err(|e| AppError::io(format!("Failed to write {}", hash_path.display()), e))?;
+    Ok(Some(combined.stops.elements.len()))
+}
+
+/// 64-bit FNV-1a. Unlike `DefaultHasher`, its output is stable between
+/// builds, so the saved hashes stay valid after an update.
+struct Fnv64(u64);
+
+impl Fnv64 {
+    fn new() -> Self {
+        Fnv64(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+        // Keep "ab" + "c" and "a" + "bc" apart
+        self.0 ^= bytes.len() as u64;
+        self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hound::{SampleFormat, WavSpec, WavWriter};
+
+    use super::*;
+    use crate::subtitles::SubtitleSettings;
+
+    /// Fresh directory for the files of one test
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = temp_dir().join(format!(
+            "malti_prac_psm_gen_test_{}_{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn load(dir: &Path, json: &str) -> Result<Manifest> {
+        let path = dir.join("manifest.json");
+        fs::write(&path, json).unwrap();
+        Manifest::load(&path)
+    }
+
+    fn lesson_json(name: &str) -> String {
+        format!(
+            r#"{{"name": "{}", "tracks": [{{"text": "a.txt", "audio": "a.wav"}}]}}"#,
+            name
+        )
+    }
+
+    fn manifest_json(names: &[&str]) -> String {
+        let lessons: Vec<String> = names.iter().map(|name| lesson_json(name)).collect();
+        format!(
+            r#"{{"output_dir": "out", "lessons": [{}]}}"#,
+            lessons.join(", ")
+        )
+    }
+
+    #[test]
+    fn manifest_paths_are_relative_to_it() {
+        let dir = test_dir("manifest_paths");
+        let manifest = load(&dir, &manifest_json(&["one", "two"])).unwrap();
+        assert_eq!(manifest.output_dir, dir.join("out"));
+        assert_eq!(manifest.lessons[1].name, "two");
+        let track = &manifest.lessons[0].tracks[0];
+        assert_eq!(track.text, dir.join("a.txt"));
+        assert_eq!(track.audio, dir.join("a.wav"));
+        assert_eq!(track.labels, None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn manifest_rejects_bad_lesson_names() {
+        let dir = test_dir("manifest_names");
+        let names: &[&[&str]] = &[&[""], &["."], &[".."], &["a/b"], &["/abs"], &["one", "One"]];
+        for names in names {
+            let result = load(&dir, &manifest_json(names));
+            assert!(
+                matches!(result, Err(AppError::InvalidManifest { .. })),
+                "{:?} was accepted",
+                names
+            );
+        }
+        assert!(matches!(
+            load(&dir, "{\"lessons\": []}"),
+            Err(AppError::Json { .. })
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn hash(parts: &[&[u8]]) -> u64 {
+        let mut hasher = Fnv64::new();
+        for part in parts {
+            hasher.write(part);
+        }
+        hasher.finish()
+    }
+
+    #[test]
+    fn hash_is_stable() {
+        // Changing these invalidates every inputs.hash written so far
+        assert_eq!(hash(&[]), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(hash(&[b""]), 0xaf63_bd4c_8601_b7df);
+        assert_eq!(hash(&[b"malti"]), 0x0bbf_c79d_2cd0_5551);
+        assert_eq!(hash(&[b"lesson", b"settings"]), 0xe09d_caf7_fef6_4d80);
+        assert_ne!(hash(&[b"ab", b"c"]), hash(&[b"a", b"bc"]));
+    }
+
+    #[test]
+    fn missing_subtitles_are_rebuilt() {
+        let dir = test_dir("batch_outputs");
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: 8000,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let mut writer = WavWriter::create(dir.join("a.wav"), spec).unwrap();
+        for i in 0..16_000 {
+            writer.write_sample((i % 40) as i16 * 200).unwrap();
+        }
+        writer.finalize().unwrap();
+        fs::write(dir.join("a.txt"), "One.\nTwo.\n").unwrap();
+        fs::write(dir.join("a.labels"), "0.2\t0.8\tone\n1.0\t1.6\ttwo\n").unwrap();
+
+        let lesson = ManifestLesson {
+            name: "lesson".to_string(),
+            tracks: vec![ManifestTrack {
+                label: None,
+                text: dir.join("a.txt"),
+                audio: dir.join("a.wav"),
+                labels: Some(dir.join("a.labels")),
+                segmentation: None,
+            }],
+            segmentation: None,
+            lesson: None,
+            sentence_split: None,
+        };
+        let export = ExportSettings {
+            subtitles: SubtitleSettings {
+                srt: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let output_dir = dir.join("out");
+        let build = |force| {
+            build_lesson_outputs(
+                &lesson,
+                0,
+                &output_dir,
+                &Settings::default(),
+                &export,
+                force,
+            )
+            .unwrap()
+        };
+
+        assert_eq!(build(false), Some(2));
+        assert_eq!(build(false), None);
+        assert_eq!(build(true), Some(2));
+
+        let srt = output_dir.join("lesson.srt");
+        fs::remove_file(&srt).unwrap();
+        assert_eq!(build(false), Some(2));
+        assert!(srt.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,483 @@
+use std::{collections::BTreeMap, fs, path::PathBuf, sync::atomic::AtomicBool, thread};
+
+use crate::{
+    analyze::scratch_dir,
+    anki::{AnkiTrack, export_anki},
+    batch::{LessonReport, LessonStatus, Manifest, REPORT_FILE, run_batch},
+    encode::OutputFormat,
+    export::export_lesson,
+    job::{JobEvent, JobInput, TrackAudio, TrackInput, run_pipeline},
+    labels::read_audacity_labels,
+    loudness::{NormalizeMode, NormalizeScope},
+    sentences::{SplitMode, split_sentences},
+    settings::Settings,
+    stops::StopsFormat,
+    subtitles::CueLayout,
+};
+
+const USAGE: &str = "\
+Usage: malti_prac_psm_gen [COMMAND]
+
+Without a command the window opens. Commands:
+  generate    Build one lesson from text and audio files
+  batch       Build every lesson listed in a manifest
+
+Run a command with --help for its options.";
+
+const GENERATE_USAGE: &str = "\
+Usage: malti_prac_psm_gen generate [OPTIONS] --out <FILE> --stops <JSON>
+
+Builds a lesson without opening the window, using the settings saved by the GUI.
+
+Tracks are named by a letter (a, b, c, ...) and combined in that order:
+  --text-<x> <FILE>     Text of the track, split as saved in the settings
+  --audio-<x> <FILE>    Recording of the track
+  --label-<x> <NAME>    Name used in messages (default: Track <X>)
+  --labels-<x> <FILE>   Audacity label file to cut the recording at instead
+                        of detecting silences
+
+Text:
+  --split <MODE>        Split the text per line, sentence or paragraph
+  --delimiter <TEXT>    Split the text at TEXT instead (\\n is a line break)
+
+Audio:
+  --normalize <MODE>    Bring the clips to the saved peak or loudness target
+                        before combining: off, peak or loudness
+  --normalize-tracks    Give every clip of a track the same gain, instead of
+                        normalizing each clip
+
+Output:
+  --out <FILE>          Where to write the combined lesson; the extension
+                        (wav, mp3, ogg, opus, flac) picks the format
+  --stops <JSON>        Where to write the sentence stops
+  --legacy-stops        Write the stops as the old array of sentence and start
+  --srt, --vtt          Also write SRT / WebVTT subtitles next to the audio
+  --lrc                 Also write LRC lyrics next to the audio
+  --audacity-labels     Also write an Audacity label track next to the audio
+  --pair-cues           Put both languages on one subtitle cue
+  --anki <FILE>         Also write Anki notes (tab-separated) with the clips
+                        in <FILE stem>.media next to it
+  --work-dir <DIR>      Scratch directory for decoded audio and clips
+
+  -h, --help            Show this help";
+
+const BATCH_USAGE: &str = "\
+Usage: malti_prac_psm_gen batch [OPTIONS] <MANIFEST>
+
+Builds every lesson listed in a JSON manifest, skipping lessons whose inputs
+and settings have not changed since their last build:
+
+  {
+    \"output_dir\": \"out\",
+    \"lessons\": [
+      {
+        \"name\": \"lesson01\",
+        \"tracks\": [
+          { \"label\": \"Maltese\", \"text\": \"01/mt.txt\", \"audio\": \"01/mt.mp3\" },
+          { \"label\": \"English\", \"text\": \"01/en.txt\", \"audio\": \"01/en.mp3\",
+            \"labels\": \"01/en.labels.txt\" }
+        ]
+      }
+    ]
+  }
+
+Lessons and tracks may also set \"segmentation\", and lessons \"lesson\", in the
+same form as the saved settings to replace them. A top-level \"encoding\" and
+\"stops_format\" replace the ones saved in the Export panel.
+
+Options:
+  -j, --jobs <N>        Lessons built at the same time (default: CPU count)
+  -f, --force           Rebuild lessons even if they are up to date
+  -h, --help            Show this help";
+
+/// Exit code for bad arguments
+const EXIT_USAGE: i32 = 2;
+/// Exit code for a pipeline that ran and failed
+const EXIT_FAILURE: i32 = 1;
+
+#[derive(Debug, Default)]
+struct TrackArgs {
+    text: Option<PathBuf>,
+    audio: Option<PathBuf>,
+    label: Option<String>,
+    labels: Option<PathBuf>,
+}
+
+#[derive(Debug, Default)]
+struct GenerateArgs {
+    tracks: BTreeMap<char, TrackArgs>,
+    out: Option<PathBuf>,
+    stops: Option<PathBuf>,
+    legacy_stops: bool,
+    srt: bool,
+    vtt: bool,
+    lrc: bool,
+    audacity_labels: bool,
+    pair_cues: bool,
+    anki: Option<PathBuf>,
+    split_mode: Option<SplitMode>,
+    delimiter: Option<String>,
+    normalize: Option<NormalizeMode>,
+    normalize_tracks: bool,
+    work_dir: Option<PathBuf>,
+}
+
+/// Runs the command given on the command line, or returns `None` if the
+/// GUI should start instead
+pub fn run_from_args() -> Option<i32> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("generate") => Some(generate(&args[1..])),
+        Some("batch") => Some(batch(&args[1..])),
+        Some("-h" | "--help") => {
+            println!("{}", USAGE);
+            Some(0)
+        }
+        _ => None,
+    }
+}
+
+fn generate(args: &[String]) -> i32 {
+    let args = match parse_generate_args(args) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{}", GENERATE_USAGE);
+            return 0;
+        }
+        Err(message) => {
+            eprintln!("error: {}\n\n{}", message, GENERATE_USAGE);
+            return EXIT_USAGE;
+        }
+    };
+
+    let settings = Settings::load();
+    let (Some(out), Some(stops)) = (args.out.clone(), args.stops.clone()) else {
+        eprintln!(
+            "error: --out and --stops are required\n\n{}",
+            GENERATE_USAGE
+        );
+        return EXIT_USAGE;
+    };
+    // The format follows the extension of --out, the quality comes from the settings
+    let mut export = settings.export.clone();
+    if args.legacy_stops {
+        export.stops_format = StopsFormat::Legacy;
+    }
+    export.subtitles.srt |= args.srt;
+    export.subtitles.vtt |= args.vtt;
+    export.subtitles.lrc |= args.lrc;
+    export.subtitles.audacity |= args.audacity_labels;
+    if args.pair_cues {
+        export.subtitles.layout = CueLayout::PerPair;
+    }
+    match out
+        .extension()
+        .and_then(|e| e.to_str())
+        .and_then(OutputFormat::from_extension)
+    {
+        Some(format) => export.encoding.format = format,
+        None => {
+            let extensions: Vec<&str> = OutputFormat::ALL.iter().map(|f| f.extension()).collect();
+            eprintln!("error: --out must end in one of: {}", extensions.join(", "));
+            return EXIT_USAGE;
+        }
+    }
+
+    // Only clean up the scratch directory if it is our own
+    let own_work_dir = args.work_dir.is_none();
+    let anki = args.anki.clone();
+    let input = match build_job_input(args, &settings) {
+        Ok(input) => input,
+        Err(message) => {
+            eprintln!("error: {}", message);
+            return EXIT_USAGE;
+        }
+    };
+
+    let mut last_stage = String::new();
+    let mut clips = vec![Vec::new(); input.tracks.len()];
+    let mut report = |event: JobEvent| match event {
+        JobEvent::Progress { stage, .. } => {
+            let stage = stage.to_string();
+            if stage != last_stage {
+                eprintln!("{}", stage);
+                last_stage = stage;
+            }
+        }
+        JobEvent::TrackDone { track, processed } => clips[track] = processed.clips,
+        JobEvent::Finished(_) => {}
+    };
+
+    let result = run_pipeline(&input, &mut report, &AtomicBool::new(false)).and_then(|lesson| {
+        let mut exported = export_lesson(&lesson, &out, &stops, &export)?;
+        if let Some(notes_path) = &anki {
+            let tracks: Vec<AnkiTrack> = input
+                .tracks
+                .iter()
+                .zip(&clips)
+                .map(|(track, clips)| AnkiTrack {
+                    label: &track.label,
+                    entries: &track.entries,
+                    clips,
+                })
+                .collect();
+            let deck = match export.anki.deck.trim() {
+                "" => out
+                    .file_stem()
+                    .map_or("lesson".into(), |s| s.to_string_lossy()),
+                deck => deck.into(),
+            };
+            exported.files.extend(export_anki(
+                &tracks,
+                notes_path,
+                &deck,
+                &export.anki,
+                &export.encoding,
+            )?);
+        }
+        Ok((lesson, exported))
+    });
+    if own_work_dir {
+        let _ = fs::remove_dir_all(&input.work_dir);
+    }
+
+    match result {
+        Ok((lesson, exported)) => {
+            println!("Wrote {} sentences to:", lesson.stops.elements.len());
+            for path in &exported.files {
+                println!("  {}", path.display());
+            }
+            0
+        }
+        Err(e) => {
+            eprintln!("error: {}", e);
+            if let Some(hint) = e.hint() {
+                eprintln!("hint: {}", hint);
+            }
+            EXIT_FAILURE
+        }
+    }
+}
+
+fn batch(args: &[String]) -> i32 {
+    let mut manifest_path = None;
+    let mut workers = thread::available_parallelism().map_or(1, |n| n.get());
+    let mut force = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", BATCH_USAGE);
+                return 0;
+            }
+            "-f" | "--force" => force = true,
+            "-j" | "--jobs" => match args.next().and_then(|n| n.parse().ok()) {
+                Some(n) if n > 0 => workers = n,
+                _ => {
+                    eprintln!("error: {} needs a positive number\n\n{}", arg, BATCH_USAGE);
+                    return EXIT_USAGE;
+                }
+            },
+            _ if arg.starts_with('-') || manifest_path.is_some() => {
+                eprintln!("error: unexpected argument {}\n\n{}", arg, BATCH_USAGE);
+                return EXIT_USAGE;
+            }
+            _ => manifest_path = Some(PathBuf::from(arg)),
+        }
+    }
+
+    let Some(manifest_path) = manifest_path else {
+        eprintln!("error: no manifest given\n\n{}", BATCH_USAGE);
+        return EXIT_USAGE;
+    };
+    let manifest = match Manifest::load(&manifest_path) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return EXIT_USAGE;
+        }
+    };
+
+    let on_done = |report: &LessonReport| match report.status {
+        LessonStatus::Built => eprintln!(
+            "built   {} ({} sentences, {:.1}s)",
+            report.name, report.sentences, report.seconds
+        ),
+        LessonStatus::Skipped => eprintln!("skipped {} (up to date)", report.name),
+        LessonStatus::Failed => eprintln!(
+            "FAILED  {}: {}",
+            report.name,
+            report.error.as_deref().unwrap_or_default()
+        ),
+    };
+
+    match run_batch(&manifest, &Settings::load(), workers, force, &on_done) {
+        Ok(report) => {
+            let failed = report.count(LessonStatus::Failed);
+            println!(
+                "{} built, {} skipped, {} failed. Report written to {}",
+                report.count(LessonStatus::Built),
+                report.count(LessonStatus::Skipped),
+                failed,
+                manifest.output_dir.join(REPORT_FILE).display()
+            );
+            if failed > 0 { EXIT_FAILURE } else { 0 }
+        }
+        Err(e) => {
+            eprintln!("error: {}", e);
+            EXIT_FAILURE
+        }
+    }
+}
+
+/// Returns `Ok(None)` if help was requested
+fn parse_generate_args(args: &[String]) -> Result<Option<GenerateArgs>, String> {
+    let mut parsed = GenerateArgs::default();
+    let mut args = args.iter();
+
+    while let Some(flag) = args.next() {
+        if flag == "-h" || flag == "--help" {
+            return Ok(None);
+        }
+
+        let mut value = || {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("{} needs a value", flag))
+        };
+
+        match flag.as_str() {
+            "--out" => parsed.out = Some(PathBuf::from(value()?)),
+            "--stops" => parsed.stops = Some(PathBuf::from(value()?)),
+            "--work-dir" => parsed.work_dir = Some(PathBuf::from(value()?)),
+            "--anki" => parsed.anki = Some(PathBuf::from(value()?)),
+            "--split" => {
+                parsed.split_mode = Some(match value()?.as_str() {
+                    "line" => SplitMode::Line,
+                    "sentence" => SplitMode::Sentence,
+                    "paragraph" => SplitMode::Paragraph,
+                    other => {
+                        return Err(format!(
+                            "--split must be line, sentence or paragraph, not {}",
+                            other
+                        ));
+                    }
+                })
+            }
+            "--delimiter" => parsed.delimiter = Some(value()?),
+            "--normalize" => {
+                parsed.normalize = Some(match value()?.as_str() {
+                    "off" => NormalizeMode::Off,
+                    "peak" => NormalizeMode::Peak,
+                    "loudness" => NormalizeMode::Loudness,
+                    other => {
+                        return Err(format!(
+                            "--normalize must be off, peak or loudness, not {}",
+                            other
+                        ));
+                    }
+                })
+            }
+            "--normalize-tracks" => parsed.normalize_tracks = true,
+            "--legacy-stops" => parsed.legacy_stops = true,
+            "--srt" => parsed.srt = true,
+            "--vtt" => parsed.vtt = true,
+            "--lrc" => parsed.lrc = true,
+            "--audacity-labels" => parsed.audacity_labels = true,
+            "--pair-cues" => parsed.pair_cues = true,
+            _ => {
+                let (kind, letter) = flag
+                    .strip_prefix("--")
+                    .and_then(|name| name.rsplit_once('-'))
+                    .and_then(|(kind, letter)| {
+                        let mut chars = letter.chars();
+                        match (chars.next(), chars.next()) {
+                            (Some(c), None) if c.is_ascii_alphabetic() => {
+                                Some((kind, c.to_ascii_lowercase()))
+                            }
+                            _ => None,
+                        }
+                    })
+                    .ok_or_else(|| format!("unknown option {}", flag))?;
+
+                let track = parsed.tracks.entry(letter).or_default();
+                match kind {
+                    "text" => track.text = Some(PathBuf::from(value()?)),
+                    "audio" => track.audio = Some(PathBuf::from(value()?)),
+                    "label" => track.label = Some(value()?),
+                    "labels" => track.labels = Some(PathBuf::from(value()?)),
+                    _ => return Err(format!("unknown option {}", flag)),
+                }
+            }
+        }
+    }
+
+    Ok(Some(parsed))
+}
+
+fn build_job_input(args: GenerateArgs, settings: &Settings) -> Result<JobInput, String> {
+    if args.tracks.is_empty() {
+        return Err("give at least one track with --text-a and --audio-a".to_string());
+    }
+
+    let mut split = settings.sentence_split.clone();
+    if let Some(mode) = args.split_mode {
+        split.mode = mode;
+    }
+    if let Some(delimiter) = args.delimiter {
+        split.mode = SplitMode::Delimiter;
+        split.delimiter = delimiter;
+    }
+
+    let mut tracks = Vec::with_capacity(args.tracks.len());
+    for (letter, track) in args.tracks {
+        let label = track
+            .label
+            .unwrap_or_else(|| format!("Track {}", letter.to_ascii_uppercase()));
+        let text_path = track
+            .text
+            .ok_or_else(|| format!("{} has no --text-{}", label, letter))?;
+        let text = fs::read_to_string(&text_path)
+            .map_err(|e| format!("could not read {}: {}", text_path.display(), e))?;
+
+        let audio = match (track.audio, track.labels) {
+            (Some(file), Some(labels)) => Some(TrackAudio::Segments {
+                file,
+                segments: read_audacity_labels(&labels).map_err(|e| e.to_string())?,
+                params: None,
+            }),
+            (Some(file), None) => Some(TrackAudio::Detect {
+                params: settings.segmentation_for(&file).clone(),
+                file,
+            }),
+            (None, Some(_)) => {
+                return Err(format!("--labels-{} needs --audio-{}", letter, letter));
+            }
+            (None, None) => None,
+        };
+
+        tracks.push(TrackInput {
+            entries: split_sentences(&text, &split),
+            audio,
+            label,
+        });
+    }
+
+    let mut lesson = settings.lesson.clone();
+    if let Some(mode) = args.normalize {
+        lesson.normalization.mode = mode;
+    }
+    if args.normalize_tracks {
+        lesson.normalization.scope = NormalizeScope::PerTrack;
+    }
+
+    let work_dir = args.work_dir.unwrap_or_else(scratch_dir);
+    Ok(JobInput {
+        tracks,
+        lesson,
+        output_audio: work_dir.join("lesson.wav"),
+        output_stops: work_dir.join("stops.json"),
+        work_dir,
+    })
+}
@@ -1,163 +1,746 @@
 use hound::{WavReader, WavSpec, WavWriter};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
 
-#[derive(Debug, Serialize)]
+use crate::error::{AppError, Result, TrackCount};
+use crate::job::check_cancelled;
+use crate::loudness::{LoudnessMeter, Normalization, NormalizeMode, NormalizeScope, read_clip};
+
+/// One sentence of the combined lesson and where it sits in the audio
+#[derive(Debug, Clone)]
 pub struct ResultElement {
     pub sentence: String,
+    /// Start of the sentence's clip, in seconds
     pub audio_stop: f64,
+    /// End of the sentence's clip, in seconds
+    pub audio_end: f64,
+    /// Index of the track the sentence belongs to
+    pub track: usize,
+    /// Index of the sentence pair in the source texts
+    pub pair: usize,
+    /// Clip the audio was copied from; `None` for text shown with another track's clip
+    pub clip: Option<PathBuf>,
+}
+
+/// The combined lesson audio and the sentences in it
+#[derive(Debug, Clone)]
+pub struct CombinedAudio {
+    pub path: PathBuf,
+    pub elements: Vec<ResultElement>,
+    pub spec: WavSpec,
+    /// Length of the whole lesson, in seconds
+    pub duration: f64,
+}
+
+/// Lengths of the silences inserted into the combined lesson, in seconds
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GapSettings {
+    /// Silence between a sentence and its translation(s)
+    pub between_tracks: f64,
+    /// Silence between one sentence and the next
+    pub between_pairs: f64,
+    /// Extra pause after every clip, as a multiple of that clip's length,
+    /// so the learner has time to repeat it. 0 disables it.
+    pub thinking_pause_factor: f64,
+    /// Silence before the first clip
+    pub leading: f64,
+    /// Silence after the last clip
+    pub trailing: f64,
+}
+
+impl Default for GapSettings {
+    fn default() -> Self {
+        Self {
+            between_tracks: 1.0,
+            between_pairs: 1.0,
+            thinking_pause_factor: 0.0,
+            leading: 0.0,
+            trailing: 0.0,
+        }
+    }
+}
+
+/// One step of the sequence played for every sentence
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Step {
+    /// Play the clip of the given track
+    Clip(usize),
+    /// Show the sentence of the given track alongside the last clip, without audio
+    Text(usize),
+    /// Silence between two clips of the same sentence
+    Gap,
 }
 
-/// Combines segments of clips together alternately with 1-second gaps
+/// The order in which the clips of a sentence are played.
+///
+/// Track A is the first track (the source language), the remaining tracks
+/// are its translations or other speakers and always keep their order.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum InterleavePattern {
+    /// A, B, C…
+    SourceThenTranslation,
+    /// B, C…, A
+    TranslationThenSource,
+    /// A, B, C…, A
+    SourceTranslationSource,
+    /// A, with the other tracks shown as text only
+    SourceOnly,
+    /// A, A, B, C…
+    SlowRepeat,
+}
+
+impl InterleavePattern {
+    pub const ALL: [InterleavePattern; 5] = [
+        InterleavePattern::SourceThenTranslation,
+        InterleavePattern::TranslationThenSource,
+        InterleavePattern::SourceTranslationSource,
+        InterleavePattern::SourceOnly,
+        InterleavePattern::SlowRepeat,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            InterleavePattern::SourceThenTranslation => "A → B",
+            InterleavePattern::TranslationThenSource => "B → A",
+            InterleavePattern::SourceTranslationSource => "A → B → A",
+            InterleavePattern::SourceOnly => "A only (B as text)",
+            InterleavePattern::SlowRepeat => "A → A → B",
+        }
+    }
+
+    /// The clips and gaps emitted for every sentence, given the number of tracks
+    pub fn steps(&self, track_count: usize) -> Vec<Step> {
+        let others = 1..track_count;
+        match self {
+            InterleavePattern::SourceThenTranslation => clips_with_gaps(0..track_count),
+            InterleavePattern::TranslationThenSource => {
+                clips_with_gaps(others.chain(std::iter::once(0)))
+            }
+            InterleavePattern::SourceTranslationSource => {
+                clips_with_gaps((0..track_count).chain(std::iter::once(0)))
+            }
+            InterleavePattern::SourceOnly => std::iter::once(Step::Clip(0))
+                .chain(others.map(Step::Text))
+                .collect(),
+            InterleavePattern::SlowRepeat => clips_with_gaps([0, 0].into_iter().chain(others)),
+        }
+    }
+
+    /// Whether the clips of `track` are played, rather than only shown as text
+    pub fn plays(&self, track: usize, track_count: usize) -> bool {
+        self.steps(track_count).contains(&Step::Clip(track))
+    }
+}
+
+/// Plays the given tracks in order with a gap between consecutive clips
+fn clips_with_gaps(tracks: impl Iterator<Item = usize>) -> Vec<Step> {
+    let mut steps = Vec::new();
+    for track in tracks {
+        if !steps.is_empty() {
+            steps.push(Step::Gap);
+        }
+        steps.push(Step::Clip(track));
+    }
+    steps
+}
+
+/// How sentences are arranged in the combined lesson
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Interleave {
+    pub pattern: InterleavePattern,
+    /// Play the sentences in a random order, for review sessions
+    pub shuffle_pairs: bool,
+}
+
+impl Default for Interleave {
+    fn default() -> Self {
+        Self {
+            pattern: InterleavePattern::SourceThenTranslation,
+            shuffle_pairs: false,
+        }
+    }
+}
+
+/// Everything about the layout of the combined lesson
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LessonSettings {
+    pub gaps: GapSettings,
+    pub interleave: Interleave,
+    pub normalization: Normalization,
+}
+
+/// Combines segments of clips together following an interleave pattern
 ///
 /// # Arguments
-/// * `clips1` - First list of paths to .wav files
-/// * `clips2` - Second list of paths to .wav files (must be same length as clips1)
-/// * `entries1` - First list of sentence strings corresponding to clips1
-/// * `entries2` - Second list of sentence strings corresponding to clips2
+/// * `clips` - One list of paths to .wav files per track (all the same length)
+/// * `entries` - One list of sentence strings per track, corresponding to `clips`
 /// * `output_path` - Path where the combined file will be saved
+/// * `lesson` - Gap lengths, order of the clips within a sentence and of the
+///   sentences, and how the clips are normalized
+/// * `cancel` - Checked before every clip; once set, combining stops with `Cancelled`
 ///
 /// # Returns
-/// * `Result<(PathBuf, Vec<ResultElement>), Box<dyn std::error::Error>>` - The output path and result elements
+/// * `Result<CombinedAudio>` - The output path, result elements and audio format
 pub fn combine_clips_alternately(
-    clips1: &[PathBuf],
-    clips2: &[PathBuf],
-    entries1: &[String],
-    entries2: &[String],
+    clips: &[Vec<PathBuf>],
+    entries: &[Vec<String>],
     output_path: &Path,
-    output_path_json: &Path,
-) -> Result<(PathBuf, Vec<ResultElement>), Box<dyn std::error::Error>> {
-    if clips1.len() != clips2.len()
-        || clips1.len() != entries1.len()
-        || clips2.len() != entries2.len()
-    {
-        return Err("All input lists must have the same length".into());
-    }
-
-    if clips1.is_empty() {
-        return Err("Input lists cannot be empty".into());
-    }
+    lesson: &LessonSettings,
+    cancel: &AtomicBool,
+) -> Result<CombinedAudio> {
+    let labels: Vec<String> = (0..clips.len())
+        .map(|i| format!("Track {}", i + 1))
+        .collect();
+    check_alignment(&labels, entries, clips, lesson.interleave.pattern)?;
+    let sentence_count = entries[0].len();
 
     // Clean existing file if it exists
     if output_path.exists() {
-        fs::remove_file(output_path)?;
+        fs::remove_file(output_path)
+            .map_err(|e| AppError::io(format!("Failed to replace {}", output_path.display()), e))?;
     }
 
     // Read the first file to get the audio specification
-    let first_reader = WavReader::open(&clips1[0])?;
+    let first_clip = clips
+        .iter()
+        .flatten()
+        .next()
+        .ok_or(AppError::NothingToCombine)?;
+    let first_reader = WavReader::open(first_clip).map_err(|e| AppError::wav(first_clip, e))?;
     let spec = first_reader.spec();
 
     // Validate that all files have the same specification
-    for (i, clip_path) in clips1.iter().chain(clips2.iter()).enumerate() {
-        let reader = WavReader::open(clip_path)?;
+    for clip_path in clips.iter().flatten() {
+        let reader = WavReader::open(clip_path).map_err(|e| AppError::wav(clip_path, e))?;
         if reader.spec() != spec {
-            return Err(format!("File {} has different audio specification", i).into());
+            return Err(AppError::SpecMismatch {
+                path: clip_path.clone(),
+            });
         }
     }
 
     // Create output writer
-    let mut writer = WavWriter::create(output_path, spec)?;
+    let mut writer =
+        WavWriter::create(output_path, spec).map_err(|e| AppError::wav(output_path, e))?;
 
+    let out_err = |e| AppError::wav(output_path, e);
     let sample_rate = spec.sample_rate as f64;
-    let channels = spec.channels as usize;
-    let gap_samples = (sample_rate * channels as f64) as usize; // 1 second gap
+    let LessonSettings {
+        gaps,
+        interleave,
+        normalization,
+    } = lesson;
+    let gains = clip_gains(clips, &spec, normalization)?;
 
-    let mut current_time = 0.0;
+    // Time is tracked in frames so the stops match the written audio exactly
+    let mut current_frames = 0usize;
     let mut result_elements = Vec::new();
 
-    // Process clips alternately
-    for i in 0..clips1.len() {
-        // Process clip from first list
-        let duration1 = write_clip_to_output(&mut writer, &clips1[i], &spec)?;
+    current_frames += write_gap(&mut writer, gaps.leading, &spec).map_err(out_err)?;
 
-        result_elements.push(ResultElement {
-            sentence: entries1[i].clone(),
-            audio_stop: current_time,
-        });
+    let mut order: Vec<usize> = (0..sentence_count).collect();
+    if interleave.shuffle_pairs {
+        shuffle(&mut order);
+    }
 
-        current_time += duration1;
+    let steps = interleave.pattern.steps(clips.len());
 
-        // Add 1-second gap
-        write_silence(&mut writer, gap_samples)?;
-        current_time += 1.0;
+    for (n, &i) in order.iter().enumerate() {
+        let mut last_clip = (current_frames, current_frames);
 
-        // Process clip from second list
-        let duration2 = write_clip_to_output(&mut writer, &clips2[i], &spec)?;
+        for step in &steps {
+            match *step {
+                Step::Clip(track) => {
+                    check_cancelled(cancel)?;
+                    let clip_start = current_frames;
+                    let clip_frames = write_clip_to_output(
+                        &mut writer,
+                        output_path,
+                        &clips[track][i],
+                        &spec,
+                        gains[track][i],
+                    )?;
+                    current_frames += clip_frames;
+                    last_clip = (clip_start, current_frames);
 
-        result_elements.push(ResultElement {
-            sentence: entries2[i].clone(),
-            audio_stop: current_time,
-        });
+                    result_elements.push(ResultElement {
+                        sentence: entries[track][i].clone(),
+                        audio_stop: clip_start as f64 / sample_rate,
+                        audio_end: current_frames as f64 / sample_rate,
+                        track,
+                        pair: i,
+                        clip: Some(clips[track][i].clone()),
+                    });
 
-        current_time += duration2;
+                    let thinking_pause =
+                        clip_frames as f64 / sample_rate * gaps.thinking_pause_factor;
+                    current_frames +=
+                        write_gap(&mut writer, thinking_pause, &spec).map_err(out_err)?;
+                }
+                Step::Text(track) => {
+                    result_elements.push(ResultElement {
+                        sentence: entries[track][i].clone(),
+                        audio_stop: last_clip.0 as f64 / sample_rate,
+                        audio_end: last_clip.1 as f64 / sample_rate,
+                        track,
+                        pair: i,
+                        clip: None,
+                    });
+                }
+                Step::Gap => {
+                    current_frames +=
+                        write_gap(&mut writer, gaps.between_tracks, &spec).map_err(out_err)?;
+                }
+            }
+        }
 
-        // Add 1-second gap after each pair (except the last one)
-        if i < clips1.len() - 1 {
-            write_silence(&mut writer, gap_samples)?;
-            current_time += 1.0;
+        // Add a gap after each sentence (except the last one)
+        if n < order.len() - 1 {
+            current_frames += write_gap(&mut writer, gaps.between_pairs, &spec).map_err(out_err)?;
         }
     }
 
-    writer.finalize()?;
+    current_frames += write_gap(&mut writer, gaps.trailing, &spec).map_err(out_err)?;
 
-    std::fs::write(
-        output_path_json,
-        serde_json::to_string_pretty(&result_elements).unwrap(),
-    )
-    .unwrap();
+    writer.finalize().map_err(out_err)?;
 
-    Ok((output_path.to_path_buf(), result_elements))
+    Ok(CombinedAudio {
+        path: output_path.to_path_buf(),
+        elements: result_elements,
+        spec,
+        duration: current_frames as f64 / sample_rate,
+    })
 }
 
-/// Writes a single clip to the output writer and returns its duration in seconds
+/// Checks that every track has the same number of text lines, and one clip
+/// per line unless `pattern` only shows it as text.
+///
+/// `labels`, `entries` and `clips` are given per track, in the same order.
+pub fn check_alignment(
+    labels: &[String],
+    entries: &[Vec<String>],
+    clips: &[Vec<PathBuf>],
+    pattern: InterleavePattern,
+) -> Result<()> {
+    let sentence_count = entries.first().map_or(0, |e| e.len());
+    let aligned = entries.len() == clips.len()
+        && entries.iter().zip(clips).enumerate().all(|(i, (e, c))| {
+            e.len() == sentence_count
+                && (c.len() == sentence_count || !pattern.plays(i, clips.len()))
+        });
+
+    if !aligned {
+        return Err(AppError::CountMismatch {
+            tracks: labels
+                .iter()
+                .enumerate()
+                .map(|(i, label)| TrackCount {
+                    label: label.clone(),
+                    lines: entries.get(i).map_or(0, |e| e.len()),
+                    clips: clips.get(i).map_or(0, |c| c.len()),
+                })
+                .collect(),
+        });
+    }
+
+    if sentence_count == 0 {
+        return Err(AppError::NothingToCombine);
+    }
+
+    Ok(())
+}
+
+/// Fisher-Yates shuffle seeded from the clock, so every review session differs
+fn shuffle(order: &mut [usize]) {
+    let mut state = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
+        | 1;
+
+    for i in (1..order.len()).rev() {
+        // xorshift64
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        order.swap(i, (state % (i as u64 + 1)) as usize);
+    }
+}
+
+/// Factor every clip is scaled by, per track; all 1 when normalization is off
+fn clip_gains(
+    clips: &[Vec<PathBuf>],
+    spec: &WavSpec,
+    normalization: &Normalization,
+) -> Result<Vec<Vec<f64>>> {
+    if normalization.mode == NormalizeMode::Off {
+        return Ok(clips.iter().map(|track| vec![1.0; track.len()]).collect());
+    }
+
+    clips
+        .iter()
+        .map(|track| {
+            let mut meter = LoudnessMeter::new(spec.channels, spec.sample_rate);
+            let measured = track
+                .iter()
+                .map(|clip| Ok(meter.add_clip(&read_clip(clip)?)))
+                .collect::<Result<Vec<_>>>()?;
+            Ok(match normalization.scope {
+                NormalizeScope::PerClip => measured
+                    .iter()
+                    .map(|loudness| normalization.gain(loudness))
+                    .collect(),
+                NormalizeScope::PerTrack => vec![normalization.gain(&meter.total()); track.len()],
+            })
+        })
+        .collect()
+}
+
+/// Scales an integer sample of the given bit depth, clipping at full scale
+fn amplify(sample: i32, gain: f64, bits_per_sample: u16) -> i32 {
+    let max = ((1i64 << (bits_per_sample - 1)) - 1) as f64;
+    (sample as f64 * gain).round().clamp(-max - 1.0, max) as i32
+}
+
+/// Writes a single clip to the output writer, scaled by `gain`, and returns
+/// its length in frames
 fn write_clip_to_output(
     writer: &mut WavWriter<std::io::BufWriter<std::fs::File>>,
+    output_path: &Path,
     clip_path: &Path,
     expected_spec: &WavSpec,
-) -> Result<f64, Box<dyn std::error::Error>> {
-    let mut reader = WavReader::open(clip_path)?;
+    gain: f64,
+) -> Result<usize> {
+    let mut reader = WavReader::open(clip_path).map_err(|e| AppError::wav(clip_path, e))?;
+    let read_err = |e| AppError::wav(clip_path, e);
+    let write_err = |e| AppError::wav(output_path, e);
 
-    let sample_count = reader.len() as f64;
-    let duration =
-        sample_count / (expected_spec.sample_rate as f64 * expected_spec.channels as f64);
+    let frames = reader.len() as usize / expected_spec.channels as usize;
 
     // Write samples based on the bit depth
     match expected_spec.bits_per_sample {
         16 => {
             for sample in reader.samples::<i16>() {
-                writer.write_sample(sample?)?;
+                let sample = amplify(sample.map_err(read_err)? as i32, gain, 16) as i16;
+                writer.write_sample(sample).map_err(write_err)?;
             }
         }
         24 => {
             for sample in reader.samples::<i32>() {
-                writer.write_sample(sample?)?;
+                let sample = amplify(sample.map_err(read_err)?, gain, 24);
+                writer.write_sample(sample).map_err(write_err)?;
             }
         }
         32 => {
             if expected_spec.sample_format == hound::SampleFormat::Float {
                 for sample in reader.samples::<f32>() {
-                    writer.write_sample(sample?)?;
+                    let sample = (sample.map_err(read_err)? as f64 * gain).clamp(-1.0, 1.0);
+                    writer.write_sample(sample as f32).map_err(write_err)?;
                 }
             } else {
                 for sample in reader.samples::<i32>() {
-                    writer.write_sample(sample?)?;
+                    let sample = amplify(sample.map_err(read_err)?, gain, 32);
+                    writer.write_sample(sample).map_err(write_err)?;
                 }
             }
         }
-        _ => return Err("Unsupported bit depth".into()),
+        bits_per_sample => {
+            return Err(AppError::UnsupportedFormat {
+                path: clip_path.to_path_buf(),
+                bits_per_sample,
+            });
+        }
     }
 
-    Ok(duration)
+    Ok(frames)
+}
+
+/// Writes `seconds` of silence and returns its length in frames
+fn write_gap(
+    writer: &mut WavWriter<std::io::BufWriter<std::fs::File>>,
+    seconds: f64,
+    spec: &WavSpec,
+) -> hound::Result<usize> {
+    let frames = (seconds * spec.sample_rate as f64).round() as usize;
+    write_silence(writer, frames * spec.channels as usize, spec)?;
+    Ok(frames)
 }
 
 /// Writes silence (zeros) for the specified number of samples
 fn write_silence(
     writer: &mut WavWriter<std::io::BufWriter<std::fs::File>>,
     sample_count: usize,
-) -> Result<(), Box<dyn std::error::Error>> {
+    spec: &WavSpec,
+) -> hound::Result<()> {
     for _ in 0..sample_count {
-        writer.write_sample(0i16)?; // Writing zero samples as silence
+        // Writing zero samples as silence
+        if spec.sample_format == hound::SampleFormat::Float {
+            writer.write_sample(0.0f32)?;
+        } else {
+            writer.write_sample(0i16)?;
+        }
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hound::SampleFormat;
+
+    use crate::loudness::NormalizeMode;
+
+    const RATE: u32 = 1000;
+
+    fn spec() -> WavSpec {
+        WavSpec {
+            channels: 1,
+            sample_rate: RATE,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        }
+    }
+
+    /// Fresh directory for the clips and lesson of one test
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "malti_prac_psm_gen_test_{}_{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Writes a square wave clip of `frames` frames at `amplitude`
+    fn write_clip(path: &Path, frames: usize, amplitude: i16) -> PathBuf {
+        let mut writer = WavWriter::create(path, spec()).unwrap();
+        for frame in 0..frames {
+            let sample = if frame % 2 == 0 {
+                amplitude
+            } else {
+                -amplitude
+            };
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+        path.to_path_buf()
+    }
+
+    fn strings(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    fn labels(count: usize) -> Vec<String> {
+        (0..count).map(|i| format!("Track {}", i + 1)).collect()
+    }
+
+    #[test]
+    fn patterns_expand_to_steps() {
+        use Step::{Clip, Gap, Text};
+        let steps = |pattern: InterleavePattern| pattern.steps(3);
+
+        assert_eq!(
+            steps(InterleavePattern::SourceThenTranslation),
+            [Clip(0), Gap, Clip(1), Gap, Clip(2)]
+        );
+        assert_eq!(
+            steps(InterleavePattern::TranslationThenSource),
+            [Clip(1), Gap, Clip(2), Gap, Clip(0)]
+        );
+        assert_eq!(
+            steps(InterleavePattern::SourceTranslationSource),
+            [Clip(0), Gap, Clip(1), Gap, Clip(2), Gap, Clip(0)]
+        );
+        assert_eq!(
+            steps(InterleavePattern::SourceOnly),
+            [Clip(0), Text(1), Text(2)]
+        );
+        assert_eq!(
+            steps(InterleavePattern::SlowRepeat),
+            [Clip(0), Gap, Clip(0), Gap, Clip(1), Gap, Clip(2)]
+        );
+
+        // A single track has nothing to interleave with
+        assert_eq!(InterleavePattern::TranslationThenSource.steps(1), [Clip(0)]);
+        assert_eq!(InterleavePattern::SourceOnly.steps(1), [Clip(0)]);
+    }
+
+    #[test]
+    fn only_source_only_leaves_tracks_unplayed() {
+        for pattern in InterleavePattern::ALL {
+            assert!(pattern.plays(0, 2));
+            let plays_target = pattern != InterleavePattern::SourceOnly;
+            assert_eq!(pattern.plays(1, 2), plays_target, "{:?}", pattern);
+        }
+    }
+
+    #[test]
+    fn alignment_needs_one_clip_per_line() {
+        let entries = vec![strings(&["a", "b"]), strings(&["x", "y"])];
+        let clip = |name: &str| PathBuf::from(name);
+        let full = vec![vec![clip("0"), clip("1")], vec![clip("2"), clip("3")]];
+        let pattern = InterleavePattern::SourceThenTranslation;
+
+        assert!(check_alignment(&labels(2), &entries, &full, pattern).is_ok());
+
+        let short = vec![vec![clip("0"), clip("1")], vec![clip("2")]];
+        match check_alignment(&labels(2), &entries, &short, pattern) {
+            Err(AppError::CountMismatch { tracks }) => {
+                let counts: Vec<(usize, usize)> =
+                    tracks.iter().map(|t| (t.lines, t.clips)).collect();
+                assert_eq!(counts, [(2, 2), (2, 1)]);
+                assert_eq!(tracks[1].label, "Track 2");
+            }
+            other => panic!("expected a count mismatch, got {:?}", other),
+        }
+
+        let uneven = vec![strings(&["a", "b"]), strings(&["x"])];
+        assert!(matches!(
+            check_alignment(&labels(2), &uneven, &full, pattern),
+            Err(AppError::CountMismatch { .. })
+        ));
+
+        let no_lines: Vec<Vec<String>> = vec![Vec::new(), Vec::new()];
+        let no_clips: Vec<Vec<PathBuf>> = vec![Vec::new(), Vec::new()];
+        assert!(matches!(
+            check_alignment(&labels(2), &no_lines, &no_clips, pattern),
+            Err(AppError::NothingToCombine)
+        ));
+    }
+
+    #[test]
+    fn text_only_tracks_need_no_clips() {
+        let entries = vec![strings(&["a", "b"]), strings(&["x", "y"])];
+        let clips = vec![vec![PathBuf::from("0"), PathBuf::from("1")], Vec::new()];
+
+        assert!(
+            check_alignment(&labels(2), &entries, &clips, InterleavePattern::SourceOnly).is_ok()
+        );
+        assert!(matches!(
+            check_alignment(
+                &labels(2),
+                &entries,
+                &clips,
+                InterleavePattern::SourceThenTranslation
+            ),
+            Err(AppError::CountMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn source_only_combines_without_target_clips() {
+        let dir = test_dir("source_only");
+        let clips = vec![
+            vec![
+                write_clip(&dir.join("0.wav"), 500, 8000),
+                write_clip(&dir.join("1.wav"), 300, 8000),
+            ],
+            Vec::new(),
+        ];
+        let entries = vec![strings(&["Bonġu", "Grazzi"]), strings(&["Hello", "Thanks"])];
+        let lesson = LessonSettings {
+            interleave: Interleave {
+                pattern: InterleavePattern::SourceOnly,
+                shuffle_pairs: false,
+            },
+            ..LessonSettings::default()
+        };
+
+        let output = dir.join("lesson.wav");
+        let combined =
+            combine_clips_alternately(&clips, &entries, &output, &lesson, &AtomicBool::new(false))
+                .unwrap();
+
+        let elements: Vec<(&str, f64, f64, usize, bool)> = combined
+            .elements
+            .iter()
+            .map(|e| {
+                (
+                    e.sentence.as_str(),
+                    e.audio_stop,
+                    e.audio_end,
+                    e.track,
+                    e.clip.is_some(),
+                )
+            })
+            .collect();
+        // 0.5 s clip, 1 s between pairs, 0.3 s clip
+        assert_eq!(
+            elements,
+            [
+                ("Bonġu", 0.0, 0.5, 0, true),
+                ("Hello", 0.0, 0.5, 1, false),
+                ("Grazzi", 1.5, 1.8, 0, true),
+                ("Thanks", 1.5, 1.8, 1, false),
+            ]
+        );
+        assert_eq!(combined.duration, 1.8);
+        assert_eq!(WavReader::open(&output).unwrap().duration(), 1800);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn combining_stops_when_cancelled() {
+        let dir = test_dir("cancelled");
+        let clips = vec![vec![write_clip(&dir.join("0.wav"), 100, 8000)]];
+        let entries = vec![strings(&["Bonġu"])];
+
+        let result = combine_clips_alternately(
+            &clips,
+            &entries,
+            &dir.join("lesson.wav"),
+            &LessonSettings::default(),
+            &AtomicBool::new(true),
+        );
+        assert!(matches!(result, Err(AppError::Cancelled)));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn gains_follow_the_normalization_scope() {
+        let dir = test_dir("gains");
+        // Peaks at about -6 and -12 dBFS
+        let clips = vec![
+            vec![
+                write_clip(&dir.join("0.wav"), 500, 16384),
+                write_clip(&dir.join("1.wav"), 500, 8192),
+            ],
+            Vec::new(),
+        ];
+        let db = |gain: f64| 20.0 * gain.log10();
+
+        let off = clip_gains(&clips, &spec(), &Normalization::default()).unwrap();
+        assert_eq!(off, [vec![1.0, 1.0], vec![]]);
+
+        let mut peak = Normalization {
+            mode: NormalizeMode::Peak,
+            ..Normalization::default()
+        };
+        let per_clip = clip_gains(&clips, &spec(), &peak).unwrap();
+        assert!((db(per_clip[0][0]) - 5.02).abs() < 0.01);
+        assert!((db(per_clip[0][1]) - 11.04).abs() < 0.01);
+        assert!(per_clip[1].is_empty());
+
+        // The whole track moves by the gain of its loudest clip
+        peak.scope = NormalizeScope::PerTrack;
+        let per_track = clip_gains(&clips, &spec(), &peak).unwrap();
+        assert_eq!(per_track[0][0], per_track[0][1]);
+        assert!((db(per_track[0][0]) - 5.02).abs() < 0.01);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn amplify_clips_at_full_scale() {
+        assert_eq!(amplify(1000, 2.0, 16), 2000);
+        assert_eq!(amplify(20000, 2.0, 16), 32767);
+        assert_eq!(amplify(-20000, 2.0, 16), -32768);
+        assert_eq!(amplify(5_000_000, 2.0, 24), 8_388_607);
+    }
+}
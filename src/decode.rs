@@ -0,0 +1,258 @@
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
+use symphonia::core::{
+    audio::SampleBuffer,
+    codecs::{CODEC_TYPE_NULL, DecoderOptions},
+    errors::Error as SymphoniaError,
+    formats::FormatOptions,
+    io::MediaSourceStream,
+    meta::MetadataOptions,
+    probe::Hint,
+};
+
+use crate::error::{AppError, Result};
+
+/// Sample rate every decoded source is converted to before splitting
+pub const TARGET_SAMPLE_RATE: u32 = 44100;
+/// Channel count every decoded source is converted to before splitting
+pub const TARGET_CHANNELS: u16 = 2;
+
+/// File extensions offered in the audio file pickers
+pub const SUPPORTED_EXTENSIONS: &[&str] = &["mp3", "ogg", "flac", "m4a", "wav"];
+
+/// Interleaved 16-bit PCM, the format the rest of the pipeline works on
+#[derive(Debug, Clone)]
+pub struct DecodedAudio {
+    pub samples: Vec<i16>,
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+impl DecodedAudio {
+    pub fn spec(&self) -> WavSpec {
+        WavSpec {
+            channels: self.channels,
+            sample_rate: self.sample_rate,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        }
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.samples.len() / self.channels as usize
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.frame_count() as f64 / self.sample_rate as f64
+    }
+
+    /// Writes the whole buffer out as a 16-bit PCM WAV file
+    pub fn write_wav(&self, path: &Path) -> Result<()> {
+        let mut writer =
+            WavWriter::create(path, self.spec()).map_err(|e| AppError::wav(path, e))?;
+        for sample in &self.samples {
+            writer
+                .write_sample(*sample)
+                .map_err(|e| AppError::wav(path, e))?;
+        }
+        writer.finalize().map_err(|e| AppError::wav(path, e))
+    }
+}
+
+/// Decodes an audio file into 44.1 kHz stereo PCM.
+///
+/// The file is decoded in-process first. ffmpeg is only used as a fallback
+/// for formats the built-in decoders cannot handle, so it does not need to
+/// be installed for MP3, OGG, FLAC or M4A input.
+pub fn decode_audio(path: &Path, scratch_dir: &Path) -> Result<DecodedAudio> {
+    match decode_native(path) {
+        Ok(decoded) => Ok(decoded),
+        Err(native_err) => decode_with_ffmpeg(path, scratch_dir, &native_err),
+    }
+}
+
+fn decode_native(path: &Path) -> std::result::Result<DecodedAudio, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open file: {}", e))?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|s| s.to_str()) {
+        hint.with_extension(ext);
+    }
+
+    let probed = symphonia::default::get_probe()
+        .format(
+            &hint,
+            stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .map_err(|e| format!("Unsupported format: {}", e))?;
+    let mut format = probed.format;
+
+    let track = format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or("No audio track found")?;
+    let track_id = track.id;
+
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .map_err(|e| format!("Unsupported codec: {}", e))?;
+
+    let mut samples: Vec<f32> = Vec::new();
+    let mut sample_rate = track.codec_params.sample_rate.unwrap_or(0);
+    let mut channels = track.codec_params.channels.map(|c| c.count()).unwrap_or(0);
+
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                break;
+            }
+            Err(e) => return Err(format!("Failed to read packet: {}", e)),
+        };
+
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        match decoder.decode(&packet) {
+            Ok(buffer) => {
+                let spec = *buffer.spec();
+                sample_rate = spec.rate;
+                channels = spec.channels.count();
+
+                let mut sample_buffer = SampleBuffer::<f32>::new(buffer.capacity() as u64, spec);
+                sample_buffer.copy_interleaved_ref(buffer);
+                samples.extend_from_slice(sample_buffer.samples());
+            }
+            // A corrupt frame is skipped rather than failing the whole file
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(e) => return Err(format!("Failed to decode packet: {}", e)),
+        }
+    }
+
+    if sample_rate == 0 || channels == 0 {
+        return Err("Could not determine sample rate or channel count".to_string());
+    }
+
+    let stereo = to_stereo(&samples, channels);
+    let resampled = resample(&stereo, sample_rate, TARGET_SAMPLE_RATE);
+
+    Ok(DecodedAudio {
+        samples: resampled
+            .iter()
+            .map(|s| (s.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)
+            .collect(),
+        sample_rate: TARGET_SAMPLE_RATE,
+        channels: TARGET_CHANNELS,
+    })
+}
+
+/// Maps interleaved audio with any channel count onto interleaved stereo
+fn to_stereo(samples: &[f32], channels: usize) -> Vec<f32> {
+    match channels {
+        1 => samples.iter().flat_map(|s| [*s, *s]).collect(),
+        2 => samples.to_vec(),
+        // Keep front left/right, which is what the listener mostly hears anyway
+        _ => samples
+            .chunks_exact(channels)
+            .flat_map(|frame| [frame[0], frame[1]])
+            .collect(),
+    }
+}
+
+/// Linear interpolation resampler for interleaved stereo audio
+fn resample(samples: &[f32], from_rate: u32, to_rate: u32) -> Vec<f32> {
+    if from_rate == to_rate {
+        return samples.to_vec();
+    }
+
+    let frames_in = samples.len() / 2;
+    if frames_in == 0 {
+        return Vec::new();
+    }
+
+    let ratio = from_rate as f64 / to_rate as f64;
+    let frames_out = (frames_in as f64 / ratio).floor() as usize;
+    let mut out = Vec::with_capacity(frames_out * 2);
+
+    for i in 0..frames_out {
+        let pos = i as f64 * ratio;
+        let index = pos.floor() as usize;
+        let next = (index + 1).min(frames_in - 1);
+        let frac = (pos - index as f64) as f32;
+
+        for ch in 0..2 {
+            let a = samples[index * 2 + ch];
+            let b = samples[next * 2 + ch];
+            out.push(a + (b - a) * frac);
+        }
+    }
+
+    out
+}
+
+/// Converts the file with an external ffmpeg binary and reads the result back.
+/// `native_err` is why the built-in decoders gave up, for the error message.
+fn decode_with_ffmpeg(path: &Path, scratch_dir: &Path, native_err: &str) -> Result<DecodedAudio> {
+    let wav_path: PathBuf = scratch_dir.join("ffmpeg_converted.wav");
+
+    let output = Command::new("ffmpeg")
+        .arg("-i")
+        .arg(path)
+        .args([
+            "-acodec",
+            "pcm_s16le",
+            "-ar",
+            &TARGET_SAMPLE_RATE.to_string(),
+            "-ac",
+            &TARGET_CHANNELS.to_string(),
+        ])
+        .arg(&wav_path)
+        .arg("-y") // Overwrite output file
+        .output()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => AppError::FfmpegMissing {
+                path: path.to_path_buf(),
+                reason: native_err.to_string(),
+            },
+            _ => AppError::Decode {
+                path: path.to_path_buf(),
+                reason: format!("{}; failed to run ffmpeg: {}", native_err, e),
+            },
+        })?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(AppError::Decode {
+            path: path.to_path_buf(),
+            reason: format!(
+                "{}; ffmpeg: {}",
+                native_err,
+                stderr.lines().last().unwrap_or("conversion failed")
+            ),
+        });
+    }
+
+    let mut reader = WavReader::open(&wav_path).map_err(|e| AppError::wav(&wav_path, e))?;
+    let spec = reader.spec();
+    let samples = reader
+        .samples::<i16>()
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|e| AppError::wav(&wav_path, e))?;
+    let _ = std::fs::remove_file(&wav_path);
+
+    Ok(DecodedAudio {
+        samples,
+        sample_rate: spec.sample_rate,
+        channels: spec.channels,
+    })
+}
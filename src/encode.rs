@@ -0,0 +1,284 @@
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use serde::{Deserialize, Serialize};
+use symphonia::core::{
+    formats::FormatOptions, io::MediaSourceStream, meta::MetadataOptions, probe::Hint,
+};
+
+use crate::error::{AppError, Result};
+
+/// Container and codec of the exported lesson audio
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum OutputFormat {
+    #[default]
+    Wav,
+    Mp3,
+    Vorbis,
+    Opus,
+    Flac,
+}
+
+impl OutputFormat {
+    pub const ALL: [OutputFormat; 5] = [
+        OutputFormat::Wav,
+        OutputFormat::Mp3,
+        OutputFormat::Vorbis,
+        OutputFormat::Opus,
+        OutputFormat::Flac,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            OutputFormat::Wav => "WAV (uncompressed)",
+            OutputFormat::Mp3 => "MP3",
+            OutputFormat::Vorbis => "Ogg Vorbis",
+            OutputFormat::Opus => "Ogg Opus",
+            OutputFormat::Flac => "FLAC (lossless)",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Wav => "wav",
+            OutputFormat::Mp3 => "mp3",
+            OutputFormat::Vorbis => "ogg",
+            OutputFormat::Opus => "opus",
+            OutputFormat::Flac => "flac",
+        }
+    }
+
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|format| format.extension().eq_ignore_ascii_case(extension))
+    }
+
+    /// Lossy codecs prepend priming samples that players may not trim
+    fn has_encoder_delay(self) -> bool {
+        matches!(self, OutputFormat::Mp3 | OutputFormat::Opus)
+    }
+}
+
+/// Output format and the quality knobs of each encoder
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EncodeSettings {
+    pub format: OutputFormat,
+    /// Constant bitrate in kbit/s
+    pub mp3_bitrate: u32,
+    /// libvorbis quality scale, -1 (smallest) to 10 (best)
+    pub vorbis_quality: f32,
+    /// Target bitrate in kbit/s
+    pub opus_bitrate: u32,
+    /// 0 (fastest) to 12 (smallest)
+    pub flac_compression: u32,
+    /// Move the stops later by the encoder delay, for players that do not
+    /// trim it (i.e. ignore the gapless info in the file)
+    pub shift_stops_for_delay: bool,
+}
+
+impl Default for EncodeSettings {
+    fn default() -> Self {
+        Self {
+            format: OutputFormat::Wav,
+            mp3_bitrate: 96,
+            vorbis_quality: 4.0,
+            opus_bitrate: 48,
+            flac_compression: 5,
+            shift_stops_for_delay: false,
+        }
+    }
+}
+
+/// An encoded lesson and the priming/padding the encoder added to it
+#[derive(Debug, Clone)]
+pub struct EncodedAudio {
+    pub path: PathBuf,
+    /// Priming samples at the start, in seconds
+    pub delay: f64,
+    /// Padding samples at the end, in seconds
+    pub padding: f64,
+}
+
+/// Encodes a WAV file into `output` in the chosen format with ffmpeg, then
+/// reads back how much delay the encoder added
+pub fn encode_wav(wav: &Path, output: &Path, settings: &EncodeSettings) -> Result<EncodedAudio> {
+    if settings.format == OutputFormat::Wav {
+        fs::copy(wav, output).map_err(|e| {
+            AppError::io(
+                format!("Failed to copy {} to {}", wav.display(), output.display()),
+                e,
+            )
+        })?;
+        return Ok(EncodedAudio {
+            path: output.to_path_buf(),
+            delay: 0.0,
+            padding: 0.0,
+        });
+    }
+
+    let codec_args: Vec<String> = match settings.format {
+        OutputFormat::Wav => Vec::new(),
+        OutputFormat::Mp3 => vec![
+            "-c:a".into(),
+            "libmp3lame".into(),
+            "-b:a".into(),
+            format!("{}k", settings.mp3_bitrate),
+        ],
+        OutputFormat::Vorbis => vec![
+            "-c:a".into(),
+            "libvorbis".into(),
+            "-q:a".into(),
+            settings.vorbis_quality.to_string(),
+        ],
+        OutputFormat::Opus => vec![
+            "-c:a".into(),
+            "libopus".into(),
+            "-b:a".into(),
+            format!("{}k", settings.opus_bitrate),
+        ],
+        OutputFormat::Flac => vec![
+            "-c:a".into(),
+            "flac".into(),
+            "-compression_level".into(),
+            settings.flac_compression.to_string(),
+        ],
+    };
+
+    let result = Command::new("ffmpeg")
+        .args(["-hide_banner", "-loglevel", "error", "-y", "-i"])
+        .arg(wav)
+        .args(&codec_args)
+        .arg(output)
+        .output()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => AppError::EncoderMissing {
+                format: settings.format.label(),
+            },
+            _ => AppError::Encode {
+                path: output.to_path_buf(),
+                reason: format!("failed to run ffmpeg: {}", e),
+            },
+        })?;
+
+    if !result.status.success() {
+        let stderr = String::from_utf8_lossy(&result.stderr);
+        return Err(AppError::Encode {
+            path: output.to_path_buf(),
+            reason: stderr
+                .lines()
+                .last()
+                .unwrap_or("encoding failed")
+                .to_string(),
+        });
+    }
+
+    let (delay, padding) = if settings.format.has_encoder_delay() {
+        probe_priming(output).unwrap_or((0.0, 0.0))
+    } else {
+        (0.0, 0.0)
+    };
+
+    Ok(EncodedAudio {
+        path: output.to_path_buf(),
+        delay,
+        padding,
+    })
+}
+
+/// Reads the encoder delay and padding recorded in the file (the LAME tag
+/// for MP3, the pre-skip for Opus), in seconds
+fn probe_priming(path: &Path) -> Option<(f64, f64)> {
+    let file = File::open(path).ok()?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(extension);
+    }
+
+    let probed = symphonia::default::get_probe()
+        .format(
+            &hint,
+            stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .ok()?;
+    let params = &probed.format.default_track()?.codec_params;
+    let sample_rate = params.sample_rate? as f64;
+
+    Some((
+        params.delay.unwrap_or(0) as f64 / sample_rate,
+        params.padding.unwrap_or(0) as f64 / sample_rate,
+    ))
+}
+
+/// Format choice and the quality setting of the chosen encoder
+pub fn render_encode_controls(ui: &mut egui::Ui, settings: &mut EncodeSettings) {
+    egui::Grid::new(ui.next_auto_id())
+        .num_columns(2)
+        .show(ui, |ui| {
+            ui.label("Format:");
+            egui::ComboBox::from_id_source("output_format")
+                .selected_text(settings.format.label())
+                .show_ui(ui, |ui| {
+                    for format in OutputFormat::ALL {
+                        ui.selectable_value(&mut settings.format, format, format.label());
+                    }
+                });
+            ui.end_row();
+
+            match settings.format {
+                OutputFormat::Wav => {}
+                OutputFormat::Mp3 => {
+                    ui.label("Bitrate:");
+                    ui.add(
+                        egui::DragValue::new(&mut settings.mp3_bitrate)
+                            .clamp_range(32..=320)
+                            .suffix(" kbit/s"),
+                    );
+                    ui.end_row();
+                }
+                OutputFormat::Vorbis => {
+                    ui.label("Quality:");
+                    ui.add(
+                        egui::DragValue::new(&mut settings.vorbis_quality)
+                            .speed(0.1)
+                            .clamp_range(-1.0..=10.0),
+                    );
+                    ui.end_row();
+                }
+                OutputFormat::Opus => {
+                    ui.label("Bitrate:");
+                    ui.add(
+                        egui::DragValue::new(&mut settings.opus_bitrate)
+                            .clamp_range(6..=256)
+                            .suffix(" kbit/s"),
+                    );
+                    ui.end_row();
+                }
+                OutputFormat::Flac => {
+                    ui.label("Compression level:");
+                    ui.add(
+                        egui::DragValue::new(&mut settings.flac_compression).clamp_range(0..=12),
+                    );
+                    ui.end_row();
+                }
+            }
+        });
+
+    if settings.format.has_encoder_delay() {
+        ui.checkbox(
+            &mut settings.shift_stops_for_delay,
+            "Shift stops by the encoder delay",
+        )
+        .on_hover_text(
+            "Turn on if the practice app plays the encoder's priming silence \
+             (ignores gapless info), so the stops still land on the sentence ends.",
+        );
+    }
+}
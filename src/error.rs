@@ -0,0 +1,253 @@
+use std::{fmt, io, path::PathBuf};
+
+/// Number of text lines and audio clips found for one track
+#[derive(Debug, Clone)]
+pub struct TrackCount {
+    pub label: String,
+    pub lines: usize,
+    pub clips: usize,
+}
+
+/// Everything that can go wrong between reading the inputs and writing the lesson
+#[derive(Debug)]
+pub enum AppError {
+    /// Neither the built-in decoders nor ffmpeg could read the file
+    Decode {
+        path: PathBuf,
+        reason: String,
+    },
+    /// The built-in decoders failed and ffmpeg, the fallback, is not installed
+    FfmpegMissing {
+        path: PathBuf,
+        reason: String,
+    },
+    /// A track has text but no audio file to go with it
+    MissingAudio {
+        track: String,
+    },
+    /// The tracks do not all have one clip per text line
+    CountMismatch {
+        tracks: Vec<TrackCount>,
+    },
+    /// There are no sentences to combine
+    NothingToCombine,
+    /// A clip does not have the same sample rate, channels or bit depth as the first one
+    SpecMismatch {
+        path: PathBuf,
+    },
+    /// A clip uses a sample format the combiner cannot copy
+    UnsupportedFormat {
+        path: PathBuf,
+        bits_per_sample: u16,
+    },
+    Io {
+        context: String,
+        source: io::Error,
+    },
+    Wav {
+        path: PathBuf,
+        source: hound::Error,
+    },
+    Json {
+        context: String,
+        source: serde_json::Error,
+    },
+    /// ffmpeg, which does all the encoding, is not installed
+    EncoderMissing {
+        format: &'static str,
+    },
+    /// ffmpeg could not encode the lesson
+    Encode {
+        path: PathBuf,
+        reason: String,
+    },
+    /// An Audacity label file that cannot be used as segmentation
+    InvalidLabels {
+        path: PathBuf,
+        /// 1-based, 0 if the problem is not on a single line
+        line: usize,
+        reason: String,
+    },
+    /// A script that could not be imported as sentences
+    UnreadableText {
+        path: PathBuf,
+        reason: String,
+    },
+    /// A batch manifest whose lessons cannot be built as listed
+    InvalidManifest {
+        path: PathBuf,
+        reason: String,
+    },
+    /// No sound output is available
+    Playback {
+        reason: String,
+    },
+    /// A project file written by a newer version of the application
+    UnsupportedProject {
+        path: PathBuf,
+        version: u32,
+    },
+    /// The user stopped the job
+    Cancelled,
+}
+
+pub type Result<T> = std::result::Result<T, AppError>;
+
+impl AppError {
+    pub fn io(context: impl Into<String>, source: io::Error) -> Self {
+        AppError::Io {
+            context: context.into(),
+            source,
+        }
+    }
+
+    pub fn wav(path: impl Into<PathBuf>, source: hound::Error) -> Self {
+        AppError::Wav {
+            path: path.into(),
+            source,
+        }
+    }
+
+    pub fn json(context: impl Into<String>, source: serde_json::Error) -> Self {
+        AppError::Json {
+            context: context.into(),
+            source,
+        }
+    }
+
+    /// What the user can do about the error, if anything
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            AppError::Decode { .. } => {
+                Some("Check that the file is a valid MP3, OGG, FLAC, M4A or WAV recording.")
+            }
+            AppError::FfmpegMissing { .. } => Some(
+                "Install ffmpeg and make sure it is on your PATH, or convert the file to MP3, OGG, FLAC or WAV.",
+            ),
+            AppError::MissingAudio { .. } => {
+                Some("Choose an audio file for this track on the Main screen.")
+            }
+            AppError::CountMismatch { .. } => Some(
+                "Remove blank or extra lines from the text, or adjust the segmentation settings so every sentence becomes exactly one clip.",
+            ),
+            AppError::NothingToCombine => Some("Enter at least one sentence in every track."),
+            AppError::SpecMismatch { .. } | AppError::UnsupportedFormat { .. } => {
+                Some("Run the analysis again so all clips are cut from freshly decoded audio.")
+            }
+            AppError::Io { .. } | AppError::Wav { .. } | AppError::Json { .. } => {
+                Some("Check that the location exists, is writable and has free space.")
+            }
+            AppError::EncoderMissing { .. } => {
+                Some("Install ffmpeg and make sure it is on your PATH, or export as WAV.")
+            }
+            AppError::Encode { .. } => {
+                Some("Check that your ffmpeg build includes the encoder, or pick another format.")
+            }
+            AppError::InvalidLabels { .. } => Some(
+                "Export the labels from Audacity with File > Export > Export Labels, one label per sentence.",
+            ),
+            AppError::UnreadableText { .. } => {
+                Some("Import a .txt, .csv, .tsv, .docx, .odt or .srt file, or paste the text.")
+            }
+            AppError::InvalidManifest { .. } => {
+                Some("Give every lesson its own name, without slashes, to use as its directory.")
+            }
+            AppError::Playback { .. } => Some(
+                "Check that a speaker or headphones are connected and not used by another program.",
+            ),
+            AppError::UnsupportedProject { .. } => {
+                Some("Update the application to open this project.")
+            }
+            AppError::Cancelled => None,
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::Decode { path, reason } => {
+                write!(f, "Could not decode {}: {}", path.display(), reason)
+            }
+            AppError::FfmpegMissing { path, reason } => write!(
+                f,
+                "Could not decode {} ({}) and ffmpeg is not installed to try instead",
+                path.display(),
+                reason
+            ),
+            AppError::MissingAudio { track } => write!(f, "{} has no audio file", track),
+            AppError::CountMismatch { tracks } => {
+                let counts: Vec<String> = tracks
+                    .iter()
+                    .map(|t| format!("{} has {} lines and {} clips", t.label, t.lines, t.clips))
+                    .collect();
+                write!(
+                    f,
+                    "Text lines and audio clips do not match: {}",
+                    counts.join(", ")
+                )
+            }
+            AppError::NothingToCombine => write!(f, "There are no sentences to combine"),
+            AppError::SpecMismatch { path } => write!(
+                f,
+                "{} has a different sample rate, channel count or bit depth than the other clips",
+                path.display()
+            ),
+            AppError::UnsupportedFormat {
+                path,
+                bits_per_sample,
+            } => write!(
+                f,
+                "{} uses an unsupported {}-bit sample format",
+                path.display(),
+                bits_per_sample
+            ),
+            AppError::Io { context, source } => write!(f, "{}: {}", context, source),
+            AppError::Wav { path, source } => {
+                write!(f, "Could not read or write {}: {}", path.display(), source)
+            }
+            AppError::Json { context, source } => write!(f, "{}: {}", context, source),
+            AppError::EncoderMissing { format } => {
+                write!(
+                    f,
+                    "Exporting as {} needs ffmpeg, which is not installed",
+                    format
+                )
+            }
+            AppError::Encode { path, reason } => {
+                write!(f, "Could not encode {}: {}", path.display(), reason)
+            }
+            AppError::InvalidLabels { path, line, reason } if *line > 0 => {
+                write!(f, "{}, line {}: {}", path.display(), line, reason)
+            }
+            AppError::InvalidLabels { path, reason, .. } => {
+                write!(f, "{}: {}", path.display(), reason)
+            }
+            AppError::UnreadableText { path, reason } => {
+                write!(f, "Could not import {}: {}", path.display(), reason)
+            }
+            AppError::InvalidManifest { path, reason } => {
+                write!(f, "{}: {}", path.display(), reason)
+            }
+            AppError::Playback { reason } => write!(f, "Could not play audio: {}", reason),
+            AppError::UnsupportedProject { path, version } => write!(
+                f,
+                "{} is a version {} project, which this version cannot open",
+                path.display(),
+                version
+            ),
+            AppError::Cancelled => write!(f, "Cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for AppError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AppError::Io { source, .. } => Some(source),
+            AppError::Wav { source, .. } => Some(source),
+            AppError::Json { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,365 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    MyApp,
+    anki::{AnkiSettings, AnkiTrack, export_anki, render_anki_controls},
+    encode::{EncodeSettings, encode_wav, render_encode_controls},
+    error::Result,
+    job::CombinedLesson,
+    stops::StopsFormat,
+    subtitles::{SubtitleFormat, SubtitleSettings, render_subtitle_controls, write_subtitles},
+};
+
+/// Placeholders the naming template understands
+pub const TEMPLATE_PLACEHOLDERS: &str = "{lesson}, {date}, {pair}";
+
+/// Where and under which name lessons are exported
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExportSettings {
+    /// File name of the exported lesson, without extension
+    pub name_template: String,
+    /// Directory the save dialog opens in
+    pub last_dir: Option<PathBuf>,
+    pub encoding: EncodeSettings,
+    /// Layout of the exported stops file
+    pub stops_format: StopsFormat,
+    pub subtitles: SubtitleSettings,
+    pub anki: AnkiSettings,
+}
+
+impl Default for ExportSettings {
+    fn default() -> Self {
+        Self {
+            name_template: "{lesson}_{pair}_{date}".to_string(),
+            last_dir: None,
+            encoding: EncodeSettings::default(),
+            stops_format: StopsFormat::default(),
+            subtitles: SubtitleSettings::default(),
+            anki: AnkiSettings::default(),
+        }
+    }
+}
+
+/// Fills in the naming template and strips characters that are not allowed
+/// in file names
+pub fn render_file_name(template: &str, lesson: &str, pair: &str, date: &str) -> String {
+    let lesson = if lesson.trim().is_empty() {
+        "lesson"
+    } else {
+        lesson.trim()
+    };
+    let name = template
+        .replace("{lesson}", lesson)
+        .replace("{date}", date)
+        .replace("{pair}", pair);
+
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+
+    let name = name.trim().trim_matches('.').to_string();
+    if name.is_empty() {
+        "lesson".to_string()
+    } else {
+        name
+    }
+}
+
+/// Today's date as YYYY-MM-DD (UTC)
+pub fn today() -> String {
+    let days = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() / 86_400) as i64;
+
+    // Civil-from-days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Adds `.extension` to a path picked in a save dialog unless it already ends
+/// in it, so dots in the name ("Unit 1.2") are kept
+pub fn with_extension(path: &Path, extension: &str) -> PathBuf {
+    let has_extension = path
+        .extension()
+        .is_some_and(|ext| ext.to_string_lossy().eq_ignore_ascii_case(extension));
+    if has_extension {
+        return path.to_path_buf();
+    }
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".");
+    name.push(extension);
+    PathBuf::from(name)
+}
+
+/// Files written by an export
+#[derive(Debug, Clone, Default)]
+pub struct ExportedLesson {
+    pub files: Vec<PathBuf>,
+    /// Priming the encoder added, in seconds
+    pub encoder_delay: f64,
+}
+
+/// Where the subtitles in `format` of the lesson at `audio_path` go
+pub fn subtitle_path(audio_path: &Path, format: SubtitleFormat) -> PathBuf {
+    audio_path.with_extension(format.extension())
+}
+
+/// Encodes the combined audio to `audio_path` and writes the stops to
+/// `stops_path`, shifted by the encoder delay if the settings ask for it.
+/// Subtitles and labels go next to the audio, with the same name.
+pub fn export_lesson(
+    lesson: &CombinedLesson,
+    audio_path: &Path,
+    stops_path: &Path,
+    settings: &ExportSettings,
+) -> Result<ExportedLesson> {
+    let encoded = encode_wav(&lesson.audio_path, audio_path, &settings.encoding)?;
+    let mut files = vec![encoded.path, stops_path.to_path_buf()];
+
+    let stops = lesson.stops.clone().with_encoder_priming(
+        encoded.delay,
+        encoded.padding,
+        settings.encoding.shift_stops_for_delay,
+    );
+    stops.write(stops_path, settings.stops_format)?;
+
+    for format in settings.subtitles.formats() {
+        let path = subtitle_path(audio_path, format);
+        write_subtitles(&stops, settings.subtitles.layout, format, &path)?;
+        files.push(path);
+    }
+
+    Ok(ExportedLesson {
+        files,
+        encoder_delay: encoded.delay,
+    })
+}
+
+impl MyApp {
+    /// The track labels joined for the {pair} placeholder
+    fn language_pair(&self) -> String {
+        self.tracks
+            .iter()
+            .map(|track| track.label.trim())
+            .collect::<Vec<_>>()
+            .join("-")
+    }
+
+    pub fn export_file_name(&self) -> String {
+        render_file_name(
+            &self.settings.export.name_template,
+            &self.lesson_name,
+            &self.language_pair(),
+            &today(),
+        )
+    }
+
+    /// Writes the sentence pairs and their clips as Anki notes
+    fn export_anki_dialog(&mut self) {
+        let mut dialog = rfd::FileDialog::new()
+            .add_filter("Anki notes", &["txt"])
+            .set_title("Export Anki Notes")
+            .set_file_name(format!("{}.txt", self.export_file_name()));
+        if let Some(dir) = &self.settings.export.last_dir {
+            dialog = dialog.set_directory(dir);
+        }
+        let Some(path) = dialog.save_file() else {
+            return;
+        };
+        let path = with_extension(&path, "txt");
+
+        let tracks: Vec<AnkiTrack> = self
+            .analysis_data
+            .tracks
+            .iter()
+            .map(|track| AnkiTrack {
+                label: &track.label,
+                entries: &track.text_entries,
+                clips: &track.audio_clips,
+            })
+            .collect();
+        let settings = &self.settings.export;
+        let deck = match settings.anki.deck.trim() {
+            "" if self.lesson_name.trim().is_empty() => "Malti",
+            "" => self.lesson_name.trim(),
+            deck => deck,
+        };
+
+        match export_anki(&tracks, &path, deck, &settings.anki, &settings.encoding) {
+            Ok(files) => {
+                self.settings.export.last_dir = path.parent().map(Path::to_path_buf);
+                self.analysis_data.exported = ExportedLesson {
+                    files,
+                    encoder_delay: 0.0,
+                };
+                self.analysis_data.error = None;
+            }
+            Err(e) => {
+                self.analysis_data.exported = Default::default();
+                self.analysis_data.error = Some(e);
+            }
+        }
+    }
+
+    /// Lesson name, naming template and the Export button
+    pub fn render_export_panel(&mut self, ui: &mut egui::Ui) {
+        egui::Grid::new("export_grid")
+            .num_columns(2)
+            .show(ui, |ui| {
+                ui.label("Lesson name:");
+                ui.add(
+                    egui::TextEdit::singleline(&mut self.lesson_name).hint_text("e.g. Lesson 1"),
+                );
+                ui.end_row();
+
+                ui.label("File name:");
+                ui.add(egui::TextEdit::singleline(
+                    &mut self.settings.export.name_template,
+                ))
+                .on_hover_text(format!("Placeholders: {}", TEMPLATE_PLACEHOLDERS));
+                ui.end_row();
+
+                ui.label("");
+                ui.weak(format!(
+                    "{}.{}",
+                    self.export_file_name(),
+                    self.settings.export.encoding.format.extension()
+                ));
+                ui.end_row();
+            });
+
+        render_encode_controls(ui, &mut self.settings.export.encoding);
+
+        render_subtitle_controls(ui, &mut self.settings.export.subtitles);
+
+        ui.horizontal(|ui| {
+            ui.label("Stops file:");
+            egui::ComboBox::from_id_source("stops_format")
+                .selected_text(self.settings.export.stops_format.label())
+                .show_ui(ui, |ui| {
+                    for format in StopsFormat::ALL {
+                        ui.selectable_value(
+                            &mut self.settings.export.stops_format,
+                            format,
+                            format.label(),
+                        );
+                    }
+                });
+        });
+
+        render_anki_controls(ui, &mut self.settings.export.anki, &self.lesson_name);
+
+        ui.add_space(5.0);
+        let has_clips = self
+            .analysis_data
+            .tracks
+            .iter()
+            .any(|track| !track.audio_clips.is_empty());
+        if ui
+            .add_enabled(has_clips, egui::Button::new("🃏 Export Anki Notes…"))
+            .on_hover_text(
+                "One note per sentence pair with a text and an audio field per track. \
+                 Copy the media folder's contents into Anki's collection.media folder, \
+                 then import the notes with a note type that has these fields.",
+            )
+            .clicked()
+        {
+            self.export_anki_dialog();
+        }
+
+        let Some(lesson) = &self.analysis_data.combined else {
+            ui.weak("Combine the lesson to export it.");
+            self.render_exported_files(ui);
+            return;
+        };
+
+        if ui.button("💾 Export…").clicked() {
+            let settings = &self.settings.export;
+            let encoding = &settings.encoding;
+            let extension = encoding.format.extension();
+            let mut dialog = rfd::FileDialog::new()
+                .add_filter(encoding.format.label(), &[extension])
+                .set_title("Export Lesson")
+                .set_file_name(format!("{}.{}", self.export_file_name(), extension));
+            if let Some(dir) = &self.settings.export.last_dir {
+                dialog = dialog.set_directory(dir);
+            }
+
+            if let Some(path) = dialog.save_file() {
+                let audio_path = with_extension(&path, extension);
+                let stops_path = audio_path.with_extension("json");
+                match export_lesson(lesson, &audio_path, &stops_path, settings) {
+                    Ok(exported) => {
+                        self.settings.export.last_dir = path.parent().map(Path::to_path_buf);
+                        self.analysis_data.exported = exported;
+                        self.analysis_data.error = None;
+                    }
+                    Err(e) => {
+                        self.analysis_data.exported = Default::default();
+                        self.analysis_data.error = Some(e);
+                    }
+                }
+            }
+        }
+
+        self.render_exported_files(ui);
+    }
+
+    fn render_exported_files(&self, ui: &mut egui::Ui) {
+        let exported = &self.analysis_data.exported;
+        if !exported.files.is_empty() {
+            ui.label("Exported:");
+            for path in &exported.files {
+                ui.monospace(path.display().to_string());
+            }
+            if exported.encoder_delay > 0.0 {
+                ui.weak(format!(
+                    "Encoder delay: {:.1} ms, recorded in the file for gapless playback",
+                    exported.encoder_delay * 1000.0
+                ));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extension_is_added_only_when_missing() {
+        let cases = [
+            ("Unit 1.2", "Unit 1.2.mp3"),
+            ("Unit 1.2.mp3", "Unit 1.2.mp3"),
+            ("Unit 1.MP3", "Unit 1.MP3"),
+            ("lesson.wav", "lesson.wav.mp3"),
+        ];
+        for (picked, expected) in cases {
+            let audio = with_extension(Path::new(picked), "mp3");
+            assert_eq!(audio, Path::new(expected));
+        }
+
+        let audio = with_extension(Path::new("dir/Unit 1.2"), "mp3");
+        assert_eq!(audio.with_extension("json"), Path::new("dir/Unit 1.2.json"));
+    }
+}
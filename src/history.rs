@@ -0,0 +1,391 @@
+use egui::{Id, Key, Modifiers};
+
+use crate::{
+    MyApp, Track,
+    alignment::{AlignmentState, TrackEdit},
+};
+
+/// Steps kept for undo; the oldest are forgotten first
+const MAX_HISTORY: usize = 200;
+
+/// One undoable change, with the state on both sides of it
+#[derive(Debug, Clone)]
+enum Command {
+    /// Anything on the Main screen: text, labels, audio files, added or removed tracks
+    Tracks {
+        before: Vec<Track>,
+        after: Vec<Track>,
+    },
+    /// Entries and clips of one analyzed track
+    Alignment {
+        track: usize,
+        before: AlignmentState,
+        after: AlignmentState,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct Step {
+    description: String,
+    command: Command,
+}
+
+/// Undo and redo stacks of the session
+#[derive(Debug, Default)]
+pub struct History {
+    undo: Vec<Step>,
+    redo: Vec<Step>,
+    /// The tracks as of the last recorded step
+    tracks: Vec<Track>,
+    /// Text field being typed in; its changes become one step when it is left
+    typing_in: Option<Id>,
+    /// Whether the history window is open
+    pub show: bool,
+}
+
+impl History {
+    /// Forgets every step, e.g. when another project is opened
+    pub(crate) fn reset(&mut self, tracks: &[Track]) {
+        self.undo.clear();
+        self.redo.clear();
+        self.tracks = tracks.to_vec();
+        self.typing_in = None;
+    }
+
+    /// Forgets the steps on analyzed tracks, which no longer apply once the
+    /// text is analyzed again
+    pub fn forget_alignment(&mut self) {
+        let keep = |step: &Step| matches!(step.command, Command::Tracks { .. });
+        self.undo.retain(keep);
+        self.redo.retain(keep);
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    fn push(&mut self, step: Step) {
+        self.redo.clear();
+        self.undo.push(step);
+        if self.undo.len() > MAX_HISTORY {
+            self.undo.remove(0);
+        }
+    }
+
+    /// Records an edit of an analyzed track, unless it changed nothing
+    pub fn record_alignment(
+        &mut self,
+        track: usize,
+        edit: &TrackEdit,
+        before: AlignmentState,
+        after: AlignmentState,
+    ) {
+        if before != after {
+            self.push(Step {
+                description: edit.describe(),
+                command: Command::Alignment {
+                    track,
+                    before,
+                    after,
+                },
+            });
+        }
+    }
+
+    /// Records changes to the tracks since the last step. Typing is
+    /// collected into one step until the text field loses focus.
+    pub(crate) fn watch_tracks(&mut self, tracks: &[Track], focus: Option<Id>) {
+        if tracks == self.tracks {
+            self.typing_in = focus;
+            return;
+        }
+        if focus.is_some() && self.typing_in == focus {
+            return;
+        }
+        self.typing_in = focus;
+        self.flush_tracks(tracks);
+    }
+
+    /// Records changes to the tracks right away, even while typing
+    fn flush_tracks(&mut self, tracks: &[Track]) {
+        if tracks == self.tracks {
+            return;
+        }
+        let before = std::mem::replace(&mut self.tracks, tracks.to_vec());
+        self.push(Step {
+            description: describe_track_change(&before, tracks),
+            command: Command::Tracks {
+                before,
+                after: tracks.to_vec(),
+            },
+        });
+    }
+}
+
+/// What changed between two versions of the tracks
+fn describe_track_change(before: &[Track], after: &[Track]) -> String {
+    if after.len() > before.len() {
+        return "Add track".to_string();
+    }
+    if after.len() < before.len() {
+        return match before.iter().find(|track| !after.contains(track)) {
+            Some(track) if !track.label.is_empty() => format!("Remove track {}", track.label),
+            _ => "Remove track".to_string(),
+        };
+    }
+
+    let changed: Vec<(&Track, &Track)> = before
+        .iter()
+        .zip(after)
+        .filter(|(old, new)| old != new)
+        .collect();
+    let [(old, new)] = changed.as_slice() else {
+        return "Change tracks".to_string();
+    };
+    if old.text != new.text {
+        format!("Edit text of {}", new.label)
+    } else if old.label != new.label {
+        format!("Rename {} to {}", old.label, new.label)
+    } else if old.audio_file != new.audio_file {
+        format!("Change audio of {}", new.label)
+    } else {
+        format!("Change labels of {}", new.label)
+    }
+}
+
+impl MyApp {
+    /// Goes back one step
+    pub fn undo(&mut self, ctx: &egui::Context) {
+        self.history.flush_tracks(&self.tracks);
+        if let Some(step) = self.history.undo.pop() {
+            self.restore(&step.command, true, ctx);
+            self.history.redo.push(step);
+        }
+    }
+
+    /// Goes forward one undone step
+    pub fn redo(&mut self, ctx: &egui::Context) {
+        self.history.flush_tracks(&self.tracks);
+        if let Some(step) = self.history.redo.pop() {
+            self.restore(&step.command, false, ctx);
+            self.history.undo.push(step);
+        }
+    }
+
+    /// Puts back the state from before (`undo`) or after a command
+    fn restore(&mut self, command: &Command, undo: bool, ctx: &egui::Context) {
+        match command {
+            Command::Tracks { before, after } => {
+                let tracks = if undo { before } else { after };
+                self.tracks = tracks.clone();
+                self.history.tracks = tracks.clone();
+            }
+            Command::Alignment {
+                track,
+                before,
+                after,
+            } => {
+                let Some(analysis) = self.analysis_data.tracks.get_mut(*track) else {
+                    return;
+                };
+                let result = analysis.restore_alignment(if undo { before } else { after });
+                if let Err(e) = result {
+                    self.analysis_data.error = Some(e);
+                }
+                self.stop_clips_of(*track);
+                self.alignment_changed(ctx);
+            }
+        }
+    }
+
+    /// Ctrl+Z and Ctrl+Shift+Z, unless a text field has focus and handles them itself
+    pub fn handle_history_shortcuts(&mut self, ctx: &egui::Context) {
+        if ctx.wants_keyboard_input() || self.analysis_data.is_processing {
+            return;
+        }
+        let (redo, undo) = ctx.input_mut(|i| {
+            (
+                i.consume_key(Modifiers::COMMAND | Modifiers::SHIFT, Key::Z),
+                i.consume_key(Modifiers::COMMAND, Key::Z),
+            )
+        });
+        if redo {
+            self.redo(ctx);
+        } else if undo {
+            self.undo(ctx);
+        }
+    }
+
+    /// Undo and redo buttons for the navigation bar
+    pub fn render_history_buttons(&mut self, ui: &mut egui::Ui) {
+        let enabled = !self.analysis_data.is_processing;
+        if ui
+            .add_enabled(enabled && self.history.can_undo(), egui::Button::new("↶"))
+            .on_hover_text(match self.history.undo.last() {
+                Some(step) => format!("Undo {} (Ctrl+Z)", step.description),
+                None => "Undo (Ctrl+Z)".to_string(),
+            })
+            .clicked()
+        {
+            self.undo(ui.ctx());
+        }
+        if ui
+            .add_enabled(enabled && self.history.can_redo(), egui::Button::new("↷"))
+            .on_hover_text(match self.history.redo.last() {
+                Some(step) => format!("Redo {} (Ctrl+Shift+Z)", step.description),
+                None => "Redo (Ctrl+Shift+Z)".to_string(),
+            })
+            .clicked()
+        {
+            self.redo(ui.ctx());
+        }
+        ui.toggle_value(&mut self.history.show, "🕘 History");
+    }
+
+    /// Window listing the steps; clicking one undoes or redoes up to it
+    pub fn render_history_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.history.show;
+        // Number of steps that should be done afterwards
+        let mut target = None;
+        let done = self.history.undo.len();
+
+        egui::Window::new("History")
+            .open(&mut open)
+            .default_width(260.0)
+            .show(ctx, |ui| {
+                if self.analysis_data.is_processing {
+                    ui.weak("Waiting for processing to finish…");
+                    ui.add_enabled_ui(false, |ui| self.render_history_steps(ui, done));
+                    return;
+                }
+                target = self.render_history_steps(ui, done);
+            });
+        self.history.show = open;
+
+        if let Some(target) = target {
+            while self.history.undo.len() > target && self.history.can_undo() {
+                self.undo(ctx);
+            }
+            while self.history.undo.len() < target && self.history.can_redo() {
+                self.redo(ctx);
+            }
+        }
+    }
+
+    /// The done steps, oldest first, then the undone ones greyed out
+    fn render_history_steps(&self, ui: &mut egui::Ui, done: usize) -> Option<usize> {
+        let mut target = None;
+        egui::ScrollArea::vertical()
+            .max_height(400.0)
+            .show(ui, |ui| {
+                if ui.selectable_label(done == 0, "Start").clicked() {
+                    target = Some(0);
+                }
+                for (i, step) in self.history.undo.iter().enumerate() {
+                    if ui
+                        .selectable_label(done == i + 1, &step.description)
+                        .clicked()
+                    {
+                        target = Some(i + 1);
+                    }
+                }
+                for (i, step) in self.history.redo.iter().rev().enumerate() {
+                    let text = egui::RichText::new(&step.description).weak();
+                    if ui.selectable_label(false, text).clicked() {
+                        target = Some(done + i + 1);
+                    }
+                }
+            });
+        target
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app_with_text(text: &str) -> MyApp {
+        let mut app = MyApp {
+            tracks: vec![Track::new(0)],
+            ..Default::default()
+        };
+        app.tracks[0].text = text.to_string();
+        app.history.reset(&app.tracks);
+        app
+    }
+
+    /// Types `text` into the first track and leaves the field
+    fn edit(app: &mut MyApp, text: &str) {
+        app.tracks[0].text = text.to_string();
+        app.history.watch_tracks(&app.tracks, None);
+    }
+
+    #[test]
+    fn undo_and_redo_restore_the_tracks() {
+        let ctx = egui::Context::default();
+        let mut app = app_with_text("one");
+        edit(&mut app, "two");
+        edit(&mut app, "three");
+        assert_eq!(app.history.undo.len(), 2);
+        assert_eq!(app.history.undo[0].description, "Edit text of Track A");
+
+        app.undo(&ctx);
+        assert_eq!(app.tracks[0].text, "two");
+        app.undo(&ctx);
+        assert_eq!(app.tracks[0].text, "one");
+        assert!(!app.history.can_undo());
+
+        app.redo(&ctx);
+        assert_eq!(app.tracks[0].text, "two");
+        assert!(app.history.can_undo());
+        assert!(app.history.can_redo());
+    }
+
+    #[test]
+    fn new_edit_drops_the_undone_steps() {
+        let ctx = egui::Context::default();
+        let mut app = app_with_text("one");
+        edit(&mut app, "two");
+        app.undo(&ctx);
+        assert!(app.history.can_redo());
+
+        edit(&mut app, "other");
+        assert!(!app.history.can_redo());
+        app.redo(&ctx);
+        assert_eq!(app.tracks[0].text, "other");
+    }
+
+    #[test]
+    fn typing_becomes_one_step_when_the_field_is_left() {
+        let field = Id::new("text");
+        let mut app = app_with_text("");
+        app.history.watch_tracks(&app.tracks, Some(field));
+        for text in ["a", "ab", "abc"] {
+            app.tracks[0].text = text.to_string();
+            app.history.watch_tracks(&app.tracks, Some(field));
+        }
+        assert!(!app.history.can_undo());
+
+        app.history.watch_tracks(&app.tracks, None);
+        assert_eq!(app.history.undo.len(), 1);
+    }
+
+    #[test]
+    fn oldest_steps_are_forgotten_past_the_limit() {
+        let ctx = egui::Context::default();
+        let mut app = app_with_text("0");
+        for i in 1..=MAX_HISTORY + 5 {
+            edit(&mut app, &i.to_string());
+        }
+        assert_eq!(app.history.undo.len(), MAX_HISTORY);
+
+        while app.history.can_undo() {
+            app.undo(&ctx);
+        }
+        assert_eq!(app.tracks[0].text, "5");
+    }
+}
@@ -0,0 +1,300 @@
+use std::{
+    fmt,
+    path::PathBuf,
+    sync::{
+        Arc, OnceLock,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, TryRecvError},
+    },
+};
+
+use tokio::runtime::Runtime;
+
+use crate::{
+    analyze::{ProcessedTrack, process_mp3_file},
+    combine::{LessonSettings, check_alignment, combine_clips_alternately},
+    error::{AppError, Result},
+    silence::{Segment, Segmentation, SilenceParams},
+    stops::{StopsFile, StopsFormat, TrackSource},
+};
+
+/// What the pipeline is doing right now
+#[derive(Debug, Clone)]
+pub enum Stage {
+    Decoding {
+        track: String,
+    },
+    Detecting {
+        track: String,
+    },
+    WritingClip {
+        track: String,
+        index: usize,
+        total: usize,
+    },
+    Combining,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stage::Decoding { track } => write!(f, "Decoding audio for {}...", track),
+            Stage::Detecting { track } => write!(f, "Detecting silence in {}...", track),
+            Stage::WritingClip {
+                track,
+                index,
+                total,
+            } => write!(
+                f,
+                "Writing clip {} of {} for {}...",
+                index + 1,
+                total,
+                track
+            ),
+            Stage::Combining => write!(f, "Combining clips into the lesson..."),
+        }
+    }
+}
+
+/// Messages sent from the worker back to the UI
+#[derive(Debug)]
+pub enum JobEvent {
+    Progress {
+        stage: Stage,
+        fraction: f32,
+    },
+    /// All clips of one track have been written
+    TrackDone {
+        track: usize,
+        processed: ProcessedTrack,
+    },
+    Finished(Result<CombinedLesson>),
+}
+
+/// Where the clips of a track come from
+#[derive(Debug, Clone)]
+pub enum TrackAudio {
+    /// Decode the file and split it with silence detection
+    Detect {
+        file: PathBuf,
+        params: SilenceParams,
+    },
+    /// Decode the file and cut it at known boundaries, e.g. from a saved
+    /// project. `params` are the settings they were detected with, if any.
+    Segments {
+        file: PathBuf,
+        segments: Vec<Segment>,
+        params: Option<SilenceParams>,
+    },
+    /// Clips that have already been cut, e.g. after manual edits. `source`,
+    /// `segments` and `params` say where they came from, if known.
+    Clips {
+        clips: Vec<PathBuf>,
+        source: Option<PathBuf>,
+        segments: Vec<Segment>,
+        params: Option<SilenceParams>,
+    },
+}
+
+/// Everything the pipeline needs about one track
+#[derive(Debug, Clone)]
+pub struct TrackInput {
+    pub label: String,
+    pub entries: Vec<String>,
+    pub audio: Option<TrackAudio>,
+}
+
+#[derive(Debug, Clone)]
+pub struct JobInput {
+    pub tracks: Vec<TrackInput>,
+    pub lesson: LessonSettings,
+    /// Scratch directory for the decoded sources and clips
+    pub work_dir: PathBuf,
+    pub output_audio: PathBuf,
+    pub output_stops: PathBuf,
+}
+
+/// The combined lesson written at the end of the pipeline
+#[derive(Debug, Clone)]
+pub struct CombinedLesson {
+    pub audio_path: PathBuf,
+    pub stops_path: PathBuf,
+    pub stops: StopsFile,
+}
+
+/// A pipeline running on the background runtime
+pub struct JobHandle {
+    receiver: Receiver<JobEvent>,
+    cancel: Arc<AtomicBool>,
+}
+
+impl JobHandle {
+    /// Asks the worker to stop at the next clip boundary
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns the next pending event without blocking, or `None` if there is
+    /// nothing new. A worker that died without finishing reports an error.
+    pub fn poll(&self) -> Option<JobEvent> {
+        match self.receiver.try_recv() {
+            Ok(event) => Some(event),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(JobEvent::Finished(Err(AppError::io(
+                "The processing worker stopped unexpectedly",
+                std::io::Error::from(std::io::ErrorKind::BrokenPipe),
+            )))),
+        }
+    }
+}
+
+fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| Runtime::new().expect("Failed to start the tokio runtime"))
+}
+
+/// Starts the pipeline off the UI thread. `ctx` is repainted on every event
+/// so progress shows up without waiting for user input.
+pub fn spawn(input: JobInput, ctx: egui::Context) -> JobHandle {
+    let (sender, receiver) = mpsc::channel();
+    let cancel = Arc::new(AtomicBool::new(false));
+    let worker_cancel = cancel.clone();
+
+    runtime().spawn_blocking(move || {
+        let mut report = |event: JobEvent| {
+            let _ = sender.send(event);
+            ctx.request_repaint();
+        };
+
+        let result = run_pipeline(&input, &mut report, &worker_cancel);
+        report(JobEvent::Finished(result));
+    });
+
+    JobHandle { receiver, cancel }
+}
+
+/// Decodes and splits every track, then combines the clips into a lesson.
+///
+/// Runs synchronously; `report` receives progress and per-track results.
+pub fn run_pipeline(
+    input: &JobInput,
+    report: &mut dyn FnMut(JobEvent),
+    cancel: &AtomicBool,
+) -> Result<CombinedLesson> {
+    // Every track gets an equal share of the progress bar, combining gets the last one
+    let share = 1.0 / (input.tracks.len() + 1) as f32;
+    let mut clips = Vec::with_capacity(input.tracks.len());
+    let mut sources = Vec::with_capacity(input.tracks.len());
+
+    for (i, track) in input.tracks.iter().enumerate() {
+        let (audio_file, segmentation, params) = match &track.audio {
+            Some(TrackAudio::Detect { file, params }) => {
+                (file, Segmentation::Detect(params), Some(params))
+            }
+            Some(TrackAudio::Segments {
+                file,
+                segments,
+                params,
+            }) => (file, Segmentation::Manual(segments), params.as_ref()),
+            Some(TrackAudio::Clips {
+                clips: track_clips,
+                source,
+                segments,
+                params,
+            }) => {
+                clips.push(track_clips.clone());
+                sources.push(TrackSource {
+                    label: track.label.clone(),
+                    source: source.clone(),
+                    segments: segments.clone(),
+                    segmentation: params.clone(),
+                });
+                continue;
+            }
+            None if input.lesson.interleave.pattern.plays(i, input.tracks.len()) => {
+                return Err(AppError::MissingAudio {
+                    track: track.label.clone(),
+                });
+            }
+            // Only shown as text, so no clips are needed
+            None => {
+                clips.push(Vec::new());
+                sources.push(TrackSource {
+                    label: track.label.clone(),
+                    source: None,
+                    segments: Vec::new(),
+                    segmentation: None,
+                });
+                continue;
+            }
+        };
+
+        let base = i as f32 * share;
+        let mut on_stage = |stage: Stage| {
+            let within = match &stage {
+                Stage::Decoding { .. } => 0.0,
+                Stage::Detecting { .. } => 0.3,
+                Stage::WritingClip { index, total, .. } => {
+                    0.4 + 0.6 * *index as f32 / *total as f32
+                }
+                Stage::Combining => 1.0,
+            };
+            report(JobEvent::Progress {
+                stage,
+                fraction: base + within * share,
+            });
+        };
+
+        let processed = process_mp3_file(
+            audio_file,
+            &input.work_dir,
+            i,
+            &track.label,
+            segmentation,
+            &mut on_stage,
+            cancel,
+        )?;
+
+        clips.push(processed.clips.clone());
+        sources.push(TrackSource {
+            label: track.label.clone(),
+            source: Some(audio_file.clone()),
+            segments: processed.segments.clone(),
+            segmentation: params.cloned(),
+        });
+        report(JobEvent::TrackDone {
+            track: i,
+            processed,
+        });
+    }
+
+    report(JobEvent::Progress {
+        stage: Stage::Combining,
+        fraction: 1.0 - share,
+    });
+
+    let labels: Vec<String> = input.tracks.iter().map(|t| t.label.clone()).collect();
+    let entries: Vec<Vec<String>> = input.tracks.iter().map(|t| t.entries.clone()).collect();
+    check_alignment(&labels, &entries, &clips, input.lesson.interleave.pattern)?;
+
+    check_cancelled(cancel)?;
+    let combined =
+        combine_clips_alternately(&clips, &entries, &input.output_audio, &input.lesson, cancel)?;
+    let stops = StopsFile::new(&combined, &sources, &input.lesson);
+    stops.write(&input.output_stops, StopsFormat::Versioned)?;
+
+    Ok(CombinedLesson {
+        audio_path: combined.path,
+        stops_path: input.output_stops.clone(),
+        stops,
+    })
+}
+
+/// Returns an error once the user has asked for the job to stop
+pub fn check_cancelled(cancel: &AtomicBool) -> Result<()> {
+    if cancel.load(Ordering::Relaxed) {
+        Err(AppError::Cancelled)
+    } else {
+        Ok(())
+    }
+}
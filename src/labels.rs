@@ -0,0 +1,130 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{AppError, Result},
+    silence::Segment,
+};
+
+/// Clip boundaries imported from a label file, used instead of silence detection
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImportedLabels {
+    pub path: PathBuf,
+    pub segments: Vec<Segment>,
+}
+
+impl ImportedLabels {
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(Self {
+            path: path.to_path_buf(),
+            segments: read_audacity_labels(path)?,
+        })
+    }
+}
+
+/// Reads an Audacity label file (tab-separated start, end and label, one per
+/// line) as clip boundaries, so the labels can replace silence detection.
+///
+/// Point labels and the frequency lines of spectral labels are skipped.
+pub fn read_audacity_labels(path: &Path) -> Result<Vec<Segment>> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| AppError::io(format!("Failed to read {}", path.display()), e))?;
+    parse_audacity_labels(&text, path)
+}
+
+/// Parses the contents of a label file; `path` is only used in errors
+fn parse_audacity_labels(text: &str, path: &Path) -> Result<Vec<Segment>> {
+    let mut segments = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() || line.starts_with('\\') {
+            continue;
+        }
+
+        let invalid = |reason: &str| AppError::InvalidLabels {
+            path: path.to_path_buf(),
+            line: i + 1,
+            reason: reason.to_string(),
+        };
+
+        let mut fields = line.split('\t');
+        let mut time = |name: &str| {
+            fields
+                .next()
+                // Audacity writes the decimal separator of the system locale
+                .map(|field| field.trim().replace(',', "."))
+                .and_then(|field| field.parse::<f64>().ok())
+                .filter(|value| value.is_finite() && *value >= 0.0)
+                .ok_or_else(|| invalid(&format!("missing or invalid {} time", name)))
+        };
+        let start = time("start")?;
+        let end = time("end")?;
+
+        if end < start {
+            return Err(invalid("the label ends before it starts"));
+        }
+        if end > start {
+            segments.push(Segment { start, end });
+        }
+    }
+
+    if segments.is_empty() {
+        return Err(AppError::InvalidLabels {
+            path: path.to_path_buf(),
+            line: 0,
+            reason: "no labels with a length found".to_string(),
+        });
+    }
+
+    segments.sort_by(|a, b| a.start.total_cmp(&b.start));
+    Ok(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Result<Vec<(f64, f64)>> {
+        parse_audacity_labels(text, Path::new("labels.txt"))
+            .map(|segments| segments.iter().map(|s| (s.start, s.end)).collect())
+    }
+
+    fn invalid_line(text: &str) -> usize {
+        match parse(text) {
+            Err(AppError::InvalidLabels { line, .. }) => line,
+            other => panic!("expected invalid labels, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reads_labels_with_either_decimal_separator() {
+        let text = "0.500000\t1.250000\tBonġu\r\n1,500000\t2,750000\tKif int?\r\n";
+        assert_eq!(parse(text).unwrap(), [(0.5, 1.25), (1.5, 2.75)]);
+    }
+
+    #[test]
+    fn skips_spectral_lines_and_point_labels() {
+        let text = "0.5\t1.0\tfirst\n\\\t100.0\t4000.0\n2.0\t2.0\tpoint\n\n3.0\t4.0\tsecond\n";
+        assert_eq!(parse(text).unwrap(), [(0.5, 1.0), (3.0, 4.0)]);
+    }
+
+    #[test]
+    fn sorts_labels_by_start() {
+        let text = "3.0\t4.0\tc\n0.0\t1.0\ta\n1.5\t2.0\tb\n";
+        assert_eq!(parse(text).unwrap(), [(0.0, 1.0), (1.5, 2.0), (3.0, 4.0)]);
+    }
+
+    #[test]
+    fn reports_the_malformed_line() {
+        assert_eq!(invalid_line("0.0\t1.0\tok\nhello\n"), 2);
+        assert_eq!(invalid_line("0.0\t1.0\tok\n\n2.0\n"), 3);
+        assert_eq!(invalid_line("-1.0\t1.0\tnegative\n"), 1);
+        assert_eq!(invalid_line("0.0\t1.0\tok\n2.0\t1.5\tbackwards\n"), 2);
+        assert_eq!(invalid_line("1.0\t1.0\tonly a point\n"), 0);
+        assert_eq!(invalid_line(""), 0);
+    }
+}
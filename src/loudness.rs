@@ -0,0 +1,626 @@
+use std::{f64::consts::PI, path::Path};
+
+use hound::{SampleFormat, WavReader};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    decode::DecodedAudio,
+    error::{AppError, Result},
+    silence::Segment,
+};
+
+/// Gating blocks of ITU-R BS.1770, in seconds, and how far apart they start
+const BLOCK_LENGTH: f64 = 0.4;
+const BLOCK_STEP: f64 = 0.1;
+const ABSOLUTE_GATE: f64 = -70.0;
+/// Blocks this many LU below the ungated average are left out
+const RELATIVE_GATE: f64 = 10.0;
+/// Largest change normalization makes, so near-silent clips are not blown up
+const MAX_GAIN_DB: f64 = 30.0;
+/// How far from the median a clip may be before it is flagged, in LU
+pub const OUTLIER_LU: f64 = 6.0;
+
+/// What clips are brought to before they are combined
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum NormalizeMode {
+    /// Leave the clips as recorded
+    #[default]
+    Off,
+    /// Scale so the loudest sample reaches the peak target
+    Peak,
+    /// Scale to the loudness target (EBU R128), never pushing a peak above
+    /// the peak target
+    Loudness,
+}
+
+impl NormalizeMode {
+    pub const ALL: [NormalizeMode; 3] = [
+        NormalizeMode::Off,
+        NormalizeMode::Peak,
+        NormalizeMode::Loudness,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            NormalizeMode::Off => "Off",
+            NormalizeMode::Peak => "Peak",
+            NormalizeMode::Loudness => "Loudness (LUFS)",
+        }
+    }
+}
+
+/// Whether every clip gets its own gain, or every clip of a track the same
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum NormalizeScope {
+    #[default]
+    PerClip,
+    /// Keeps the differences within a recording, evens out the recordings
+    PerTrack,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Normalization {
+    pub mode: NormalizeMode,
+    pub scope: NormalizeScope,
+    /// In dBFS
+    pub peak_target: f64,
+    /// In LUFS
+    pub loudness_target: f64,
+}
+
+impl Default for Normalization {
+    fn default() -> Self {
+        Self {
+            mode: NormalizeMode::Off,
+            scope: NormalizeScope::PerClip,
+            peak_target: -1.0,
+            loudness_target: -18.0,
+        }
+    }
+}
+
+impl Normalization {
+    /// Factor to multiply the samples of audio measured as `loudness` by
+    pub fn gain(&self, loudness: &Loudness) -> f64 {
+        let headroom = self.peak_target - loudness.peak;
+        let db = match (self.mode, loudness.integrated) {
+            _ if !loudness.peak.is_finite() => 0.0,
+            (NormalizeMode::Off, _) | (NormalizeMode::Loudness, None) => 0.0,
+            (NormalizeMode::Peak, _) => headroom,
+            (NormalizeMode::Loudness, Some(integrated)) => {
+                (self.loudness_target - integrated).min(headroom)
+            }
+        };
+        10f64.powf(db.clamp(-MAX_GAIN_DB, MAX_GAIN_DB) / 20.0)
+    }
+}
+
+/// Measured level of some audio
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Loudness {
+    /// Integrated loudness in LUFS; `None` if everything is below the gate
+    pub integrated: Option<f64>,
+    /// Loudest sample in dBFS; minus infinity for digital silence
+    pub peak: f64,
+}
+
+/// Loudness meter after ITU-R BS.1770-4: K-weighting, 400 ms blocks with
+/// 75% overlap, and an absolute and a relative gate. Clips are measured one
+/// at a time and together, as if they were played back to back.
+pub struct LoudnessMeter {
+    channels: usize,
+    sample_rate: f64,
+    /// Mean square of every gating block of every clip so far
+    blocks: Vec<f64>,
+    peak: f32,
+}
+
+impl LoudnessMeter {
+    pub fn new(channels: u16, sample_rate: u32) -> Self {
+        Self {
+            channels: channels.max(1) as usize,
+            sample_rate: sample_rate as f64,
+            blocks: Vec::new(),
+            peak: 0.0,
+        }
+    }
+
+    /// Measures one clip of interleaved samples between -1 and 1
+    pub fn add_clip(&mut self, samples: &[f32]) -> Loudness {
+        let frames = samples.len() / self.channels;
+        let peak = samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        self.peak = self.peak.max(peak);
+
+        // K-weighted energy of every frame, summed over the channels. The
+        // channel weights of BS.1770 are 1 for everything but surround.
+        let mut energy = vec![0.0f64; frames];
+        for channel in 0..self.channels {
+            let mut filter = KWeighting::new(self.sample_rate);
+            for (frame, sum) in energy.iter_mut().enumerate() {
+                let weighted = filter.process(samples[frame * self.channels + channel] as f64);
+                *sum += weighted * weighted;
+            }
+        }
+
+        // A clip shorter than one block is measured as a single block
+        let block = (BLOCK_LENGTH * self.sample_rate) as usize;
+        let step = (BLOCK_STEP * self.sample_rate).max(1.0) as usize;
+        let blocks: Vec<f64> = if frames == 0 {
+            Vec::new()
+        } else if frames < block {
+            vec![energy.iter().sum::<f64>() / frames as f64]
+        } else {
+            (0..=frames - block)
+                .step_by(step)
+                .map(|start| energy[start..start + block].iter().sum::<f64>() / block as f64)
+                .collect()
+        };
+
+        self.blocks.extend_from_slice(&blocks);
+        Loudness {
+            integrated: gated_loudness(&blocks),
+            peak: to_db(peak as f64),
+        }
+    }
+
+    /// Everything added so far, as one recording
+    pub fn total(&self) -> Loudness {
+        Loudness {
+            integrated: gated_loudness(&self.blocks),
+            peak: to_db(self.peak as f64),
+        }
+    }
+}
+
+fn to_db(amplitude: f64) -> f64 {
+    20.0 * amplitude.log10()
+}
+
+fn block_loudness(mean_square: f64) -> f64 {
+    -0.691 + 10.0 * mean_square.log10()
+}
+
+/// Integrated loudness of the blocks that pass both gates
+fn gated_loudness(blocks: &[f64]) -> Option<f64> {
+    let mean = |blocks: &[f64]| blocks.iter().sum::<f64>() / blocks.len() as f64;
+
+    let audible: Vec<f64> = blocks
+        .iter()
+        .copied()
+        .filter(|&block| block_loudness(block) > ABSOLUTE_GATE)
+        .collect();
+    if audible.is_empty() {
+        return None;
+    }
+
+    let threshold = block_loudness(mean(&audible)) - RELATIVE_GATE;
+    let gated: Vec<f64> = audible
+        .into_iter()
+        .filter(|&block| block_loudness(block) > threshold)
+        .collect();
+    Some(block_loudness(mean(&gated)))
+}
+
+/// The two filters of BS.1770's K-weighting: a high shelf for the head and
+/// a high-pass for the low end
+struct KWeighting {
+    stages: [Biquad; 2],
+}
+
+impl KWeighting {
+    /// Coefficients for any sample rate, as derived in libebur128
+    fn new(sample_rate: f64) -> Self {
+        let f0 = 1681.974450955533;
+        let gain = 3.999843853973347;
+        let q = 0.7071752369554196;
+        let k = (PI * f0 / sample_rate).tan();
+        let vh = 10f64.powf(gain / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        let shelf = Biquad::new(
+            [
+                (vh + vb * k / q + k * k) / a0,
+                2.0 * (k * k - vh) / a0,
+                (vh - vb * k / q + k * k) / a0,
+            ],
+            [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        );
+
+        let f0 = 38.13547087602444;
+        let q = 0.5003270373238773;
+        let k = (PI * f0 / sample_rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+        let high_pass = Biquad::new(
+            [1.0, -2.0, 1.0],
+            [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        );
+
+        Self {
+            stages: [shelf, high_pass],
+        }
+    }
+
+    fn process(&mut self, sample: f64) -> f64 {
+        self.stages
+            .iter_mut()
+            .fold(sample, |sample, stage| stage.process(sample))
+    }
+}
+
+/// Direct form II biquad with a0 normalized to 1
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    state: [f64; 2],
+}
+
+impl Biquad {
+    fn new(b: [f64; 3], a: [f64; 2]) -> Self {
+        Self {
+            b,
+            a,
+            state: [0.0; 2],
+        }
+    }
+
+    fn process(&mut self, input: f64) -> f64 {
+        let w = input - self.a[0] * self.state[0] - self.a[1] * self.state[1];
+        let output = self.b[0] * w + self.b[1] * self.state[0] + self.b[2] * self.state[1];
+        self.state = [w, self.state[0]];
+        output
+    }
+}
+
+/// Samples of a clip file between -1 and 1, whatever its bit depth
+pub fn read_clip(path: &Path) -> Result<Vec<f32>> {
+    let mut reader = WavReader::open(path).map_err(|e| AppError::wav(path, e))?;
+    let spec = reader.spec();
+    match spec.sample_format {
+        SampleFormat::Float => reader
+            .samples::<f32>()
+            .collect::<hound::Result<_>>()
+            .map_err(|e| AppError::wav(path, e)),
+        SampleFormat::Int => {
+            let scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|sample| sample.map(|s| s as f32 / scale))
+                .collect::<hound::Result<_>>()
+                .map_err(|e| AppError::wav(path, e))
+        }
+    }
+}
+
+/// Measured loudness of one clip of a recording
+#[derive(Debug, Clone)]
+pub struct ClipLoudness {
+    pub segment: Segment,
+    pub loudness: Loudness,
+    /// Gating blocks, so clips can be measured together
+    blocks: Vec<f64>,
+}
+
+/// Measures every segment of a recording, reusing the measurements in
+/// `previous` of segments that did not change
+pub fn measure_segments(
+    audio: &DecodedAudio,
+    segments: &[Segment],
+    previous: &[ClipLoudness],
+) -> Vec<ClipLoudness> {
+    let channels = audio.channels.max(1) as usize;
+    let rate = audio.sample_rate as f64;
+    segments
+        .iter()
+        .map(|segment| {
+            if let Some(known) = previous.iter().find(|clip| clip.segment == *segment) {
+                return known.clone();
+            }
+
+            let first = ((segment.start * rate) as usize).min(audio.frame_count());
+            let last = ((segment.end * rate) as usize).clamp(first, audio.frame_count());
+            let samples: Vec<f32> = audio.samples[first * channels..last * channels]
+                .iter()
+                .map(|&s| s as f32 / 32768.0)
+                .collect();
+            let mut meter = LoudnessMeter::new(audio.channels, audio.sample_rate);
+            let loudness = meter.add_clip(&samples);
+            ClipLoudness {
+                segment: *segment,
+                loudness,
+                blocks: meter.blocks,
+            }
+        })
+        .collect()
+}
+
+/// Loudness of the clips played back to back
+pub fn total_loudness(clips: &[ClipLoudness]) -> Loudness {
+    let blocks: Vec<f64> = clips
+        .iter()
+        .flat_map(|clip| clip.blocks.iter().copied())
+        .collect();
+    Loudness {
+        integrated: gated_loudness(&blocks),
+        peak: clips
+            .iter()
+            .map(|clip| clip.loudness.peak)
+            .fold(f64::NEG_INFINITY, f64::max),
+    }
+}
+
+/// Middle of the measured loudnesses, to spot clips far from it
+pub fn median_loudness<'a>(clips: impl Iterator<Item = &'a ClipLoudness>) -> Option<f64> {
+    let mut values: Vec<f64> = clips.filter_map(|clip| clip.loudness.integrated).collect();
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(f64::total_cmp);
+    Some(values[values.len() / 2])
+}
+
+/// "-18.2 LUFS", or "silent" below the gate
+pub fn format_loudness(loudness: Option<f64>) -> String {
+    match loudness {
+        Some(lufs) => format!("{:.1} LUFS", lufs),
+        None => "silent".to_string(),
+    }
+}
+
+/// Editing widgets for the normalization of the combined lesson
+pub fn render_normalization_controls(ui: &mut egui::Ui, normalization: &mut Normalization) {
+    ui.horizontal(|ui| {
+        ui.label("Normalize:");
+        egui::ComboBox::from_id_source("normalize_mode")
+            .selected_text(normalization.mode.label())
+            .show_ui(ui, |ui| {
+                for mode in NormalizeMode::ALL {
+                    ui.selectable_value(&mut normalization.mode, mode, mode.label());
+                }
+            });
+    });
+    if normalization.mode == NormalizeMode::Off {
+        return;
+    }
+
+    ui.horizontal(|ui| {
+        ui.radio_value(
+            &mut normalization.scope,
+            NormalizeScope::PerClip,
+            "Every clip",
+        );
+        ui.radio_value(
+            &mut normalization.scope,
+            NormalizeScope::PerTrack,
+            "Every track as a whole",
+        )
+        .on_hover_text("Keeps the differences between the sentences of one speaker");
+    });
+
+    egui::Grid::new(ui.next_auto_id())
+        .num_columns(2)
+        .show(ui, |ui| {
+            if normalization.mode == NormalizeMode::Loudness {
+                ui.label("Loudness target:");
+                ui.add(
+                    egui::DragValue::new(&mut normalization.loudness_target)
+                        .speed(0.1)
+                        .clamp_range(-40.0..=-5.0)
+                        .suffix(" LUFS"),
+                )
+                .on_hover_text(
+                    "-23 is the EBU R128 broadcast level; speech apps often use -16 to -18",
+                );
+                ui.end_row();
+            }
+
+            ui.label(match normalization.mode {
+                NormalizeMode::Loudness => "Peak ceiling:",
+                _ => "Peak target:",
+            });
+            ui.add(
+                egui::DragValue::new(&mut normalization.peak_target)
+                    .speed(0.1)
+                    .clamp_range(-20.0..=0.0)
+                    .suffix(" dBFS"),
+            );
+            ui.end_row();
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48_000;
+
+    /// Interleaved 997 Hz sine at `dbfs`, the same on every channel
+    fn sine(dbfs: f64, seconds: f64, channels: usize) -> Vec<f32> {
+        let amplitude = 10f64.powf(dbfs / 20.0);
+        let frames = (seconds * RATE as f64) as usize;
+        (0..frames)
+            .flat_map(|frame| {
+                let t = frame as f64 / RATE as f64;
+                let sample = (amplitude * (2.0 * PI * 997.0 * t).sin()) as f32;
+                std::iter::repeat_n(sample, channels)
+            })
+            .collect()
+    }
+
+    fn measure(samples: &[f32], channels: u16) -> Loudness {
+        LoudnessMeter::new(channels, RATE).add_clip(samples)
+    }
+
+    fn assert_near(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() < tolerance,
+            "{} is not within {} of {}",
+            actual,
+            tolerance,
+            expected
+        );
+    }
+
+    #[test]
+    fn sine_reads_at_the_reference_loudness() {
+        // BS.1770: a 997 Hz sine at 0 dBFS in both stereo channels is 0 LUFS
+        let stereo = measure(&sine(-20.0, 3.0, 2), 2);
+        assert_near(stereo.integrated.unwrap(), -20.0, 0.05);
+        assert_near(stereo.peak, -20.0, 0.01);
+
+        let mono = measure(&sine(-20.0, 3.0, 1), 1);
+        assert_near(mono.integrated.unwrap(), -23.01, 0.05);
+    }
+
+    #[test]
+    fn short_clips_are_one_block() {
+        let loudness = measure(&sine(-20.0, 0.2, 1), 1);
+        assert_near(loudness.integrated.unwrap(), -23.01, 0.2);
+    }
+
+    #[test]
+    fn silence_is_below_the_absolute_gate() {
+        let silent = measure(&vec![0.0; RATE as usize], 1);
+        assert_eq!(silent.integrated, None);
+        assert_eq!(silent.peak, f64::NEG_INFINITY);
+
+        let hum = measure(&sine(-80.0, 1.0, 1), 1);
+        assert_eq!(hum.integrated, None);
+        assert_near(hum.peak, -80.0, 0.01);
+
+        assert_eq!(measure(&[], 1).integrated, None);
+    }
+
+    #[test]
+    fn relative_gate_ignores_quiet_passages() {
+        let mut samples = sine(-20.0, 2.0, 1);
+        samples.extend(sine(-45.0, 2.0, 1));
+        let gated = measure(&samples, 1).integrated.unwrap();
+
+        // Without the relative gate the quiet half would pull it to about -26
+        assert!(gated > -23.6 && gated < -22.9, "{}", gated);
+    }
+
+    #[test]
+    fn meter_adds_up_clips_as_one_recording() {
+        let mut meter = LoudnessMeter::new(1, RATE);
+        let loud = meter.add_clip(&sine(-20.0, 1.0, 1));
+        let quiet = meter.add_clip(&sine(-30.0, 1.0, 1));
+        assert_near(loud.integrated.unwrap(), -23.01, 0.05);
+        assert_near(quiet.integrated.unwrap(), -33.01, 0.05);
+
+        let total = meter.total();
+        assert_eq!(total.peak, loud.peak);
+        assert!(total.integrated.unwrap() < -23.01 && total.integrated.unwrap() > -33.01);
+    }
+
+    fn normalization(mode: NormalizeMode) -> Normalization {
+        Normalization {
+            mode,
+            ..Normalization::default()
+        }
+    }
+
+    fn loudness(integrated: Option<f64>, peak: f64) -> Loudness {
+        Loudness { integrated, peak }
+    }
+
+    fn gain_db(normalization: &Normalization, measured: &Loudness) -> f64 {
+        to_db(normalization.gain(measured))
+    }
+
+    #[test]
+    fn gain_reaches_the_target() {
+        let peak = normalization(NormalizeMode::Peak);
+        assert_near(gain_db(&peak, &loudness(Some(-30.0), -7.0)), 6.0, 1e-9);
+        assert_near(gain_db(&peak, &loudness(None, -7.0)), 6.0, 1e-9);
+
+        let lufs = normalization(NormalizeMode::Loudness);
+        assert_near(gain_db(&lufs, &loudness(Some(-24.0), -12.0)), 6.0, 1e-9);
+        assert_near(gain_db(&lufs, &loudness(Some(-10.0), -2.0)), -8.0, 1e-9);
+    }
+
+    #[test]
+    fn gain_is_capped_by_the_peak_ceiling() {
+        // 12 dB to the loudness target, but only 2 dB until the peak hits -1 dBFS
+        let lufs = normalization(NormalizeMode::Loudness);
+        assert_near(gain_db(&lufs, &loudness(Some(-30.0), -3.0)), 2.0, 1e-9);
+    }
+
+    #[test]
+    fn gain_leaves_silence_and_disabled_modes_alone() {
+        let lufs = normalization(NormalizeMode::Loudness);
+        assert_eq!(lufs.gain(&loudness(None, -50.0)), 1.0);
+        assert_eq!(lufs.gain(&loudness(None, f64::NEG_INFINITY)), 1.0);
+
+        let off = normalization(NormalizeMode::Off);
+        assert_eq!(off.gain(&loudness(Some(-40.0), -30.0)), 1.0);
+
+        let peak = normalization(NormalizeMode::Peak);
+        assert_near(gain_db(&peak, &loudness(None, -70.0)), MAX_GAIN_DB, 1e-9);
+    }
+
+    fn recording(parts: &[(f64, f64)]) -> DecodedAudio {
+        let samples = parts
+            .iter()
+            .flat_map(|&(dbfs, seconds)| sine(dbfs, seconds, 1))
+            .map(|s| (s * 32768.0) as i16)
+            .collect();
+        DecodedAudio {
+            samples,
+            sample_rate: RATE,
+            channels: 1,
+        }
+    }
+
+    #[test]
+    fn segments_are_measured_and_reused() {
+        let audio = recording(&[(-20.0, 1.0), (-100.0, 1.0), (-30.0, 1.0)]);
+        let segments = [
+            Segment {
+                start: 0.0,
+                end: 1.0,
+            },
+            Segment {
+                start: 1.0,
+                end: 2.0,
+            },
+            Segment {
+                start: 2.0,
+                end: 3.0,
+            },
+        ];
+
+        let measured = measure_segments(&audio, &segments, &[]);
+        assert_near(measured[0].loudness.integrated.unwrap(), -23.01, 0.05);
+        assert_eq!(measured[1].loudness.integrated, None);
+        assert_near(measured[2].loudness.integrated.unwrap(), -33.01, 0.05);
+
+        // Unchanged segments keep their measurement, even in a new position
+        let moved = [segments[2], segments[0]];
+        let remeasured = measure_segments(&audio, &moved, &measured);
+        assert_eq!(remeasured[0].loudness, measured[2].loudness);
+        assert_eq!(remeasured[1].loudness, measured[0].loudness);
+
+        let total = total_loudness(&measured);
+        assert_near(total.peak, -20.0, 0.01);
+        assert!(total.integrated.unwrap() < -23.01);
+    }
+
+    #[test]
+    fn median_skips_silent_clips() {
+        let audio = recording(&[(-20.0, 1.0), (-100.0, 1.0), (-30.0, 1.0), (-40.0, 1.0)]);
+        let segments: Vec<Segment> = (0..4)
+            .map(|i| Segment {
+                start: i as f64,
+                end: i as f64 + 1.0,
+            })
+            .collect();
+        let measured = measure_segments(&audio, &segments, &[]);
+
+        assert_near(median_loudness(measured.iter()).unwrap(), -33.01, 0.05);
+        assert_eq!(median_loudness(measured[1..2].iter()), None);
+        assert_eq!(format_loudness(None), "silent");
+        assert_eq!(format_loudness(Some(-18.24)), "-18.2 LUFS");
+    }
+}
@@ -1,11 +1,37 @@
 use eframe::egui;
-use std::path::PathBuf;
+use settings::Settings;
+use std::{path::PathBuf, sync::Arc};
 
+pub mod alignment;
 pub mod analyze;
+pub mod anki;
+pub mod batch;
+pub mod cli;
 pub mod combine;
+pub mod decode;
+pub mod encode;
+pub mod error;
+pub mod export;
+pub mod history;
+pub mod job;
+pub mod labels;
+pub mod loudness;
 pub mod other;
+pub mod player;
+pub mod project;
+pub mod sentences;
+pub mod settings;
+pub mod silence;
+pub mod stops;
+pub mod subtitles;
+pub mod text_import;
+pub mod waveform;
 
 fn main() -> Result<(), eframe::Error> {
+    if let Some(code) = cli::run_from_args() {
+        std::process::exit(code);
+    }
+
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default().with_inner_size([800.0, 600.0]),
         ..Default::default()
@@ -14,12 +40,13 @@ fn main() -> Result<(), eframe::Error> {
     eframe::run_native(
         "Multi-Screen Text Editor with MP3 File Selector",
         options,
-        Box::new(|_cc| Box::new(MyApp::default())),
+        Box::new(|cc| Box::new(MyApp::new(&cc.egui_ctx))),
     )
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Default)]
 enum AppScreen {
+    #[default]
     Main,
     Settings,
     FileManager,
@@ -31,238 +58,474 @@ struct MyApp {
     current_screen: AppScreen,
 
     // Main screen data
-    text_area_1: String,
-    text_area_2: String,
-    mp3_file_1: Option<PathBuf>,
-    mp3_file_2: Option<PathBuf>,
+    tracks: Vec<Track>,
+    lesson_name: String,
+    /// File being previewed before its text goes into the tracks
+    text_import: Option<text_import::TextImport>,
 
     // Settings screen data
-    window_title: String,
-    auto_save: bool,
-    theme_dark: bool,
+    settings: Settings,
+    saved_settings: Settings,
+    /// Why the last write of the settings failed
+    settings_error: Option<error::AppError>,
 
     // File manager screen data
     file_history: Vec<PathBuf>,
 
     // analysis
     analysis_data: AnalysisData,
+    /// Plays clips, source recordings and the lesson preview
+    player: player::Player,
+    /// Undo and redo across the tracks and the analyzer
+    history: history::History,
+
+    // The project file the session is saved to
+    project: project::ProjectState,
+}
+
+/// One language or speaker: its script and the recording of it
+#[derive(Debug, Clone, PartialEq, Default)]
+struct Track {
+    label: String,
+    text: String,
+    audio_file: Option<PathBuf>,
+    /// Clip boundaries for `audio_file` that replace silence detection
+    labels: Option<labels::ImportedLabels>,
+}
+
+impl Track {
+    fn new(index: usize) -> Self {
+        Self {
+            label: format!("Track {}", (b'A' + (index % 26) as u8) as char),
+            ..Default::default()
+        }
+    }
+
+    /// Changes the recording; labels made for the old one no longer apply
+    fn set_audio_file(&mut self, path: Option<PathBuf>) {
+        if path != self.audio_file {
+            self.labels = None;
+        }
+        self.audio_file = path;
+    }
 }
 
 #[derive(Default)]
 struct AnalysisData {
-    text_entries_1: Vec<String>,
-    text_entries_2: Vec<String>,
-    audio_clips_1: Vec<PathBuf>,
-    audio_clips_2: Vec<PathBuf>,
+    tracks: Vec<TrackAnalysis>,
     is_processing: bool,
     processing_status: String,
+    progress: f32,
+    error: Option<error::AppError>,
+    job: Option<job::JobHandle>,
+    /// The last lesson combined into the scratch directory
+    combined: Option<job::CombinedLesson>,
+    /// `combined`'s audio, decoded the first time it is previewed
+    lesson_audio: Option<Arc<decode::DecodedAudio>>,
+    /// Files written by the last export
+    exported: export::ExportedLesson,
+    /// Scratch directories of the jobs so far, the running or last one last
+    job_dirs: Vec<PathBuf>,
 }
 
-impl Default for AppScreen {
-    fn default() -> Self {
-        AppScreen::Main
-    }
+#[derive(Default)]
+struct TrackAnalysis {
+    label: String,
+    text_entries: Vec<String>,
+    audio_clips: Vec<PathBuf>,
+
+    // Where the clips came from, so they can be re-cut after manual edits
+    source_path: Option<PathBuf>,
+    source: Option<Arc<decode::DecodedAudio>>,
+    segments: Vec<silence::Segment>,
+    /// Silence detection the segments came from; `None` for imported labels
+    segmentation: Option<silence::SilenceParams>,
+    clips_dir: PathBuf,
+    /// Clip being split and the offset chosen so far
+    pending_split: Option<(usize, f64)>,
+    /// Entry being typed in and its text so far
+    editing_entry: Option<(usize, String)>,
+    /// Measured loudness of the clips, updated when the segments change
+    loudness: Vec<loudness::ClipLoudness>,
+    waveform: waveform::WaveformState,
 }
 
 impl MyApp {
+    fn new(ctx: &egui::Context) -> Self {
+        let settings = Settings::load();
+        let mut app = Self {
+            saved_settings: settings.clone(),
+            settings,
+            ..Default::default()
+        };
+
+        app.new_project();
+        if app.settings.auto_save {
+            app.restore_last_session(ctx);
+        }
+        app
+    }
+
     fn render_main_screen(&mut self, ui: &mut egui::Ui) {
         ui.heading("Text Editor with MP3 File Selector");
         ui.separator();
 
-        // First section: Text Area 1 and MP3 File 1
-        ui.group(|ui| {
-            ui.label("Text Area 1:");
-            egui::ScrollArea::vertical()
-                .id_source("0")
-                .max_height(150.0)
-                .show(ui, |ui| {
-                    ui.add(
-                        egui::TextEdit::multiline(&mut self.text_area_1)
-                            .desired_width(f32::INFINITY)
-                            .desired_rows(8)
-                            .hint_text("Enter your text here..."),
-                    );
+        sentences::render_split_controls(ui, &mut self.settings.sentence_split);
+        ui.add_space(5.0);
+
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            let mut to_remove = None;
+            let mut import_into = None;
+            let mut label_error = None;
+            let track_count = self.tracks.len();
+
+            // One section per track: its label, text area and audio file
+            for (i, track) in self.tracks.iter_mut().enumerate() {
+                ui.push_id(i, |ui| {
+                    ui.group(|ui| {
+                        ui.horizontal(|ui| {
+                            ui.label(format!("Track {}:", i + 1));
+                            ui.add(
+                                egui::TextEdit::singleline(&mut track.label)
+                                    .hint_text("Label, e.g. Maltese"),
+                            );
+
+                            if ui
+                                .button("📄 Import Text…")
+                                .on_hover_text(
+                                    "Load sentences from a text, spreadsheet, document or subtitle file",
+                                )
+                                .clicked()
+                            {
+                                import_into = Some(i);
+                            }
+
+                            if track_count > 1 && ui.button("Remove Track").clicked() {
+                                to_remove = Some(i);
+                            }
+                        });
+
+                        egui::ScrollArea::vertical()
+                            .max_height(150.0)
+                            .show(ui, |ui| {
+                                ui.add(
+                                    egui::TextEdit::multiline(&mut track.text)
+                                        .desired_width(f32::INFINITY)
+                                        .desired_rows(8)
+                                        .hint_text("Enter your text here..."),
+                                );
+                            });
+                        sentences::render_split_preview(
+                            ui,
+                            &track.text,
+                            &self.settings.sentence_split,
+                        );
+
+                        ui.add_space(10.0);
+
+                        ui.horizontal(|ui| {
+                            ui.label("Audio File:");
+                            if let Some(path) = &track.audio_file {
+                                ui.label(format!("Selected: {}", path.display()));
+                            } else {
+                                ui.label("No file selected");
+                            }
+                        });
+
+                        ui.horizontal(|ui| {
+                            if ui.button("Browse Audio File").clicked()
+                                && let Some(path) = rfd::FileDialog::new()
+                                    .add_filter("Audio", decode::SUPPORTED_EXTENSIONS)
+                                    .set_title(format!("Select Audio for {}", track.label))
+                                    .pick_file()
+                            {
+                                track.set_audio_file(Some(path.clone()));
+                                if !self.file_history.contains(&path) {
+                                    self.file_history.push(path);
+                                }
+                            }
+
+                            if track.audio_file.is_some() && ui.button("Clear Audio File").clicked()
+                            {
+                                track.set_audio_file(None);
+                            }
+                        });
+
+                        if let Some(path) = &track.audio_file {
+                            ui.horizontal(|ui| match &track.labels {
+                                Some(labels) => {
+                                    ui.label(format!(
+                                        "Using {} clips from {} instead of silence detection",
+                                        labels.segments.len(),
+                                        labels
+                                            .path
+                                            .file_name()
+                                            .unwrap_or_default()
+                                            .to_string_lossy()
+                                    ));
+                                    if ui.button("Clear Labels").clicked() {
+                                        track.labels = None;
+                                    }
+                                }
+                                None => {
+                                    if ui
+                                        .button("Import Audacity Labels…")
+                                        .on_hover_text(
+                                            "Cut the clips at the labels instead of at silences",
+                                        )
+                                        .clicked()
+                                        && let Some(labels_path) = rfd::FileDialog::new()
+                                            .add_filter("Audacity labels", &["txt"])
+                                            .set_title(format!("Select Labels for {}", track.label))
+                                            .pick_file()
+                                    {
+                                        match labels::ImportedLabels::load(&labels_path) {
+                                            Ok(labels) => track.labels = Some(labels),
+                                            Err(e) => label_error = Some(e),
+                                        }
+                                    }
+                                }
+                            });
+
+                            if track.labels.is_none() {
+                                settings::render_override_controls(ui, &mut self.settings, path);
+                            }
+                        }
+                    });
                 });
 
-            ui.add_space(10.0);
+                ui.add_space(20.0);
+            }
 
-            ui.horizontal(|ui| {
-                ui.label("MP3 File 1:");
-                if let Some(path) = &self.mp3_file_1 {
-                    ui.label(format!("Selected: {}", path.display()));
-                } else {
-                    ui.label("No file selected");
-                }
-            });
+            if let Some(index) = to_remove {
+                self.tracks.remove(index);
+            }
 
-            ui.horizontal(|ui| {
-                if ui.button("Browse MP3 File 1").clicked() {
-                    if let Some(path) = rfd::FileDialog::new()
-                        .add_filter("MP3 Audio", &["mp3"])
-                        .set_title("Select MP3 File 1")
-                        .pick_file()
-                    {
-                        self.mp3_file_1 = Some(path.clone());
-                        if !self.file_history.contains(&path) {
-                            self.file_history.push(path);
-                        }
-                    }
-                }
+            if let Some(index) = import_into {
+                self.open_text_import(index);
+            }
+
+            if let Some(e) = label_error {
+                self.project.error = Some(e);
+            }
+
+            if ui.button("+ Add Track").clicked() {
+                self.tracks.push(Track::new(self.tracks.len()));
+            }
+
+            // Navigation to Text Analyzer
+            ui.separator();
+            if ui
+                .add_enabled(
+                    !self.analysis_data.is_processing,
+                    egui::Button::new("🔍 Analyze Text Content"),
+                )
+                .clicked()
+            {
+                self.current_screen = AppScreen::TextAnalyzer;
+                self.handle_analyze_text(ui.ctx());
+            }
 
-                if self.mp3_file_1.is_some() && ui.button("Clear MP3 File 1").clicked() {
-                    self.mp3_file_1 = None;
+            // Status section
+            ui.separator();
+            ui.label("Status:");
+            for track in &self.tracks {
+                ui.label(format!("{}: {} characters", track.label, track.text.len()));
+
+                if let Some(path) = &track.audio_file {
+                    ui.label(format!(
+                        "{} audio: {}",
+                        track.label,
+                        path.file_name().unwrap_or_default().to_string_lossy()
+                    ));
                 }
-            });
+            }
         });
+    }
 
-        ui.add_space(20.0);
-
-        // Second section: Text Area 2 and MP3 File 2
-        ui.group(|ui| {
-            ui.label("Text Area 2:");
-            egui::ScrollArea::vertical()
-                .id_source("1")
-                .max_height(150.0)
-                .show(ui, |ui| {
-                    ui.add(
-                        egui::TextEdit::multiline(&mut self.text_area_2)
-                            .desired_width(f32::INFINITY)
-                            .desired_rows(8)
-                            .hint_text("Enter your text here..."),
-                    );
-                });
+    /// Writes the settings if they changed since the last write
+    fn save_settings(&mut self) {
+        if self.settings == self.saved_settings {
+            return;
+        }
+        self.settings_error = self.settings.save().err();
+        self.saved_settings = self.settings.clone();
+    }
 
-            ui.add_space(10.0);
+    fn render_settings_screen(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Settings");
+        ui.separator();
+
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            ui.group(|ui| {
+                ui.label("Application Settings");
+                ui.add_space(10.0);
 
-            ui.horizontal(|ui| {
-                ui.label("MP3 File 2:");
-                if let Some(path) = &self.mp3_file_2 {
-                    ui.label(format!("Selected: {}", path.display()));
-                } else {
-                    ui.label("No file selected");
+                ui.horizontal(|ui| {
+                    ui.label("Window Title:");
+                    ui.text_edit_singleline(&mut self.settings.window_title);
+                });
+
+                ui.add_space(5.0);
+                ui.checkbox(
+                    &mut self.settings.auto_save,
+                    "Auto-save the project and reopen it on startup",
+                );
+                ui.checkbox(
+                    &mut self.settings.theme_dark,
+                    "Dark theme (not implemented)",
+                );
+
+                ui.add_space(10.0);
+
+                if ui.button("Reset to Defaults").clicked() {
+                    self.settings.window_title.clear();
+                    self.settings.auto_save = false;
+                    self.settings.theme_dark = false;
                 }
             });
 
-            ui.horizontal(|ui| {
-                if ui.button("Browse MP3 File 2").clicked() {
-                    if let Some(path) = rfd::FileDialog::new()
-                        .add_filter("MP3 Audio", &["mp3"])
-                        .set_title("Select MP3 File 2")
-                        .pick_file()
-                    {
-                        self.mp3_file_2 = Some(path.clone());
-                        if !self.file_history.contains(&path) {
-                            self.file_history.push(path);
-                        }
+            ui.add_space(20.0);
+
+            ui.group(|ui| {
+                ui.label("Segmentation");
+                ui.add_space(5.0);
+                ui.label("Used for every audio file without its own override.");
+                ui.add_space(5.0);
+
+                settings::render_segmentation_controls(ui, &mut self.settings.segmentation);
+
+                ui.add_space(5.0);
+                ui.horizontal(|ui| {
+                    if ui.button("Reset Segmentation").clicked() {
+                        self.settings.segmentation = Default::default();
                     }
-                }
 
-                if self.mp3_file_2.is_some() && ui.button("Clear MP3 File 2").clicked() {
-                    self.mp3_file_2 = None;
-                }
+                    let override_count = self.settings.segmentation_overrides.len();
+                    if override_count > 0
+                        && ui
+                            .button(format!("Clear {} Per-File Override(s)", override_count))
+                            .clicked()
+                    {
+                        self.settings.segmentation_overrides.clear();
+                    }
+                });
             });
-        });
 
-        ui.add_space(20.0);
+            ui.add_space(20.0);
 
-        // Navigation to Text Analyzer
-        ui.separator();
-        if ui.button("🔍 Analyze Text Content").clicked() {
-            self.current_screen = AppScreen::TextAnalyzer;
-            self.handle_analyze_text();
-        }
+            ui.group(|ui| {
+                ui.label("Lesson Gaps");
+                ui.add_space(5.0);
 
-        // Status section
-        ui.separator();
-        ui.label("Status:");
-        ui.label(format!(
-            "Text Area 1: {} characters",
-            self.text_area_1.len()
-        ));
-        ui.label(format!(
-            "Text Area 2: {} characters",
-            self.text_area_2.len()
-        ));
-
-        if let Some(path1) = &self.mp3_file_1 {
-            ui.label(format!(
-                "MP3 File 1: {}",
-                path1.file_name().unwrap_or_default().to_string_lossy()
-            ));
-        }
+                settings::render_gap_controls(ui, &mut self.settings.lesson.gaps);
 
-        if let Some(path2) = &self.mp3_file_2 {
-            ui.label(format!(
-                "MP3 File 2: {}",
-                path2.file_name().unwrap_or_default().to_string_lossy()
-            ));
-        }
-    }
+                ui.add_space(5.0);
+                if ui.button("Reset Gaps").clicked() {
+                    self.settings.lesson.gaps = Default::default();
+                }
+            });
 
-    fn render_settings_screen(&mut self, ui: &mut egui::Ui) {
-        ui.heading("Settings");
-        ui.separator();
+            ui.add_space(20.0);
 
-        ui.group(|ui| {
-            ui.label("Application Settings");
-            ui.add_space(10.0);
+            ui.group(|ui| {
+                ui.label("Lesson Pattern");
+                ui.add_space(5.0);
 
-            ui.horizontal(|ui| {
-                ui.label("Window Title:");
-                ui.text_edit_singleline(&mut self.window_title);
+                settings::render_interleave_controls(ui, &mut self.settings.lesson.interleave);
             });
 
-            ui.add_space(5.0);
-            ui.checkbox(&mut self.auto_save, "Auto-save text content");
-            ui.checkbox(&mut self.theme_dark, "Dark theme (not implemented)");
+            ui.add_space(20.0);
 
-            ui.add_space(10.0);
+            ui.group(|ui| {
+                ui.label("Loudness");
+                ui.add_space(5.0);
+                ui.label("Evens out the clips of different recordings before they are combined.");
+                ui.add_space(5.0);
 
-            if ui.button("Reset to Defaults").clicked() {
-                self.window_title.clear();
-                self.auto_save = false;
-                self.theme_dark = false;
-            }
-        });
+                loudness::render_normalization_controls(
+                    ui,
+                    &mut self.settings.lesson.normalization,
+                );
+            });
 
-        ui.add_space(20.0);
-
-        ui.group(|ui| {
-            ui.label("Statistics");
-            ui.add_space(5.0);
-            ui.label(format!(
-                "Total files in history: {}",
-                self.file_history.len()
-            ));
-            ui.label(format!(
-                "Current text length: {} + {} = {} characters",
-                self.text_area_1.len(),
-                self.text_area_2.len(),
-                self.text_area_1.len() + self.text_area_2.len()
-            ));
-
-            if ui.button("Clear All Data").clicked() {
-                self.text_area_1.clear();
-                self.text_area_2.clear();
-                self.mp3_file_1 = None;
-                self.mp3_file_2 = None;
-            }
+            ui.add_space(20.0);
+
+            ui.group(|ui| {
+                ui.label("Statistics");
+                ui.add_space(5.0);
+                ui.label(format!(
+                    "Total files in history: {}",
+                    self.file_history.len()
+                ));
+                let lengths: Vec<String> = self
+                    .tracks
+                    .iter()
+                    .map(|track| track.text.len().to_string())
+                    .collect();
+                ui.label(format!(
+                    "Current text length: {} = {} characters",
+                    lengths.join(" + "),
+                    self.tracks
+                        .iter()
+                        .map(|track| track.text.len())
+                        .sum::<usize>()
+                ));
+
+                if ui.button("Clear All Data").clicked() {
+                    for track in &mut self.tracks {
+                        track.text.clear();
+                        track.set_audio_file(None);
+                    }
+                }
+            });
         });
     }
 }
 
 impl eframe::App for MyApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.poll_analysis_job();
+        self.player.update();
+        self.handle_history_shortcuts(ctx);
+
         egui::CentralPanel::default().show(ctx, |ui| {
             self.render_navigation(ui);
             ui.add_space(10.0);
 
             match self.current_screen {
-                AppScreen::Main => self.render_main_screen(ui),
+                AppScreen::Main => {
+                    self.render_main_screen(ui);
+                    self.render_text_import(ctx);
+                }
                 AppScreen::Settings => self.render_settings_screen(ui),
                 AppScreen::FileManager => self.render_file_manager_screen(ui),
                 AppScreen::TextAnalyzer => self.render_text_analyzer_screen(ui),
             }
         });
+
+        self.history
+            .watch_tracks(&self.tracks, ctx.memory(|m| m.focus()));
+        self.render_history_window(ctx);
+
+        // Keep the playback position moving
+        if self.player.is_active() {
+            ctx.request_repaint();
+        }
+
+        self.autosave(ctx);
+
+        // Wait for the pointer so a dragged value is written once, not every frame
+        if !ctx.input(|i| i.pointer.any_down()) {
+            self.save_settings();
+        }
+    }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        if let Some(job) = &self.analysis_data.job {
+            job.cancel();
+        }
+        self.save_settings();
+        let _ = std::fs::remove_dir_all(analyze::scratch_dir());
     }
 }
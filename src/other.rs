@@ -9,38 +9,24 @@ impl MyApp {
             ui.label("Currently Selected Files");
             ui.add_space(5.0);
 
-            let mut clear_file_1 = false;
-            let mut clear_file_2 = false;
-
-            if let Some(path) = &self.mp3_file_1 {
-                ui.horizontal(|ui| {
-                    ui.label("MP3 File 1:");
-                    ui.label(path.display().to_string());
-                    if ui.button("Remove").clicked() {
-                        clear_file_1 = true;
-                    }
-                });
-            } else {
-                ui.label("MP3 File 1: Not selected");
-            }
-
-            if let Some(path) = &self.mp3_file_2 {
-                ui.horizontal(|ui| {
-                    ui.label("MP3 File 2:");
-                    ui.label(path.display().to_string());
-                    if ui.button("Remove").clicked() {
-                        clear_file_2 = true;
-                    }
-                });
-            } else {
-                ui.label("MP3 File 2: Not selected");
+            let mut to_clear = None;
+
+            for (i, track) in self.tracks.iter().enumerate() {
+                if let Some(path) = &track.audio_file {
+                    ui.horizontal(|ui| {
+                        ui.label(format!("{}:", track.label));
+                        ui.label(path.display().to_string());
+                        if ui.button("Remove").clicked() {
+                            to_clear = Some(i);
+                        }
+                    });
+                } else {
+                    ui.label(format!("{}: Not selected", track.label));
+                }
             }
 
-            if clear_file_1 {
-                self.mp3_file_1 = None;
-            }
-            if clear_file_2 {
-                self.mp3_file_2 = None;
+            if let Some(index) = to_clear {
+                self.tracks[index].set_audio_file(None);
             }
         });
 
@@ -54,8 +40,7 @@ impl MyApp {
                 ui.label("No files in history");
             } else {
                 let mut to_remove = None;
-                let mut select_file_1 = None;
-                let mut select_file_2 = None;
+                let mut select_for_track = None;
 
                 egui::ScrollArea::vertical()
                     .max_height(200.0)
@@ -64,12 +49,10 @@ impl MyApp {
                             ui.horizontal(|ui| {
                                 ui.label(format!("{}. {}", i + 1, path.display()));
 
-                                if ui.button("Select as File 1").clicked() {
-                                    select_file_1 = Some(path.clone());
-                                }
-
-                                if ui.button("Select as File 2").clicked() {
-                                    select_file_2 = Some(path.clone());
+                                for (track_index, track) in self.tracks.iter().enumerate() {
+                                    if ui.button(format!("Select for {}", track.label)).clicked() {
+                                        select_for_track = Some((track_index, path.clone()));
+                                    }
                                 }
 
                                 if ui.button("Remove").clicked() {
@@ -83,12 +66,8 @@ impl MyApp {
                     self.file_history.remove(index);
                 }
 
-                if let Some(path) = select_file_1 {
-                    self.mp3_file_1 = Some(path);
-                }
-
-                if let Some(path) = select_file_2 {
-                    self.mp3_file_2 = Some(path);
+                if let Some((track_index, path)) = select_for_track {
+                    self.tracks[track_index].set_audio_file(Some(path));
                 }
 
                 ui.add_space(10.0);
@@ -113,7 +92,25 @@ impl MyApp {
                 AppScreen::TextAnalyzer,
                 "🔍 Text Analyzer",
             );
+
+            ui.separator();
+            self.render_project_menu(ui);
+
+            ui.separator();
+            self.render_history_buttons(ui);
         });
+
+        for error in [&self.project.error, &self.settings_error]
+            .into_iter()
+            .flatten()
+        {
+            ui.horizontal(|ui| {
+                ui.colored_label(ui.visuals().error_fg_color, error.to_string());
+                if let Some(hint) = error.hint() {
+                    ui.weak(hint);
+                }
+            });
+        }
         ui.separator();
     }
 }
@@ -0,0 +1,310 @@
+use std::{sync::Arc, time::Instant};
+
+use rodio::{OutputStream, OutputStreamHandle, Sink, Source, buffer::SamplesBuffer};
+
+use crate::{
+    decode::DecodedAudio,
+    error::{AppError, Result},
+};
+
+/// What is playing, so the UI can mark it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaybackId {
+    /// One clip of an analyzed track
+    Clip { track: usize, index: usize },
+    /// A stretch of a track's source recording
+    Source { track: usize },
+    /// The combined lesson
+    Lesson,
+}
+
+/// The sound currently loaded into the player
+struct Playing {
+    id: PlaybackId,
+    audio: Arc<DecodedAudio>,
+    /// The range that is played (and looped), in seconds of `audio`
+    start: f64,
+    end: f64,
+    /// Where the current run started, after a seek or a resume
+    offset: f64,
+    /// Seconds of audio played since `offset` before the last pause or speed change
+    played: f64,
+    /// When playback last (re)started; `None` while paused
+    resumed: Option<Instant>,
+}
+
+/// The one audio output of the app. Starting a sound stops the previous one.
+pub struct Player {
+    output: Option<(OutputStream, OutputStreamHandle)>,
+    sink: Option<Sink>,
+    current: Option<Playing>,
+    /// 1.0 is normal speed; the pitch changes with it
+    pub speed: f32,
+    /// Repeat the range until stopped, e.g. to drill a sentence
+    pub looping: bool,
+}
+
+impl Default for Player {
+    fn default() -> Self {
+        Self {
+            output: None,
+            sink: None,
+            current: None,
+            speed: 1.0,
+            looping: false,
+        }
+    }
+}
+
+impl Player {
+    /// Plays `audio` from `start` to `end` seconds, replacing whatever was playing
+    pub fn play(
+        &mut self,
+        id: PlaybackId,
+        audio: Arc<DecodedAudio>,
+        start: f64,
+        end: f64,
+    ) -> Result<()> {
+        let end = end.min(audio.duration());
+        let start = start.clamp(0.0, end);
+        self.current = Some(Playing {
+            id,
+            audio,
+            start,
+            end,
+            offset: start,
+            played: 0.0,
+            resumed: None,
+        });
+        self.restart(start)
+    }
+
+    /// Starts the current sound again at `position`, with the current speed and looping
+    fn restart(&mut self, position: f64) -> Result<()> {
+        if let Some(sink) = self.sink.take() {
+            sink.stop();
+        }
+        if self.current.is_none() {
+            return Ok(());
+        }
+
+        // The output device is opened on first use, so the app starts without one
+        let (_, handle) = match &self.output {
+            Some(output) => output,
+            None => {
+                self.output
+                    .insert(OutputStream::try_default().map_err(|e| AppError::Playback {
+                        reason: e.to_string(),
+                    })?)
+            }
+        };
+        let sink = Sink::try_new(handle).map_err(|e| AppError::Playback {
+            reason: e.to_string(),
+        })?;
+
+        let Some(playing) = &mut self.current else {
+            return Ok(());
+        };
+        let position = position.clamp(playing.start, playing.end);
+        sink.append(samples(&playing.audio, position, playing.end));
+        if self.looping {
+            sink.append(samples(&playing.audio, playing.start, playing.end).repeat_infinite());
+        }
+        sink.set_speed(self.speed);
+
+        playing.offset = position;
+        playing.played = 0.0;
+        playing.resumed = Some(Instant::now());
+        self.sink = Some(sink);
+        Ok(())
+    }
+
+    pub fn stop(&mut self) {
+        if let Some(sink) = self.sink.take() {
+            sink.stop();
+        }
+        self.current = None;
+    }
+
+    pub fn toggle_pause(&mut self) {
+        let (Some(sink), Some(playing)) = (&self.sink, &mut self.current) else {
+            return;
+        };
+        match playing.resumed.take() {
+            Some(resumed) => {
+                playing.played += resumed.elapsed().as_secs_f64() * self.speed as f64;
+                sink.pause();
+            }
+            None => {
+                playing.resumed = Some(Instant::now());
+                sink.play();
+            }
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.current
+            .as_ref()
+            .is_some_and(|playing| playing.resumed.is_none())
+    }
+
+    /// Jumps to `position` seconds within the loaded sound
+    pub fn seek(&mut self, position: f64) -> Result<()> {
+        let paused = self.is_paused();
+        self.restart(position)?;
+        if paused {
+            self.toggle_pause();
+        }
+        Ok(())
+    }
+
+    pub fn set_speed(&mut self, speed: f32) {
+        if let Some(playing) = &mut self.current
+            && let Some(resumed) = &mut playing.resumed
+        {
+            playing.played += resumed.elapsed().as_secs_f64() * self.speed as f64;
+            *resumed = Instant::now();
+        }
+        self.speed = speed;
+        if let Some(sink) = &self.sink {
+            sink.set_speed(speed);
+        }
+    }
+
+    /// Turns looping on or off for the sound that is playing, too
+    pub fn set_looping(&mut self, looping: bool) -> Result<()> {
+        if looping == self.looping {
+            return Ok(());
+        }
+        self.looping = looping;
+        match self.position() {
+            Some(position) => self.seek(position),
+            None => Ok(()),
+        }
+    }
+
+    /// Forgets the sound once it has played to the end. Call once per frame.
+    pub fn update(&mut self) {
+        if self.sink.as_ref().is_some_and(Sink::empty) {
+            self.sink = None;
+            self.current = None;
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.current.is_some()
+    }
+
+    pub fn current_id(&self) -> Option<PlaybackId> {
+        self.current.as_ref().map(|playing| playing.id)
+    }
+
+    /// The loaded sound, if it is `audio`
+    pub fn is_playing_from(&self, audio: &Arc<DecodedAudio>) -> bool {
+        self.current
+            .as_ref()
+            .is_some_and(|playing| Arc::ptr_eq(&playing.audio, audio))
+    }
+
+    /// The range being played, in seconds
+    pub fn range(&self) -> Option<(f64, f64)> {
+        self.current
+            .as_ref()
+            .map(|playing| (playing.start, playing.end))
+    }
+
+    /// Where playback is, in seconds of the loaded sound
+    pub fn position(&self) -> Option<f64> {
+        let playing = self.current.as_ref()?;
+        let elapsed = playing.played
+            + playing.resumed.map_or(0.0, |resumed| {
+                resumed.elapsed().as_secs_f64() * self.speed as f64
+            });
+
+        let position = playing.offset + elapsed;
+        let length = playing.end - playing.start;
+        Some(if position <= playing.end || length <= 0.0 {
+            position.min(playing.end)
+        } else if self.looping {
+            playing.start + (position - playing.end) % length
+        } else {
+            playing.end
+        })
+    }
+}
+
+/// The frames of `audio` between two times, ready for rodio
+fn samples(audio: &DecodedAudio, start: f64, end: f64) -> SamplesBuffer<i16> {
+    let channels = audio.channels.max(1) as usize;
+    let rate = audio.sample_rate as f64;
+    let first = ((start * rate) as usize).min(audio.frame_count());
+    let last = ((end * rate) as usize).clamp(first, audio.frame_count());
+    SamplesBuffer::new(
+        audio.channels,
+        audio.sample_rate,
+        audio.samples[first * channels..last * channels].to_vec(),
+    )
+}
+
+/// Pause, stop, position and seek bar, loop switch and speed of the player
+pub fn render_transport(ui: &mut egui::Ui, player: &mut Player) -> Result<()> {
+    let mut result = Ok(());
+
+    ui.horizontal(|ui| {
+        let active = player.is_active();
+        let pause_label = if player.is_paused() { "▶" } else { "⏸" };
+        if ui
+            .add_enabled(active, egui::Button::new(pause_label))
+            .on_hover_text("Pause or resume")
+            .clicked()
+        {
+            player.toggle_pause();
+        }
+        if ui
+            .add_enabled(active, egui::Button::new("⏹"))
+            .on_hover_text("Stop")
+            .clicked()
+        {
+            player.stop();
+        }
+
+        if let (Some(mut position), Some((start, end))) = (player.position(), player.range()) {
+            let slider = ui.add(
+                egui::Slider::new(&mut position, start..=end)
+                    .show_value(false)
+                    .trailing_fill(true),
+            );
+            if slider.changed() {
+                result = player.seek(position);
+            }
+            ui.monospace(format!("{:.2}s / {:.2}s", position - start, end - start));
+        } else {
+            ui.weak("Nothing playing");
+        }
+
+        ui.separator();
+        let mut looping = player.looping;
+        if ui
+            .checkbox(&mut looping, "Loop")
+            .on_hover_text("Repeat the clip until stopped")
+            .changed()
+        {
+            result = player.set_looping(looping);
+        }
+
+        let mut speed = player.speed;
+        if ui
+            .add(
+                egui::Slider::new(&mut speed, 0.5..=1.5)
+                    .step_by(0.05)
+                    .suffix("×"),
+            )
+            .on_hover_text("Playback speed; the pitch changes with it")
+            .changed()
+        {
+            player.set_speed(speed);
+        }
+    });
+
+    result
+}
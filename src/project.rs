@@ -0,0 +1,505 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    MyApp, Track, TrackAnalysis,
+    combine::LessonSettings,
+    error::{AppError, Result},
+    export::{ExportSettings, with_extension},
+    job::{TrackAudio, TrackInput},
+    labels::ImportedLabels,
+    sentences::{SentenceSplit, split_sentences},
+    settings,
+    silence::{Segment, SilenceParams},
+};
+
+/// File extension used for project files
+pub const PROJECT_EXTENSION: &str = "mpproj";
+
+/// Version written into new project files. Bump it when the format changes
+/// in a way older versions cannot read.
+const PROJECT_VERSION: u32 = 2;
+
+/// How often the session is written while auto-save is on
+const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A whole session: the tracks, their analysis and the settings that shape the lesson
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Project {
+    pub version: u32,
+    pub lesson_name: String,
+    pub tracks: Vec<ProjectTrack>,
+    pub segmentation: SilenceParams,
+    pub segmentation_overrides: HashMap<PathBuf, SilenceParams>,
+    pub lesson: LessonSettings,
+    pub sentence_split: SentenceSplit,
+    pub file_history: Vec<PathBuf>,
+    /// Naming, format, stops, subtitles and Anki. Added in version 2.
+    pub export: ExportSettings,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProjectTrack {
+    pub label: String,
+    pub text: String,
+    pub audio_file: Option<PathBuf>,
+    /// Imported clip boundaries that replace silence detection
+    pub labels: Option<ImportedLabels>,
+    /// Missing if the track was never analyzed
+    pub analysis: Option<ProjectAnalysis>,
+}
+
+/// The analyzed state of a track, including any manual clip edits
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProjectAnalysis {
+    pub entries: Vec<String>,
+    /// Clip boundaries in the source audio, after merges, splits and drops
+    pub segments: Vec<Segment>,
+}
+
+impl Project {
+    pub fn load(path: &Path) -> Result<Self> {
+        let json = fs::read_to_string(path)
+            .map_err(|e| AppError::io(format!("Failed to read {}", path.display()), e))?;
+        let project: Project = serde_json::from_str(&json)
+            .map_err(|e| AppError::json(format!("Failed to parse {}", path.display()), e))?;
+
+        if project.version > PROJECT_VERSION {
+            return Err(AppError::UnsupportedProject {
+                path: path.to_path_buf(),
+                version: project.version,
+            });
+        }
+        Ok(project)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .map_err(|e| AppError::io(format!("Failed to create {}", dir.display()), e))?;
+        }
+
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| AppError::json("Failed to serialize project", e))?;
+        fs::write(path, json)
+            .map_err(|e| AppError::io(format!("Failed to write {}", path.display()), e))
+    }
+}
+
+/// Where the open project lives and what has been written so far
+#[derive(Default)]
+pub struct ProjectState {
+    /// `None` until the project has been saved under a name
+    pub path: Option<PathBuf>,
+    /// The project as it was last opened or saved by the user
+    saved: Project,
+    /// The project as it was last written by auto-save
+    autosaved: Project,
+    last_autosave: Option<Instant>,
+    pub error: Option<AppError>,
+    /// Waiting for the user to confirm that unsaved changes may be discarded
+    pending_change: Option<ProjectChange>,
+}
+
+/// Replacing the open project with another one
+#[derive(Debug, Clone)]
+enum ProjectChange {
+    New,
+    /// Ask for the project file first
+    OpenDialog,
+    Open(PathBuf),
+}
+
+/// Where an untitled session is auto-saved
+fn autosave_path() -> Option<PathBuf> {
+    settings::config_dir().map(|dir| dir.join(format!("autosave.{}", PROJECT_EXTENSION)))
+}
+
+/// The session now has a name, so it must not be restored as untitled on startup
+fn discard_untitled_autosave() {
+    if let Some(path) = autosave_path() {
+        let _ = fs::remove_file(path);
+    }
+}
+
+impl MyApp {
+    /// Captures the current session
+    pub fn to_project(&self) -> Project {
+        let tracks = self
+            .tracks
+            .iter()
+            .enumerate()
+            .map(|(i, track)| ProjectTrack {
+                label: track.label.clone(),
+                text: track.text.clone(),
+                audio_file: track.audio_file.clone(),
+                labels: track.labels.clone(),
+                analysis: self
+                    .analysis_data
+                    .tracks
+                    .get(i)
+                    .filter(|analysis| analysis.label == track.label)
+                    .map(|analysis| ProjectAnalysis {
+                        entries: analysis.text_entries.clone(),
+                        segments: analysis.segments.clone(),
+                    }),
+            })
+            .collect();
+
+        Project {
+            version: PROJECT_VERSION,
+            lesson_name: self.lesson_name.clone(),
+            tracks,
+            segmentation: self.settings.segmentation.clone(),
+            segmentation_overrides: self.settings.segmentation_overrides.clone(),
+            lesson: self.settings.lesson.clone(),
+            sentence_split: self.settings.sentence_split.clone(),
+            file_history: self.file_history.clone(),
+            export: self.settings.export.clone(),
+        }
+    }
+
+    /// Replaces the session with the project. Saved clip boundaries are cut
+    /// again from the source audio in the background.
+    fn apply_project(&mut self, project: Project, ctx: &egui::Context) {
+        if let Some(job) = &self.analysis_data.job {
+            job.cancel();
+        }
+
+        self.tracks = project
+            .tracks
+            .iter()
+            .map(|track| Track {
+                label: track.label.clone(),
+                text: track.text.clone(),
+                audio_file: track.audio_file.clone(),
+                labels: track.labels.clone(),
+            })
+            .collect();
+        if self.tracks.is_empty() {
+            self.tracks = vec![Track::new(0), Track::new(1)];
+        }
+
+        self.settings.segmentation = project.segmentation;
+        self.settings.segmentation_overrides = project.segmentation_overrides;
+        self.settings.lesson = project.lesson;
+        self.settings.sentence_split = project.sentence_split;
+        // Older projects did not save them, so the current ones stay
+        if project.version >= 2 {
+            self.settings.export = project.export;
+        }
+        self.file_history = project.file_history;
+        self.lesson_name = project.lesson_name;
+        self.reset_analysis();
+        self.player.stop();
+        self.history.reset(&self.tracks);
+
+        if project.tracks.iter().any(|track| track.analysis.is_some()) {
+            self.analysis_data.tracks = project
+                .tracks
+                .iter()
+                .zip(&self.tracks)
+                .map(|(track, session_track)| TrackAnalysis {
+                    label: track.label.clone(),
+                    text_entries: match &track.analysis {
+                        Some(analysis) => analysis.entries.clone(),
+                        None => split_sentences(&track.text, &self.settings.sentence_split),
+                    },
+                    source_path: track.audio_file.clone(),
+                    segmentation: self.detection_params(session_track),
+                    ..Default::default()
+                })
+                .collect();
+
+            let input = self.job_input(
+                project
+                    .tracks
+                    .into_iter()
+                    .zip(&self.analysis_data.tracks)
+                    .map(|(track, analyzed)| TrackInput {
+                        label: track.label,
+                        entries: analyzed.text_entries.clone(),
+                        audio: track
+                            .audio_file
+                            .map(|file| match (track.analysis, track.labels) {
+                                (Some(analysis), _) if !analysis.segments.is_empty() => {
+                                    TrackAudio::Segments {
+                                        file,
+                                        segments: analysis.segments,
+                                        params: analyzed.segmentation.clone(),
+                                    }
+                                }
+                                (_, Some(labels)) => TrackAudio::Segments {
+                                    file,
+                                    segments: labels.segments,
+                                    params: None,
+                                },
+                                _ => TrackAudio::Detect {
+                                    params: self.settings.segmentation_for(&file).clone(),
+                                    file,
+                                },
+                            }),
+                    })
+                    .collect(),
+            );
+            self.start_job(input, ctx);
+        }
+
+        self.project.saved = self.to_project();
+        self.project.autosaved = self.project.saved.clone();
+        self.project.error = None;
+    }
+
+    /// Starts an empty, untitled session
+    pub fn new_project(&mut self) {
+        if let Some(job) = &self.analysis_data.job {
+            job.cancel();
+        }
+
+        self.tracks = vec![Track::new(0), Track::new(1)];
+        self.lesson_name.clear();
+        self.reset_analysis();
+        self.player.stop();
+        self.history.reset(&self.tracks);
+        self.project.path = None;
+        self.project.saved = self.to_project();
+        self.project.autosaved = self.project.saved.clone();
+        self.project.error = None;
+    }
+
+    pub fn open_project(&mut self, path: &Path, ctx: &egui::Context) {
+        match Project::load(path) {
+            Ok(project) => {
+                self.apply_project(project, ctx);
+                self.project.path = Some(path.to_path_buf());
+                discard_untitled_autosave();
+                self.settings.add_recent_project(path);
+            }
+            Err(e) => self.project.error = Some(e),
+        }
+    }
+
+    pub fn open_project_dialog(&mut self, ctx: &egui::Context) {
+        if let Some(path) = rfd::FileDialog::new()
+            .add_filter("Project", &[PROJECT_EXTENSION])
+            .set_title("Open Project")
+            .pick_file()
+        {
+            self.open_project(&path, ctx);
+        }
+    }
+
+    /// Saves to the current project file, asking for one if there is none yet
+    pub fn save_project(&mut self) {
+        match self.project.path.clone() {
+            Some(path) => self.save_project_to(&path),
+            None => self.save_project_as(),
+        }
+    }
+
+    pub fn save_project_as(&mut self) {
+        if let Some(path) = rfd::FileDialog::new()
+            .add_filter("Project", &[PROJECT_EXTENSION])
+            .set_title("Save Project As")
+            .set_file_name(format!("lesson.{}", PROJECT_EXTENSION))
+            .save_file()
+        {
+            self.save_project_to(&with_extension(&path, PROJECT_EXTENSION));
+        }
+    }
+
+    fn save_project_to(&mut self, path: &Path) {
+        let project = self.to_project();
+        if let Err(e) = project.save(path) {
+            self.project.error = Some(e);
+            return;
+        }
+
+        if self.project.path.is_none() {
+            discard_untitled_autosave();
+        }
+
+        self.project.path = Some(path.to_path_buf());
+        self.project.saved = project.clone();
+        self.project.autosaved = project;
+        self.project.error = None;
+        self.settings.add_recent_project(path);
+    }
+
+    /// Whether the session differs from the project file
+    pub fn has_unsaved_changes(&self) -> bool {
+        self.to_project() != self.project.saved
+    }
+
+    /// Reopens the last session: the untitled autosave if there is one,
+    /// otherwise the most recent project
+    pub fn restore_last_session(&mut self, ctx: &egui::Context) {
+        if let Some(path) = autosave_path().filter(|path| path.exists()) {
+            match Project::load(&path) {
+                Ok(project) => self.apply_project(project, ctx),
+                Err(e) => self.project.error = Some(e),
+            }
+        } else if let Some(path) = self.settings.recent_projects.first().cloned()
+            && path.exists()
+        {
+            self.open_project(&path, ctx);
+        }
+    }
+
+    /// Writes the session every few seconds while auto-save is on and
+    /// something has changed since the last write
+    pub fn autosave(&mut self, ctx: &egui::Context) {
+        if !self.settings.auto_save || self.analysis_data.is_processing {
+            return;
+        }
+
+        if let Some(last) = self.project.last_autosave
+            && last.elapsed() < AUTOSAVE_INTERVAL
+        {
+            ctx.request_repaint_after(AUTOSAVE_INTERVAL - last.elapsed());
+            return;
+        }
+        self.project.last_autosave = Some(Instant::now());
+
+        let project = self.to_project();
+        if project == self.project.autosaved {
+            return;
+        }
+
+        let Some(path) = self.project.path.clone().or_else(autosave_path) else {
+            return;
+        };
+        match project.save(&path) {
+            Ok(()) => {
+                if self.project.path.is_some() {
+                    self.project.saved = project.clone();
+                }
+                self.project.autosaved = project;
+            }
+            Err(e) => self.project.error = Some(e),
+        }
+    }
+
+    /// Replaces the project, after asking whether unsaved changes may be discarded
+    fn change_project(&mut self, change: ProjectChange, ctx: &egui::Context) {
+        if self.has_unsaved_changes() {
+            self.project.pending_change = Some(change);
+        } else {
+            self.apply_change(change, ctx);
+        }
+    }
+
+    fn apply_change(&mut self, change: ProjectChange, ctx: &egui::Context) {
+        match change {
+            ProjectChange::New => self.new_project(),
+            ProjectChange::OpenDialog => self.open_project_dialog(ctx),
+            ProjectChange::Open(path) => self.open_project(&path, ctx),
+        }
+    }
+
+    /// Asks what to do with unsaved changes before the project is replaced
+    fn render_discard_dialog(&mut self, ctx: &egui::Context) {
+        let Some(change) = self.project.pending_change.clone() else {
+            return;
+        };
+
+        let mut choice = None;
+        egui::Window::new("Unsaved Changes")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+            .show(ctx, |ui| {
+                ui.label("The project has unsaved changes. Discard them?");
+                ui.add_space(5.0);
+                ui.horizontal(|ui| {
+                    if ui.button("💾 Save").clicked() {
+                        choice = Some(true);
+                    }
+                    if ui.button("Discard").clicked() {
+                        choice = Some(false);
+                    }
+                    if ui.button("Cancel").clicked() {
+                        self.project.pending_change = None;
+                    }
+                });
+            });
+
+        let Some(save) = choice else {
+            return;
+        };
+        if save {
+            self.save_project();
+            // The save dialog was cancelled or saving failed
+            if self.has_unsaved_changes() {
+                return;
+            }
+        }
+        self.project.pending_change = None;
+        self.apply_change(change, ctx);
+    }
+
+    /// The Project menu and the name of the open project
+    pub fn render_project_menu(&mut self, ui: &mut egui::Ui) {
+        ui.menu_button("📂 Project", |ui| {
+            if ui.button("New").clicked() {
+                self.change_project(ProjectChange::New, ui.ctx());
+                ui.close_menu();
+            }
+            if ui.button("Open…").clicked() {
+                self.change_project(ProjectChange::OpenDialog, ui.ctx());
+                ui.close_menu();
+            }
+            if ui.button("Save").clicked() {
+                self.save_project();
+                ui.close_menu();
+            }
+            if ui.button("Save As…").clicked() {
+                self.save_project_as();
+                ui.close_menu();
+            }
+
+            ui.separator();
+            ui.menu_button("Recent", |ui| {
+                if self.settings.recent_projects.is_empty() {
+                    ui.label("No recent projects");
+                }
+
+                let mut to_open = None;
+                for path in &self.settings.recent_projects {
+                    if ui.button(path.display().to_string()).clicked() {
+                        to_open = Some(path.clone());
+                    }
+                }
+
+                if let Some(path) = to_open {
+                    self.change_project(ProjectChange::Open(path), ui.ctx());
+                    ui.close_menu();
+                }
+            });
+        });
+        self.render_discard_dialog(ui.ctx());
+
+        let name = self
+            .project
+            .path
+            .as_ref()
+            .and_then(|path| path.file_stem())
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "Untitled".to_string());
+        if self.has_unsaved_changes() {
+            ui.weak(format!("{} •", name))
+                .on_hover_text("Unsaved changes");
+        } else {
+            ui.weak(name);
+        }
+    }
+}
@@ -0,0 +1,359 @@
+use serde::{Deserialize, Serialize};
+
+/// How a track's text is cut into the entries that are matched to clips
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum SplitMode {
+    /// Every non-blank line is an entry
+    #[default]
+    Line,
+    /// Entries end at sentence punctuation, wherever the line breaks are
+    Sentence,
+    /// Entries are separated by blank lines; the lines in between are joined
+    Paragraph,
+    /// Entries are separated by a custom string
+    Delimiter,
+}
+
+impl SplitMode {
+    pub const ALL: [SplitMode; 4] = [
+        SplitMode::Line,
+        SplitMode::Sentence,
+        SplitMode::Paragraph,
+        SplitMode::Delimiter,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            SplitMode::Line => "One per line",
+            SplitMode::Sentence => "One per sentence",
+            SplitMode::Paragraph => "One per paragraph",
+            SplitMode::Delimiter => "Custom delimiter",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SentenceSplit {
+    pub mode: SplitMode,
+    /// Used by `SplitMode::Delimiter`; `\n` and `\t` stand for a line break and a tab
+    pub delimiter: String,
+}
+
+impl Default for SentenceSplit {
+    fn default() -> Self {
+        Self {
+            mode: SplitMode::Line,
+            delimiter: "|".to_string(),
+        }
+    }
+}
+
+/// Words that end in a full stop without ending the sentence, lowercase and
+/// without the stop. Maltese first, then English.
+const ABBREVIATIONS: &[&str] = &[
+    // Titles
+    "sur", "sra", "sinj", "dr", "prof", "onor", "mons", "fr", "kan", "av", "ing", "lt", "col",
+    "gen", "sant", "mr", "mrs", "ms", "st", "jr", "sr", "rev", "hon", // Months
+    "jan", "fra", "apr", "ġun", "lul", "aww", "ott", "diċ", "feb", "jun", "jul", "aug", "sep",
+    "sept", "oct", "nov", "dec", // Everything else
+    "eċċ", "ecc", "etc", "vs", "approx", "eż", "ez", "pereż", "cf", "ibid",
+];
+
+/// Abbreviations that only come before a number, e.g. "Nru. 5" or "No. 5"
+const NUMBER_ABBREVIATIONS: &[&str] = &[
+    "nru", "nri", "no", "nos", "art", "pġ", "paġ", "p", "pp", "fig", "vol", "kap", "ch", "sec",
+];
+
+/// Cuts `text` into entries the way `settings` says. Windows and old Mac
+/// line endings are accepted, runs of whitespace become single spaces and
+/// empty entries are dropped.
+pub fn split_sentences(text: &str, settings: &SentenceSplit) -> Vec<String> {
+    let text = text.replace("\r\n", "\n").replace('\r', "\n");
+
+    let pieces: Vec<String> = match settings.mode {
+        SplitMode::Line => text.lines().map(str::to_string).collect(),
+        SplitMode::Paragraph => paragraphs(&text),
+        SplitMode::Sentence => paragraphs(&text)
+            .iter()
+            .flat_map(|paragraph| sentences(paragraph))
+            .collect(),
+        SplitMode::Delimiter => {
+            let delimiter = settings.delimiter.replace("\\n", "\n").replace("\\t", "\t");
+            if delimiter.is_empty() {
+                vec![text]
+            } else {
+                text.split(delimiter.as_str()).map(str::to_string).collect()
+            }
+        }
+    };
+
+    pieces
+        .iter()
+        .map(|piece| normalize_whitespace(piece))
+        .filter(|piece| !piece.is_empty())
+        .collect()
+}
+
+fn normalize_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Blocks of text between blank lines, with their lines joined by spaces
+fn paragraphs(text: &str) -> Vec<String> {
+    let mut paragraphs = Vec::new();
+    let mut current: Vec<&str> = Vec::new();
+    for line in text.lines().chain([""]) {
+        if line.trim().is_empty() {
+            if !current.is_empty() {
+                paragraphs.push(current.join(" "));
+                current.clear();
+            }
+        } else {
+            current.push(line.trim());
+        }
+    }
+    paragraphs
+}
+
+fn is_terminator(c: char) -> bool {
+    matches!(c, '.' | '!' | '?' | '…')
+}
+
+/// Quotes and brackets that may follow the punctuation of a sentence
+fn is_closing(c: char) -> bool {
+    matches!(c, '"' | '\'' | '”' | '’' | '»' | ')' | ']')
+}
+
+/// Splits a paragraph after `.`, `!`, `?` and `…` when the next word starts
+/// a sentence and the stop does not belong to an abbreviation or initial
+fn sentences(paragraph: &str) -> Vec<String> {
+    let chars: Vec<char> = paragraph.chars().collect();
+    let mut sentences = Vec::new();
+    let mut start = 0;
+    let mut i = 0;
+
+    while i < chars.len() {
+        if !is_terminator(chars[i]) {
+            i += 1;
+            continue;
+        }
+
+        // Take the whole run of punctuation and closing quotes: "?!", "...", '."'
+        let stop = i;
+        let mut end = i + 1;
+        while end < chars.len() && (is_terminator(chars[end]) || is_closing(chars[end])) {
+            end += 1;
+        }
+        i = end;
+
+        // Only a space can separate two sentences
+        if end < chars.len() && !chars[end].is_whitespace() {
+            continue;
+        }
+        let next = chars[end..].iter().copied().find(|c| !c.is_whitespace());
+        let word: String = chars[start..stop]
+            .iter()
+            .rev()
+            .take_while(|c| !c.is_whitespace())
+            .collect::<Vec<_>>()
+            .into_iter()
+            .rev()
+            .collect();
+
+        if next.is_none_or(|next| starts_sentence(&word, chars[stop], next)) {
+            sentences.push(chars[start..end].iter().collect());
+            start = end;
+        }
+    }
+
+    if start < chars.len() {
+        sentences.push(chars[start..].iter().collect());
+    }
+    sentences
+}
+
+/// Whether the text after `word` + `stop` begins a new sentence starting with `next`
+fn starts_sentence(word: &str, stop: char, next: char) -> bool {
+    // A capital, a number, or an opening quote or bracket. Maltese words may
+    // also start with an apostrophe, as in 'il-għada or 'l hemm.
+    let opens_sentence = next.is_uppercase()
+        || next.is_ascii_digit()
+        || matches!(
+            next,
+            '"' | '“' | '«' | '(' | '[' | '\'' | '‘' | '-' | '–' | '—'
+        );
+    if !opens_sentence {
+        return false;
+    }
+    if stop != '.' {
+        return true;
+    }
+
+    let word = word.trim_start_matches(|c: char| !c.is_alphanumeric());
+    let lower = word.to_lowercase();
+    let letters = word.chars().filter(|c| c.is_alphabetic()).count();
+
+    // Initials such as "J. Borg" (but not the pronoun "I"), and dotted
+    // abbreviations such as "e.g." or "p.eż."
+    let initial = letters == 1 && word.chars().all(char::is_alphabetic) && word != "I";
+    if initial || word.contains('.') {
+        return false;
+    }
+    // The Maltese article is joined with a hyphen: is-Sur., id-Dr., in-Nru.
+    let lower = lower.rsplit('-').next().unwrap_or_default();
+    if ABBREVIATIONS.contains(&lower) {
+        return false;
+    }
+    !(next.is_ascii_digit() && NUMBER_ABBREVIATIONS.contains(&lower))
+}
+
+/// Split mode and delimiter
+pub fn render_split_controls(ui: &mut egui::Ui, settings: &mut SentenceSplit) {
+    ui.horizontal(|ui| {
+        ui.label("Split text:");
+        egui::ComboBox::from_id_source("split_mode")
+            .selected_text(settings.mode.label())
+            .show_ui(ui, |ui| {
+                for mode in SplitMode::ALL {
+                    ui.selectable_value(&mut settings.mode, mode, mode.label());
+                }
+            });
+
+        if settings.mode == SplitMode::Delimiter {
+            ui.add(egui::TextEdit::singleline(&mut settings.delimiter).desired_width(60.0))
+                .on_hover_text("Use \\n for a line break and \\t for a tab");
+        }
+    });
+}
+
+/// Numbered list of the entries the text will be split into
+pub fn render_split_preview(ui: &mut egui::Ui, text: &str, settings: &SentenceSplit) {
+    let entries = split_sentences(text, settings);
+    egui::CollapsingHeader::new(format!("{} entries", entries.len()))
+        .id_source("split_preview")
+        .show(ui, |ui| {
+            egui::ScrollArea::vertical()
+                .max_height(150.0)
+                .show(ui, |ui| {
+                    egui::Grid::new("split_preview_grid")
+                        .num_columns(2)
+                        .striped(true)
+                        .show(ui, |ui| {
+                            for (i, entry) in entries.iter().enumerate() {
+                                ui.weak(format!("{}", i + 1));
+                                ui.label(entry);
+                                ui.end_row();
+                            }
+                        });
+                });
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(text: &str, mode: SplitMode) -> Vec<String> {
+        split_sentences(
+            text,
+            &SentenceSplit {
+                mode,
+                ..SentenceSplit::default()
+            },
+        )
+    }
+
+    #[test]
+    fn line_mode_keeps_every_non_blank_line() {
+        assert_eq!(
+            split(
+                "Bonġu.\r\n\r\n  Kif  int?\rTajjeb, grazzi.\n",
+                SplitMode::Line
+            ),
+            ["Bonġu.", "Kif int?", "Tajjeb, grazzi."]
+        );
+    }
+
+    #[test]
+    fn paragraph_mode_joins_lines_between_blank_lines() {
+        assert_eq!(
+            split(
+                "L-ewwel linja\nit-tieni linja\n\n \n\nParagrafu ieħor",
+                SplitMode::Paragraph
+            ),
+            ["L-ewwel linja it-tieni linja", "Paragrafu ieħor"]
+        );
+    }
+
+    #[test]
+    fn delimiter_mode_splits_on_the_delimiter() {
+        let settings = |delimiter: &str| SentenceSplit {
+            mode: SplitMode::Delimiter,
+            delimiter: delimiter.to_string(),
+        };
+        assert_eq!(
+            split_sentences("Iva | Le||Forsi", &settings("|")),
+            ["Iva", "Le", "Forsi"]
+        );
+        assert_eq!(
+            split_sentences("Iva\tLe\nForsi", &settings("\\t")),
+            ["Iva", "Le Forsi"]
+        );
+        assert_eq!(split_sentences(" Iva\nLe ", &settings("")), ["Iva Le"]);
+    }
+
+    #[test]
+    fn sentence_mode_splits_at_punctuation_across_lines() {
+        assert_eq!(
+            split(
+                "Din hija\nsentenza waħda. U din oħra!\nKif int? \"Tajjeb.\" (Grazzi.)",
+                SplitMode::Sentence
+            ),
+            [
+                "Din hija sentenza waħda.",
+                "U din oħra!",
+                "Kif int?",
+                "\"Tajjeb.\"",
+                "(Grazzi.)"
+            ]
+        );
+    }
+
+    #[test]
+    fn abbreviations_do_not_end_sentences() {
+        assert_eq!(
+            split(
+                "Id-Dr. Borg wasal. Ġibt il-frott, eż. Tuffieħ. Il-knisja ta' Sant. Ġorġ hija qadima. \
+                 Ara n-Nru. 5 u J. Borg, e.g. Dan. Mr. Smith left.",
+                SplitMode::Sentence
+            ),
+            [
+                "Id-Dr. Borg wasal.",
+                "Ġibt il-frott, eż. Tuffieħ.",
+                "Il-knisja ta' Sant. Ġorġ hija qadima.",
+                "Ara n-Nru. 5 u J. Borg, e.g. Dan.",
+                "Mr. Smith left."
+            ]
+        );
+    }
+
+    #[test]
+    fn decimals_and_ellipses() {
+        assert_eq!(
+            split(
+                "Jiswa 3.50 ewro. Stenna... Le! Hekk... u mbagħad? Forsi… Iva. Kap. 12 hu twil.",
+                SplitMode::Sentence
+            ),
+            [
+                "Jiswa 3.50 ewro.",
+                "Stenna...",
+                "Le!",
+                "Hekk... u mbagħad?",
+                "Forsi…",
+                "Iva.",
+                "Kap. 12 hu twil."
+            ]
+        );
+    }
+}
@@ -0,0 +1,245 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    combine::{GapSettings, Interleave, InterleavePattern, LessonSettings},
+    error::{AppError, Result},
+    export::ExportSettings,
+    sentences::SentenceSplit,
+    silence::{LevelMeasure, SilenceParams},
+};
+
+/// How many projects the Recent menu remembers
+const MAX_RECENT_PROJECTS: usize = 10;
+
+/// Everything from the Settings screen that survives a restart
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub window_title: String,
+    pub auto_save: bool,
+    pub theme_dark: bool,
+
+    /// Segmentation used for every file without an override
+    pub segmentation: SilenceParams,
+    /// Per-file segmentation, keyed by the source audio path
+    pub segmentation_overrides: HashMap<PathBuf, SilenceParams>,
+
+    /// How the track text is cut into sentences
+    pub sentence_split: SentenceSplit,
+
+    /// Gaps and clip order used when the clips are combined into a lesson
+    pub lesson: LessonSettings,
+
+    /// Naming and location of exported lessons
+    pub export: ExportSettings,
+
+    /// Most recently opened or saved project files, newest first
+    pub recent_projects: Vec<PathBuf>,
+}
+
+impl Settings {
+    /// Loads the saved settings, falling back to defaults if there are none
+    pub fn load() -> Self {
+        settings_path()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = settings_path().ok_or_else(|| {
+            AppError::io(
+                "Could not determine the settings directory",
+                io::Error::from(io::ErrorKind::NotFound),
+            )
+        })?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .map_err(|e| AppError::io("Failed to create settings directory", e))?;
+        }
+
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| AppError::json("Failed to serialize settings", e))?;
+        fs::write(&path, json).map_err(|e| AppError::io("Failed to write settings", e))
+    }
+
+    /// The segmentation parameters that apply to the given source file
+    pub fn segmentation_for(&self, path: &Path) -> &SilenceParams {
+        self.segmentation_overrides
+            .get(path)
+            .unwrap_or(&self.segmentation)
+    }
+
+    /// Moves the project to the top of the recent list
+    pub fn add_recent_project(&mut self, path: &Path) {
+        self.recent_projects.retain(|recent| recent != path);
+        self.recent_projects.insert(0, path.to_path_buf());
+        self.recent_projects.truncate(MAX_RECENT_PROJECTS);
+    }
+}
+
+/// Per-user directory for the settings and the autosaved session
+pub fn config_dir() -> Option<PathBuf> {
+    let config_dir = if cfg!(target_os = "windows") {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        std::env::var_os("HOME").map(|home| PathBuf::from(home).join("Library/Application Support"))
+    } else {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    }?;
+
+    Some(config_dir.join("malti_prac_psm_gen"))
+}
+
+fn settings_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("settings.json"))
+}
+
+/// Editing widgets for a set of segmentation parameters
+pub fn render_segmentation_controls(ui: &mut egui::Ui, params: &mut SilenceParams) {
+    egui::Grid::new(ui.next_auto_id())
+        .num_columns(2)
+        .show(ui, |ui| {
+            ui.label("Minimum silence length:");
+            ui.add(
+                egui::DragValue::new(&mut params.min_silence)
+                    .speed(0.05)
+                    .clamp_range(0.05..=10.0)
+                    .suffix(" s"),
+            );
+            ui.end_row();
+
+            ui.label("Silence threshold:");
+            ui.add(
+                egui::DragValue::new(&mut params.threshold_db)
+                    .speed(0.5)
+                    .clamp_range(-96.0..=0.0)
+                    .suffix(" dBFS"),
+            );
+            ui.end_row();
+
+            ui.label("Level measured by:");
+            ui.horizontal(|ui| {
+                ui.radio_value(&mut params.measure, LevelMeasure::Rms, "RMS");
+                ui.radio_value(&mut params.measure, LevelMeasure::Peak, "Peak");
+            });
+            ui.end_row();
+
+            ui.label("Minimum clip length:");
+            ui.add(
+                egui::DragValue::new(&mut params.min_clip)
+                    .speed(0.05)
+                    .clamp_range(0.0..=10.0)
+                    .suffix(" s"),
+            );
+            ui.end_row();
+
+            ui.label("Padding before clip:");
+            ui.add(
+                egui::DragValue::new(&mut params.padding_before)
+                    .speed(0.01)
+                    .clamp_range(0.0..=2.0)
+                    .suffix(" s"),
+            );
+            ui.end_row();
+
+            ui.label("Padding after clip:");
+            ui.add(
+                egui::DragValue::new(&mut params.padding_after)
+                    .speed(0.01)
+                    .clamp_range(0.0..=2.0)
+                    .suffix(" s"),
+            );
+            ui.end_row();
+        });
+}
+
+/// Editing widgets for the gaps inserted into the combined lesson
+pub fn render_gap_controls(ui: &mut egui::Ui, gaps: &mut GapSettings) {
+    egui::Grid::new(ui.next_auto_id())
+        .num_columns(2)
+        .show(ui, |ui| {
+            ui.label("Between sentence and translation:");
+            ui.add(seconds_value(&mut gaps.between_tracks));
+            ui.end_row();
+
+            ui.label("Between sentence pairs:");
+            ui.add(seconds_value(&mut gaps.between_pairs));
+            ui.end_row();
+
+            ui.label("Thinking pause:");
+            ui.add(
+                egui::DragValue::new(&mut gaps.thinking_pause_factor)
+                    .speed(0.05)
+                    .clamp_range(0.0..=5.0)
+                    .suffix("× clip length"),
+            );
+            ui.end_row();
+
+            ui.label("Leading silence:");
+            ui.add(seconds_value(&mut gaps.leading));
+            ui.end_row();
+
+            ui.label("Trailing silence:");
+            ui.add(seconds_value(&mut gaps.trailing));
+            ui.end_row();
+        });
+}
+
+/// Editing widgets for the order clips are played in
+pub fn render_interleave_controls(ui: &mut egui::Ui, interleave: &mut Interleave) {
+    ui.horizontal(|ui| {
+        ui.label("Pattern:");
+        egui::ComboBox::from_id_source("interleave_pattern")
+            .selected_text(interleave.pattern.label())
+            .show_ui(ui, |ui| {
+                for pattern in InterleavePattern::ALL {
+                    ui.selectable_value(&mut interleave.pattern, pattern, pattern.label());
+                }
+            });
+    });
+    ui.checkbox(&mut interleave.shuffle_pairs, "Shuffle sentence order");
+}
+
+fn seconds_value(value: &mut f64) -> egui::DragValue<'_> {
+    egui::DragValue::new(value)
+        .speed(0.05)
+        .clamp_range(0.0..=30.0)
+        .suffix(" s")
+}
+
+/// Lets the user replace the global segmentation for a single source file
+pub fn render_override_controls(ui: &mut egui::Ui, settings: &mut Settings, path: &Path) {
+    egui::CollapsingHeader::new("Segmentation for this file")
+        .id_source(path)
+        .show(ui, |ui| {
+            let mut overridden = settings.segmentation_overrides.contains_key(path);
+            if ui
+                .checkbox(&mut overridden, "Override the global settings")
+                .changed()
+            {
+                if overridden {
+                    settings
+                        .segmentation_overrides
+                        .insert(path.to_path_buf(), settings.segmentation.clone());
+                } else {
+                    settings.segmentation_overrides.remove(path);
+                }
+            }
+
+            match settings.segmentation_overrides.get_mut(path) {
+                Some(params) => render_segmentation_controls(ui, params),
+                None => {
+                    ui.label("Using the global settings from the Settings screen.");
+                }
+            }
+        });
+}
@@ -0,0 +1,361 @@
+use std::path::{Path, PathBuf};
+
+use hound::WavWriter;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    decode::DecodedAudio,
+    error::{AppError, Result},
+};
+
+/// Length of the analysis window used to measure the signal level
+const WINDOW_SECONDS: f64 = 0.01;
+
+/// A stretch of non-silent audio, in seconds from the start of the source
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Segment {
+    pub start: f64,
+    pub end: f64,
+}
+
+impl Segment {
+    pub fn duration(&self) -> f64 {
+        self.end - self.start
+    }
+}
+
+/// How a source is cut into clips
+#[derive(Debug, Clone, Copy)]
+pub enum Segmentation<'a> {
+    /// Find the clips with silence detection
+    Detect(&'a SilenceParams),
+    /// Use clip boundaries that were set by hand
+    Manual(&'a [Segment]),
+}
+
+/// How the level of each analysis window is measured
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum LevelMeasure {
+    Rms,
+    Peak,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SilenceParams {
+    /// Windows below this level (dBFS) count as silence
+    pub threshold_db: f64,
+    /// Silence has to last at least this long (seconds) to split the audio
+    pub min_silence: f64,
+    /// Segments shorter than this (seconds) are dropped as noise
+    pub min_clip: f64,
+    /// Extra audio kept before each segment (seconds)
+    pub padding_before: f64,
+    /// Extra audio kept after each segment (seconds)
+    pub padding_after: f64,
+    pub measure: LevelMeasure,
+}
+
+impl Default for SilenceParams {
+    // Matches what `ffmpeg -af silencedetect=d=2` used to do
+    fn default() -> Self {
+        Self {
+            threshold_db: -60.0,
+            min_silence: 2.0,
+            min_clip: 0.0,
+            padding_before: 0.0,
+            padding_after: 0.0,
+            measure: LevelMeasure::Rms,
+        }
+    }
+}
+
+/// Finds the non-silent parts of the audio.
+///
+/// Silence shorter than `min_silence` does not split a segment, leading and
+/// trailing silence is dropped, and so is anything shorter than `min_clip`.
+/// Padding never reaches past the middle of the silence separating two
+/// segments, so padded segments do not overlap.
+pub fn detect_segments(audio: &DecodedAudio, params: &SilenceParams) -> Vec<Segment> {
+    let channels = audio.channels as usize;
+    let window_frames = ((audio.sample_rate as f64 * WINDOW_SECONDS) as usize).max(1);
+    let window_seconds = window_frames as f64 / audio.sample_rate as f64;
+    let min_silent_windows = (params.min_silence / window_seconds).ceil() as usize;
+
+    let silent: Vec<bool> = audio
+        .samples
+        .chunks(window_frames * channels)
+        .map(|window| window_level_db(window, params.measure) < params.threshold_db)
+        .collect();
+
+    // Collect the window ranges of sound, merging across short silences
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    let mut i = 0;
+    while i < silent.len() {
+        if silent[i] {
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+        while i < silent.len() && !silent[i] {
+            i += 1;
+        }
+        let end = i;
+
+        let silence_end = silent[end..]
+            .iter()
+            .position(|s| !s)
+            .map_or(silent.len(), |p| end + p);
+
+        match ranges.last_mut() {
+            Some(last) if start - last.1 < min_silent_windows => last.1 = end,
+            _ => ranges.push((start, end)),
+        }
+
+        i = silence_end;
+    }
+
+    let duration = audio.duration();
+    let to_seconds = |window: usize| (window as f64 * window_seconds).min(duration);
+
+    let mut segments: Vec<Segment> = ranges
+        .iter()
+        .map(|&(start, end)| Segment {
+            start: to_seconds(start),
+            end: to_seconds(end),
+        })
+        .filter(|segment| segment.duration() >= params.min_clip)
+        .collect();
+
+    let unpadded = segments.clone();
+    for (i, segment) in segments.iter_mut().enumerate() {
+        let lower = match i {
+            0 => 0.0,
+            _ => (unpadded[i - 1].end + unpadded[i].start) / 2.0,
+        };
+        let upper = match unpadded.get(i + 1) {
+            Some(next) => (unpadded[i].end + next.start) / 2.0,
+            None => duration,
+        };
+
+        segment.start = (segment.start - params.padding_before).max(lower);
+        segment.end = (segment.end + params.padding_after).min(upper);
+    }
+
+    segments
+}
+
+fn window_level_db(window: &[i16], measure: LevelMeasure) -> f64 {
+    if window.is_empty() {
+        return f64::NEG_INFINITY;
+    }
+
+    let full_scale = i16::MAX as f64;
+    let level = match measure {
+        LevelMeasure::Rms => {
+            let sum: f64 = window
+                .iter()
+                .map(|s| {
+                    let v = *s as f64 / full_scale;
+                    v * v
+                })
+                .sum();
+            (sum / window.len() as f64).sqrt()
+        }
+        LevelMeasure::Peak => window
+            .iter()
+            .map(|s| (*s as f64 / full_scale).abs())
+            .fold(0.0, f64::max),
+    };
+
+    20.0 * level.log10()
+}
+
+/// Writes every segment out as its own numbered WAV file in `clips_dir`.
+///
+/// `on_clip` is called with the clip index and total before each clip is
+/// written; returning an error stops the loop.
+pub fn write_segment_clips(
+    audio: &DecodedAudio,
+    segments: &[Segment],
+    clips_dir: &Path,
+    on_clip: &mut dyn FnMut(usize, usize) -> Result<()>,
+) -> Result<Vec<PathBuf>> {
+    segments
+        .iter()
+        .enumerate()
+        .map(|(i, segment)| {
+            on_clip(i, segments.len())?;
+            let path = clips_dir.join(format!("{}.wav", i));
+            write_segment(audio, segment, &path)?;
+            Ok(path)
+        })
+        .collect()
+}
+
+pub fn write_segment(audio: &DecodedAudio, segment: &Segment, path: &Path) -> Result<()> {
+    let channels = audio.channels as usize;
+    let rate = audio.sample_rate as f64;
+    let first = ((segment.start * rate) as usize).min(audio.frame_count());
+    let last = ((segment.end * rate) as usize).clamp(first, audio.frame_count());
+
+    let mut writer = WavWriter::create(path, audio.spec()).map_err(|e| AppError::wav(path, e))?;
+    for sample in &audio.samples[first * channels..last * channels] {
+        writer
+            .write_sample(*sample)
+            .map_err(|e| AppError::wav(path, e))?;
+    }
+    writer.finalize().map_err(|e| AppError::wav(path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 1 kHz makes each analysis window exactly 10 frames
+    const RATE: u32 = 1000;
+
+    /// Audio made of `(seconds, amplitude)` parts; a part with amplitude 0 is
+    /// silence, anything else a square wave of that amplitude
+    fn audio(parts: &[(f64, i16)], channels: u16) -> DecodedAudio {
+        let mut samples = Vec::new();
+        for &(seconds, amplitude) in parts {
+            let frames = (seconds * RATE as f64).round() as usize;
+            for frame in 0..frames {
+                let sample = if frame % 2 == 0 {
+                    amplitude
+                } else {
+                    -amplitude
+                };
+                samples.extend(std::iter::repeat_n(sample, channels as usize));
+            }
+        }
+        DecodedAudio {
+            samples,
+            sample_rate: RATE,
+            channels,
+        }
+    }
+
+    fn params(min_silence: f64) -> SilenceParams {
+        SilenceParams {
+            min_silence,
+            ..SilenceParams::default()
+        }
+    }
+
+    fn assert_segments(actual: &[Segment], expected: &[(f64, f64)]) {
+        let actual: Vec<(f64, f64)> = actual.iter().map(|s| (s.start, s.end)).collect();
+        assert_eq!(actual.len(), expected.len(), "{:?}", actual);
+        for (a, e) in actual.iter().zip(expected) {
+            assert!(
+                (a.0 - e.0).abs() < 1e-9 && (a.1 - e.1).abs() < 1e-9,
+                "{:?} != {:?}",
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn threshold_decides_what_is_silence() {
+        // 328 / 32767 is about -40 dBFS
+        let quiet = audio(&[(0.5, 0), (1.0, 328), (0.5, 0)], 1);
+
+        let segments = detect_segments(&quiet, &params(0.2));
+        assert_segments(&segments, &[(0.5, 1.5)]);
+
+        let strict = SilenceParams {
+            threshold_db: -30.0,
+            ..params(0.2)
+        };
+        assert_segments(&detect_segments(&quiet, &strict), &[]);
+
+        let peak = SilenceParams {
+            threshold_db: -39.0,
+            measure: LevelMeasure::Peak,
+            ..params(0.2)
+        };
+        assert_segments(&detect_segments(&quiet, &peak), &[]);
+    }
+
+    #[test]
+    fn short_silence_does_not_split() {
+        let speech = audio(&[(0.5, 8000), (0.3, 0), (0.5, 8000)], 1);
+
+        let split = detect_segments(&speech, &params(0.2));
+        assert_segments(&split, &[(0.0, 0.5), (0.8, 1.3)]);
+
+        let merged = detect_segments(&speech, &params(0.5));
+        assert_segments(&merged, &[(0.0, 1.3)]);
+    }
+
+    #[test]
+    fn clips_shorter_than_min_clip_are_dropped() {
+        let speech = audio(&[(0.2, 0), (0.05, 8000), (0.75, 0), (0.5, 8000)], 1);
+
+        let all = detect_segments(&speech, &params(0.2));
+        assert_segments(&all, &[(0.2, 0.25), (1.0, 1.5)]);
+
+        let filtered = SilenceParams {
+            min_clip: 0.1,
+            ..params(0.2)
+        };
+        assert_segments(&detect_segments(&speech, &filtered), &[(1.0, 1.5)]);
+    }
+
+    #[test]
+    fn padding_stops_halfway_between_segments() {
+        let speech = audio(&[(0.5, 0), (0.5, 8000), (0.2, 0), (0.5, 8000), (0.3, 0)], 1);
+
+        let small = SilenceParams {
+            padding_before: 0.05,
+            padding_after: 0.05,
+            ..params(0.1)
+        };
+        assert_segments(
+            &detect_segments(&speech, &small),
+            &[(0.45, 1.05), (1.15, 1.75)],
+        );
+
+        // Clamped to the start, the midpoint 1.1 and the end of the audio
+        let large = SilenceParams {
+            padding_before: 0.6,
+            padding_after: 0.5,
+            ..params(0.1)
+        };
+        assert_segments(&detect_segments(&speech, &large), &[(0.0, 1.1), (1.1, 2.0)]);
+    }
+
+    #[test]
+    fn clips_are_written_with_their_frames() {
+        let speech = audio(
+            &[(0.5, 0), (0.5, 8000), (0.25, 0), (0.3, 8000), (0.2, 0)],
+            2,
+        );
+        let segments = detect_segments(&speech, &params(0.2));
+        assert_segments(&segments, &[(0.5, 1.0), (1.25, 1.55)]);
+
+        let dir = std::env::temp_dir().join(format!(
+            "malti_prac_psm_gen_test_clips_{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut calls = Vec::new();
+        let paths = write_segment_clips(&speech, &segments, &dir, &mut |i, total| {
+            calls.push((i, total));
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(calls, [(0, 2), (1, 2)]);
+        assert_eq!(paths, [dir.join("0.wav"), dir.join("1.wav")]);
+        for (path, frames) in paths.iter().zip([500, 300]) {
+            let reader = hound::WavReader::open(path).unwrap();
+            assert_eq!(reader.spec(), speech.spec());
+            assert_eq!(reader.duration(), frames);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,283 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    combine::{CombinedAudio, LessonSettings},
+    error::{AppError, Result},
+    silence::{Segment, SilenceParams},
+};
+
+/// Version of the stops file layout. The legacy bare array counts as version 1.
+/// Version 3 replaced the scratch clip path of every stop with its source.
+pub const STOPS_SCHEMA_VERSION: u32 = 3;
+
+/// Layout of the exported stops file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum StopsFormat {
+    /// Versioned object with metadata and full timing per sentence
+    #[default]
+    Versioned,
+    /// The original array of `{ sentence, audio_stop }`
+    Legacy,
+}
+
+impl StopsFormat {
+    pub const ALL: [StopsFormat; 2] = [StopsFormat::Versioned, StopsFormat::Legacy];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            StopsFormat::Versioned => "Versioned (start, end, track, metadata)",
+            StopsFormat::Legacy => "Legacy (sentence and start only)",
+        }
+    }
+}
+
+/// Everything a player needs to step through the lesson sentence by sentence
+#[derive(Debug, Clone, Serialize)]
+pub struct StopsFile {
+    pub schema_version: u32,
+    pub generator: Generator,
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// Length of the lesson audio, in seconds
+    pub total_duration: f64,
+    /// Priming the encoder added before the first sample, in seconds. The
+    /// times below are relative to the audio without it, unless the export
+    /// was asked to shift them.
+    pub encoder_delay: f64,
+    /// Padding the encoder added after the last sample, in seconds. Players
+    /// that do not trim it see the audio this much longer than `total_duration`.
+    pub encoder_padding: f64,
+    pub settings: LessonSettings,
+    pub tracks: Vec<StopsTrack>,
+    pub elements: Vec<Stop>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Generator {
+    pub name: &'static str,
+    pub version: &'static str,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StopsTrack {
+    pub id: usize,
+    /// The track's label, usually its language
+    pub label: String,
+    /// The recording the clips were cut from, if known
+    pub source: Option<PathBuf>,
+    /// Silence detection the clips were cut with; `None` for imported labels
+    pub segmentation: Option<SilenceParams>,
+}
+
+/// One sentence in the lesson. Times are in seconds.
+#[derive(Debug, Clone, Serialize)]
+pub struct Stop {
+    pub sentence: String,
+    /// Id of the track in `tracks`
+    pub track: usize,
+    /// Index of the sentence pair in the source texts
+    pub pair: usize,
+    pub start: f64,
+    pub end: f64,
+    pub duration: f64,
+    /// Recording the audio was cut from; `None` for text shown with another
+    /// track's clip or if the source is unknown
+    pub source: Option<PathBuf>,
+    /// Where the clip starts in `source`
+    pub source_offset: Option<f64>,
+}
+
+/// Per-track information the combiner does not know about
+#[derive(Debug, Clone)]
+pub struct TrackSource {
+    pub label: String,
+    pub source: Option<PathBuf>,
+    /// Where each clip was cut from, by clip index; empty if unknown
+    pub segments: Vec<Segment>,
+    pub segmentation: Option<SilenceParams>,
+}
+
+#[derive(Serialize)]
+struct LegacyStop<'a> {
+    sentence: &'a str,
+    audio_stop: f64,
+}
+
+impl StopsFile {
+    pub fn new(
+        combined: &CombinedAudio,
+        tracks: &[TrackSource],
+        settings: &LessonSettings,
+    ) -> Self {
+        let elements = combined
+            .elements
+            .iter()
+            .map(|element| {
+                // Clips live in a scratch directory that is gone after export
+                let track = element
+                    .clip
+                    .as_ref()
+                    .and_then(|_| tracks.get(element.track));
+                Stop {
+                    sentence: element.sentence.clone(),
+                    track: element.track,
+                    pair: element.pair,
+                    start: element.audio_stop,
+                    end: element.audio_end,
+                    duration: element.audio_end - element.audio_stop,
+                    source: track.and_then(|track| track.source.clone()),
+                    source_offset: track
+                        .and_then(|track| track.segments.get(element.pair))
+                        .map(|segment| segment.start),
+                }
+            })
+            .collect();
+
+        Self {
+            schema_version: STOPS_SCHEMA_VERSION,
+            generator: Generator {
+                name: env!("CARGO_PKG_NAME"),
+                version: env!("CARGO_PKG_VERSION"),
+            },
+            sample_rate: combined.spec.sample_rate,
+            channels: combined.spec.channels,
+            total_duration: combined.duration,
+            encoder_delay: 0.0,
+            encoder_padding: 0.0,
+            settings: settings.clone(),
+            tracks: tracks
+                .iter()
+                .enumerate()
+                .map(|(id, track)| StopsTrack {
+                    id,
+                    label: track.label.clone(),
+                    source: track.source.clone(),
+                    segmentation: track.segmentation.clone(),
+                })
+                .collect(),
+            elements,
+        }
+    }
+
+    /// Records the encoder delay and padding and, if `shift` is set, moves
+    /// every stop later by the delay for players that keep the priming samples
+    pub fn with_encoder_priming(mut self, delay: f64, padding: f64, shift: bool) -> Self {
+        self.encoder_delay = delay;
+        self.encoder_padding = padding;
+        if shift {
+            for stop in &mut self.elements {
+                stop.start += delay;
+                stop.end += delay;
+            }
+        }
+        self
+    }
+
+    pub fn write(&self, path: &Path, format: StopsFormat) -> Result<()> {
+        let json = match format {
+            StopsFormat::Versioned => serde_json::to_string_pretty(self),
+            StopsFormat::Legacy => serde_json::to_string_pretty(
+                &self
+                    .elements
+                    .iter()
+                    .map(|stop| LegacyStop {
+                        sentence: &stop.sentence,
+                        audio_stop: stop.start,
+                    })
+                    .collect::<Vec<_>>(),
+            ),
+        }
+        .map_err(|e| AppError::json("Failed to serialize the stops", e))?;
+
+        fs::write(path, json)
+            .map_err(|e| AppError::io(format!("Failed to write {}", path.display()), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hound::{SampleFormat, WavSpec};
+
+    use super::*;
+    use crate::combine::ResultElement;
+
+    fn element(track: usize, pair: usize, start: f64, clip: bool) -> ResultElement {
+        ResultElement {
+            sentence: format!("{}.{}", track, pair),
+            audio_stop: start,
+            audio_end: start + 1.0,
+            track,
+            pair,
+            clip: clip.then(|| PathBuf::from(format!("/tmp/scratch/{}.wav", pair))),
+        }
+    }
+
+    #[test]
+    fn stops_point_at_the_source_recording() {
+        let combined = CombinedAudio {
+            path: PathBuf::from("lesson.wav"),
+            elements: vec![
+                element(0, 0, 0.0, true),
+                element(1, 0, 0.0, false),
+                element(0, 1, 2.0, true),
+            ],
+            spec: WavSpec {
+                channels: 1,
+                sample_rate: 16_000,
+                bits_per_sample: 16,
+                sample_format: SampleFormat::Int,
+            },
+            duration: 3.0,
+        };
+        let params = SilenceParams::default();
+        let tracks = [
+            TrackSource {
+                label: "Malti".to_string(),
+                source: Some(PathBuf::from("malti.mp3")),
+                segments: vec![
+                    Segment {
+                        start: 1.5,
+                        end: 2.5,
+                    },
+                    Segment {
+                        start: 4.0,
+                        end: 5.0,
+                    },
+                ],
+                segmentation: Some(params.clone()),
+            },
+            TrackSource {
+                label: "English".to_string(),
+                source: None,
+                segments: Vec::new(),
+                segmentation: None,
+            },
+        ];
+
+        let stops = StopsFile::new(&combined, &tracks, &LessonSettings::default());
+        let sources: Vec<(Option<&Path>, Option<f64>)> = stops
+            .elements
+            .iter()
+            .map(|stop| (stop.source.as_deref(), stop.source_offset))
+            .collect();
+        assert_eq!(
+            sources,
+            [
+                (Some(Path::new("malti.mp3")), Some(1.5)),
+                (None, None),
+                (Some(Path::new("malti.mp3")), Some(4.0)),
+            ]
+        );
+        assert_eq!(stops.tracks[0].segmentation, Some(params));
+        assert_eq!(stops.tracks[1].segmentation, None);
+
+        let json = serde_json::to_string(&stops).unwrap();
+        assert!(!json.contains("scratch"));
+        assert!(json.contains("\"schema_version\":3"));
+    }
+}
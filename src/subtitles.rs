@@ -0,0 +1,395 @@
+use std::{fmt::Write as _, fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{AppError, Result},
+    stops::{Stop, StopsFile},
+};
+
+/// How sentences are grouped into subtitle cues
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum CueLayout {
+    /// One cue per clip, shown while that clip plays
+    #[default]
+    PerClip,
+    /// One cue per sentence pair with every language on its own line,
+    /// shown from the first clip of the pair to the last
+    PerPair,
+}
+
+impl CueLayout {
+    pub const ALL: [CueLayout; 2] = [CueLayout::PerClip, CueLayout::PerPair];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            CueLayout::PerClip => "One cue per clip",
+            CueLayout::PerPair => "Both languages on one cue",
+        }
+    }
+}
+
+/// Which subtitle and label files the export writes next to the audio
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SubtitleSettings {
+    pub srt: bool,
+    pub vtt: bool,
+    pub lrc: bool,
+    pub audacity: bool,
+    pub layout: CueLayout,
+}
+
+impl SubtitleSettings {
+    /// The formats that are switched on
+    pub fn formats(&self) -> Vec<SubtitleFormat> {
+        [
+            (self.srt, SubtitleFormat::Srt),
+            (self.vtt, SubtitleFormat::Vtt),
+            (self.lrc, SubtitleFormat::Lrc),
+            (self.audacity, SubtitleFormat::AudacityLabels),
+        ]
+        .into_iter()
+        .filter_map(|(enabled, format)| enabled.then_some(format))
+        .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubtitleFormat {
+    Srt,
+    Vtt,
+    /// Lyrics with millisecond timestamps
+    Lrc,
+    /// Tab-separated start, end and label, as Audacity imports and exports them
+    AudacityLabels,
+}
+
+impl SubtitleFormat {
+    /// Extension of the file written next to the audio
+    pub fn extension(self) -> &'static str {
+        match self {
+            SubtitleFormat::Srt => "srt",
+            SubtitleFormat::Vtt => "vtt",
+            SubtitleFormat::Lrc => "lrc",
+            SubtitleFormat::AudacityLabels => "labels.txt",
+        }
+    }
+}
+
+/// A stretch of time and the lines shown during it, in seconds
+#[derive(Debug, Clone, PartialEq)]
+struct Cue {
+    start: f64,
+    end: f64,
+    lines: Vec<String>,
+}
+
+/// Groups the stops into cues. Stops that share a clip (text shown with
+/// another track's audio) always end up on the same cue.
+fn build_cues(stops: &StopsFile, layout: CueLayout) -> Vec<Cue> {
+    let same_cue = |cue: &Cue, stop: &Stop, previous: &Stop| match layout {
+        CueLayout::PerClip => stop.start == cue.start && stop.end == cue.end,
+        CueLayout::PerPair => stop.pair == previous.pair,
+    };
+
+    let mut cues: Vec<Cue> = Vec::new();
+    let mut previous: Option<&Stop> = None;
+    for stop in &stops.elements {
+        let sentence = stop.sentence.trim();
+
+        match (cues.last_mut(), previous) {
+            (Some(cue), Some(previous)) if same_cue(cue, stop, previous) => {
+                cue.end = cue.end.max(stop.end);
+                if !sentence.is_empty() && !cue.lines.iter().any(|line| line == sentence) {
+                    cue.lines.push(sentence.to_string());
+                }
+            }
+            _ => cues.push(Cue {
+                start: stop.start,
+                end: stop.end,
+                lines: if sentence.is_empty() {
+                    Vec::new()
+                } else {
+                    vec![sentence.to_string()]
+                },
+            }),
+        }
+        previous = Some(stop);
+    }
+
+    cues.retain(|cue| !cue.lines.is_empty() && cue.end > cue.start);
+    cues
+}
+
+/// `HH:MM:SS,mmm` for SRT, `HH:MM:SS.mmm` for WebVTT
+fn timestamp(seconds: f64, format: SubtitleFormat) -> String {
+    let millis = (seconds.max(0.0) * 1000.0).round() as u64;
+    let separator = if format == SubtitleFormat::Srt {
+        ','
+    } else {
+        '.'
+    };
+
+    format!(
+        "{:02}:{:02}:{:02}{}{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        separator,
+        millis % 1000
+    )
+}
+
+/// `[MM:SS.mmm]`, minutes may go past 59
+fn lrc_timestamp(seconds: f64) -> String {
+    let millis = (seconds.max(0.0) * 1000.0).round() as u64;
+    format!(
+        "[{:02}:{:02}.{:03}]",
+        millis / 60_000,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+/// Renders the stops as an SRT, WebVTT, LRC or Audacity label document
+pub fn render_subtitles(stops: &StopsFile, layout: CueLayout, format: SubtitleFormat) -> String {
+    let cues = build_cues(stops, layout);
+    let mut out = String::new();
+
+    match format {
+        SubtitleFormat::Srt | SubtitleFormat::Vtt => {
+            if format == SubtitleFormat::Vtt {
+                out.push_str("WEBVTT\n\n");
+            }
+
+            for (i, cue) in cues.iter().enumerate() {
+                // A blank line ends a cue in both formats, so lines must not be empty
+                let _ = write!(
+                    out,
+                    "{}\n{} --> {}\n{}\n\n",
+                    i + 1,
+                    timestamp(cue.start, format),
+                    timestamp(cue.end, format),
+                    cue.lines.join("\n")
+                );
+            }
+        }
+        SubtitleFormat::Lrc => {
+            for cue in &cues {
+                let _ = writeln!(out, "{}{}", lrc_timestamp(cue.start), cue.lines.join(" / "));
+            }
+            // Clear the last line once its clip is over
+            if let Some(last) = cues.last() {
+                let _ = writeln!(out, "{}", lrc_timestamp(last.end));
+            }
+        }
+        SubtitleFormat::AudacityLabels => {
+            for cue in &cues {
+                let _ = writeln!(
+                    out,
+                    "{:.6}\t{:.6}\t{}",
+                    cue.start,
+                    cue.end,
+                    cue.lines.join(" / ")
+                );
+            }
+        }
+    }
+
+    out
+}
+
+pub fn write_subtitles(
+    stops: &StopsFile,
+    layout: CueLayout,
+    format: SubtitleFormat,
+    path: &Path,
+) -> Result<()> {
+    fs::write(path, render_subtitles(stops, layout, format))
+        .map_err(|e| AppError::io(format!("Failed to write {}", path.display()), e))
+}
+
+/// Checkboxes for the subtitle files and the cue layout
+pub fn render_subtitle_controls(ui: &mut egui::Ui, settings: &mut SubtitleSettings) {
+    ui.horizontal(|ui| {
+        ui.label("Subtitles:");
+        ui.checkbox(&mut settings.srt, "SRT");
+        ui.checkbox(&mut settings.vtt, "WebVTT");
+        ui.checkbox(&mut settings.lrc, "LRC");
+        ui.checkbox(&mut settings.audacity, "Audacity labels");
+
+        if !settings.formats().is_empty() {
+            egui::ComboBox::from_id_source("cue_layout")
+                .selected_text(settings.layout.label())
+                .show_ui(ui, |ui| {
+                    for layout in CueLayout::ALL {
+                        ui.selectable_value(&mut settings.layout, layout, layout.label());
+                    }
+                });
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use hound::{SampleFormat, WavSpec};
+
+    use super::*;
+    use crate::{
+        combine::{CombinedAudio, LessonSettings, ResultElement},
+        stops::TrackSource,
+    };
+
+    /// Stops for `(sentence, start, end, track, pair, has_clip)`
+    fn stops(elements: &[(&str, f64, f64, usize, usize, bool)]) -> StopsFile {
+        let combined = CombinedAudio {
+            path: PathBuf::from("lesson.wav"),
+            elements: elements
+                .iter()
+                .map(
+                    |&(sentence, start, end, track, pair, has_clip)| ResultElement {
+                        sentence: sentence.to_string(),
+                        audio_stop: start,
+                        audio_end: end,
+                        track,
+                        pair,
+                        clip: has_clip.then(|| PathBuf::from(format!("{}/{}.wav", track, pair))),
+                    },
+                )
+                .collect(),
+            spec: WavSpec {
+                channels: 1,
+                sample_rate: 44_100,
+                bits_per_sample: 16,
+                sample_format: SampleFormat::Int,
+            },
+            duration: 3662.0,
+        };
+        let tracks = ["Malti", "English"].map(|label| TrackSource {
+            label: label.to_string(),
+            source: None,
+            segments: Vec::new(),
+            segmentation: None,
+        });
+        StopsFile::new(&combined, &tracks, &LessonSettings::default())
+    }
+
+    /// Two pairs, the last one running past an hour
+    fn lesson() -> StopsFile {
+        stops(&[
+            ("Bonġu", 0.0, 1.2346, 0, 0, true),
+            ("Good morning", 2.2346, 3.5, 1, 0, true),
+            ("Grazzi", 4.5, 59.9996, 0, 1, true),
+            (" Thanks ", 60.9996, 3661.9996, 1, 1, true),
+        ])
+    }
+
+    #[test]
+    fn srt_has_one_cue_per_clip() {
+        assert_eq!(
+            render_subtitles(&lesson(), CueLayout::PerClip, SubtitleFormat::Srt),
+            "1\n00:00:00,000 --> 00:00:01,235\nBonġu\n\n\
+             2\n00:00:02,235 --> 00:00:03,500\nGood morning\n\n\
+             3\n00:00:04,500 --> 00:01:00,000\nGrazzi\n\n\
+             4\n00:01:01,000 --> 01:01:02,000\nThanks\n\n"
+        );
+    }
+
+    #[test]
+    fn vtt_has_a_header_and_dots() {
+        assert_eq!(
+            render_subtitles(&lesson(), CueLayout::PerPair, SubtitleFormat::Vtt),
+            "WEBVTT\n\n\
+             1\n00:00:00.000 --> 00:00:03.500\nBonġu\nGood morning\n\n\
+             2\n00:00:04.500 --> 01:01:02.000\nGrazzi\nThanks\n\n"
+        );
+    }
+
+    #[test]
+    fn lrc_clears_the_last_line() {
+        assert_eq!(
+            render_subtitles(&lesson(), CueLayout::PerPair, SubtitleFormat::Lrc),
+            "[00:00.000]Bonġu / Good morning\n\
+             [00:04.500]Grazzi / Thanks\n\
+             [61:02.000]\n"
+        );
+    }
+
+    #[test]
+    fn audacity_labels_keep_microseconds() {
+        assert_eq!(
+            render_subtitles(
+                &lesson(),
+                CueLayout::PerClip,
+                SubtitleFormat::AudacityLabels
+            ),
+            "0.000000\t1.234600\tBonġu\n\
+             2.234600\t3.500000\tGood morning\n\
+             4.500000\t59.999600\tGrazzi\n\
+             60.999600\t3661.999600\tThanks\n"
+        );
+    }
+
+    #[test]
+    fn text_shown_with_a_clip_shares_its_cue() {
+        let source_only = stops(&[
+            ("Bonġu", 0.5, 1.5, 0, 0, true),
+            ("Good morning", 0.5, 1.5, 1, 0, false),
+            ("Bonġu", 2.5, 3.0, 0, 1, true),
+            ("", 2.5, 3.0, 1, 1, false),
+        ]);
+        assert_eq!(
+            render_subtitles(&source_only, CueLayout::PerClip, SubtitleFormat::Srt),
+            "1\n00:00:00,500 --> 00:00:01,500\nBonġu\nGood morning\n\n\
+             2\n00:00:02,500 --> 00:00:03,000\nBonġu\n\n"
+        );
+    }
+
+    #[test]
+    fn empty_and_zero_length_cues_are_dropped() {
+        let odd = stops(&[
+            ("  ", 0.0, 1.0, 0, 0, true),
+            ("Point", 2.0, 2.0, 0, 1, true),
+            ("Iva", 3.0, 4.0, 0, 2, true),
+        ]);
+        assert_eq!(
+            render_subtitles(&odd, CueLayout::PerClip, SubtitleFormat::Lrc),
+            "[00:03.000]Iva\n[00:04.000]\n"
+        );
+        assert_eq!(
+            render_subtitles(&stops(&[]), CueLayout::PerClip, SubtitleFormat::Vtt),
+            "WEBVTT\n\n"
+        );
+    }
+
+    #[test]
+    fn encoder_delay_shifts_every_cue() {
+        let shifted = lesson().with_encoder_priming(0.05, 0.02, true);
+        let srt = render_subtitles(&shifted, CueLayout::PerClip, SubtitleFormat::Srt);
+        assert!(srt.starts_with("1\n00:00:00,050 --> 00:00:01,285\nBonġu\n\n"));
+        assert!(srt.ends_with("4\n00:01:01,050 --> 01:01:02,050\nThanks\n\n"));
+
+        // Recorded only: the times stay as they are
+        let recorded = lesson().with_encoder_priming(0.05, 0.02, false);
+        assert_eq!(
+            render_subtitles(&recorded, CueLayout::PerClip, SubtitleFormat::Srt),
+            render_subtitles(&lesson(), CueLayout::PerClip, SubtitleFormat::Srt)
+        );
+    }
+
+    #[test]
+    fn only_enabled_formats_are_written() {
+        let settings = SubtitleSettings {
+            vtt: true,
+            audacity: true,
+            ..SubtitleSettings::default()
+        };
+        assert_eq!(
+            settings.formats(),
+            [SubtitleFormat::Vtt, SubtitleFormat::AudacityLabels]
+        );
+    }
+}
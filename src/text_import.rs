@@ -0,0 +1,822 @@
+use std::{
+    fs::{self, File},
+    io::Read,
+    mem,
+    path::{Path, PathBuf},
+};
+
+use chardetng::EncodingDetector;
+use encoding_rs::{
+    Encoding, ISO_8859_3, ISO_8859_15, UTF_8, UTF_16BE, UTF_16LE, WINDOWS_1250, WINDOWS_1252,
+};
+
+use crate::{
+    MyApp, Track,
+    error::{AppError, Result},
+};
+
+/// Extensions offered in the open dialog
+pub const SUPPORTED_EXTENSIONS: &[&str] = &["txt", "csv", "tsv", "docx", "odt", "srt", "vtt"];
+
+/// Encodings the user can switch to if the detected one is wrong
+const ENCODINGS: &[&Encoding] = &[
+    UTF_8,
+    UTF_16LE,
+    UTF_16BE,
+    ISO_8859_3,
+    WINDOWS_1252,
+    ISO_8859_15,
+    WINDOWS_1250,
+];
+
+/// Rows shown in the preview; the import always takes all of them
+const PREVIEW_ROWS: usize = 200;
+
+/// How the file is split into rows and columns
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TextFormat {
+    /// One sentence per line
+    Plain,
+    /// One row per line, one column per language
+    Delimited(u8),
+    /// SRT or WebVTT, one sentence per cue
+    Subtitles,
+    /// Word or OpenDocument text. Paragraphs are rows; table cells and
+    /// tab-separated text become columns.
+    Document,
+}
+
+impl TextFormat {
+    fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "txt" => Some(TextFormat::Plain),
+            // The delimiter of .csv files is sniffed once the text is decoded
+            "csv" => Some(TextFormat::Delimited(b',')),
+            "tsv" => Some(TextFormat::Delimited(b'\t')),
+            "srt" | "vtt" => Some(TextFormat::Subtitles),
+            "docx" | "odt" => Some(TextFormat::Document),
+            _ => None,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            TextFormat::Plain => "Plain text, one sentence per line",
+            TextFormat::Delimited(b'\t') => "Tab-separated columns",
+            TextFormat::Delimited(b';') => "Semicolon-separated columns",
+            TextFormat::Delimited(_) => "Comma-separated columns",
+            TextFormat::Subtitles => "Subtitles, one sentence per cue",
+            TextFormat::Document => "Document, one sentence per paragraph",
+        }
+    }
+}
+
+/// Where a column of the imported file goes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImportTarget {
+    Skip,
+    Track(usize),
+    NewTrack,
+}
+
+/// A file being imported, shown in the preview until the user confirms
+#[derive(Debug, Clone)]
+pub struct TextImport {
+    pub path: PathBuf,
+    pub format: TextFormat,
+    /// Raw file contents; documents are stored as the extracted UTF-8 text
+    bytes: Vec<u8>,
+    pub encoding: &'static Encoding,
+    pub detected_encoding: &'static Encoding,
+    pub first_row_is_header: bool,
+    rows: Vec<Vec<String>>,
+    pub targets: Vec<ImportTarget>,
+}
+
+impl TextImport {
+    /// Reads and splits the file, sending column `i` to track `first_track + i`
+    pub fn open(path: &Path, first_track: usize, track_count: usize) -> Result<Self> {
+        let unreadable = |reason: String| AppError::UnreadableText {
+            path: path.to_path_buf(),
+            reason,
+        };
+
+        let mut format = TextFormat::from_path(path)
+            .ok_or_else(|| unreadable("unsupported file type".to_string()))?;
+        let bytes = match format {
+            TextFormat::Document => document_text(path).map_err(unreadable)?.into_bytes(),
+            _ => fs::read(path)
+                .map_err(|e| AppError::io(format!("Failed to read {}", path.display()), e))?,
+        };
+        let encoding = match format {
+            TextFormat::Document => UTF_8,
+            _ => detect_encoding(&bytes),
+        };
+        if format == TextFormat::Delimited(b',') {
+            format = TextFormat::Delimited(sniff_delimiter(&decode(&bytes, encoding)));
+        }
+
+        let mut import = Self {
+            path: path.to_path_buf(),
+            format,
+            bytes,
+            encoding,
+            detected_encoding: encoding,
+            first_row_is_header: false,
+            rows: Vec::new(),
+            targets: Vec::new(),
+        };
+        import.split();
+        if import.rows.is_empty() {
+            return Err(unreadable("no text found".to_string()));
+        }
+
+        import.targets = (0..import.column_count())
+            .map(|i| match first_track + i {
+                track if track < track_count => ImportTarget::Track(track),
+                _ => ImportTarget::NewTrack,
+            })
+            .collect();
+        Ok(import)
+    }
+
+    pub fn set_encoding(&mut self, encoding: &'static Encoding) {
+        if encoding != self.encoding {
+            self.encoding = encoding;
+            self.split();
+            self.targets.resize(self.column_count(), ImportTarget::Skip);
+        }
+    }
+
+    fn split(&mut self) {
+        let text = decode(&self.bytes, self.encoding);
+        let rows = match self.format {
+            TextFormat::Plain => text.lines().map(|line| vec![line.to_string()]).collect(),
+            TextFormat::Delimited(delimiter) => delimited_rows(&text, delimiter),
+            TextFormat::Subtitles => subtitle_rows(&text),
+            TextFormat::Document => text
+                .lines()
+                .map(|line| line.split(CELL_SEPARATOR).map(str::to_string).collect())
+                .collect(),
+        };
+
+        self.rows = rows
+            .into_iter()
+            .map(|row: Vec<String>| row.iter().map(|cell| cell.trim().to_string()).collect())
+            .filter(|row: &Vec<String>| row.iter().any(|cell| !cell.is_empty()))
+            .collect();
+    }
+
+    pub fn column_count(&self) -> usize {
+        self.rows.iter().map(Vec::len).max().unwrap_or(0)
+    }
+
+    pub fn header(&self, column: usize) -> Option<&str> {
+        self.first_row_is_header
+            .then(|| self.rows.first()?.get(column).map(String::as_str))
+            .flatten()
+            .filter(|header| !header.is_empty())
+    }
+
+    /// The rows that become sentences
+    fn body(&self) -> &[Vec<String>] {
+        let skip = usize::from(self.first_row_is_header).min(self.rows.len());
+        &self.rows[skip..]
+    }
+
+    /// One sentence per line, as it will be put into the track
+    pub fn column_text(&self, column: usize) -> String {
+        self.body()
+            .iter()
+            .map(|row| row.get(column).map_or("", String::as_str))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+fn decode(bytes: &[u8], encoding: &'static Encoding) -> String {
+    encoding.decode_with_bom_removal(bytes).0.into_owned()
+}
+
+/// Byte order mark, then UTF-8, then chardetng's guess
+fn detect_encoding(bytes: &[u8]) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return encoding;
+    }
+    if std::str::from_utf8(bytes).is_ok() {
+        return UTF_8;
+    }
+
+    let mut detector = EncodingDetector::new();
+    detector.feed(bytes, true);
+    let guess = detector.guess(None, true);
+
+    // chardetng does not know ISO-8859-3, the legacy encoding for Maltese. Its
+    // Ħ ħ Ż ż Ċ ċ Ġ ġ are bytes windows-1252 uses for symbols such as ¡ ± ¯ ¿
+    // that rarely appear in running text, and that Central European encodings
+    // read as letters such as ą and ő.
+    const MALTESE_LATIN3: [u8; 8] = [0xA1, 0xB1, 0xAF, 0xBF, 0xC5, 0xE5, 0xD5, 0xF5];
+    let has_maltese = bytes.iter().any(|b| MALTESE_LATIN3.contains(b));
+    if has_maltese && (guess == WINDOWS_1252 || reads_as_maltese(bytes)) {
+        ISO_8859_3
+    } else {
+        guess
+    }
+}
+
+/// Whether every non-ASCII character is a Maltese letter when read as ISO-8859-3
+fn reads_as_maltese(bytes: &[u8]) -> bool {
+    const MALTESE_LETTERS: &str = "ĦħĠġĊċŻżÀàÈèÌìÒòÙù";
+    ISO_8859_3
+        .decode_without_bom_handling_and_without_replacement(bytes)
+        .is_some_and(|text| {
+            text.chars()
+                .all(|c| c.is_ascii() || MALTESE_LETTERS.contains(c))
+        })
+}
+
+/// Picks the most common of comma, semicolon and tab in the first line
+fn sniff_delimiter(text: &str) -> u8 {
+    let first_line = text
+        .lines()
+        .find(|line| !line.trim().is_empty())
+        .unwrap_or("");
+    [b',', b';', b'\t']
+        .into_iter()
+        .max_by_key(|&delimiter| first_line.bytes().filter(|&byte| byte == delimiter).count())
+        .filter(|&delimiter| first_line.bytes().any(|byte| byte == delimiter))
+        .unwrap_or(b',')
+}
+
+fn delimited_rows(text: &str, delimiter: u8) -> Vec<Vec<String>> {
+    csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(text.as_bytes())
+        .records()
+        .filter_map(|record| record.ok())
+        .map(|record| record.iter().map(str::to_string).collect())
+        .collect()
+}
+
+/// The text of every cue, with its lines joined and formatting tags removed.
+/// Blocks without a timing line (the WebVTT header, notes) are skipped.
+fn subtitle_rows(text: &str) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut cue: Vec<String> = Vec::new();
+    let mut timed = false;
+
+    for line in text.lines().chain([""]) {
+        let line = line.trim();
+        if line.is_empty() {
+            if timed && !cue.is_empty() {
+                rows.push(vec![cue.join(" ")]);
+            }
+            cue.clear();
+            timed = false;
+        } else if line.contains("-->") {
+            // Anything before the timing line is the cue's number or id
+            cue.clear();
+            timed = true;
+        } else if timed {
+            cue.push(strip_tags(line));
+        }
+    }
+
+    rows
+}
+
+/// Removes `<i>`-style tags and `{\an8}`-style overrides
+fn strip_tags(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut closing = None;
+    for c in line.chars() {
+        match (closing, c) {
+            (None, '<') => closing = Some('>'),
+            (None, '{') => closing = Some('}'),
+            (None, c) => out.push(c),
+            (Some(end), c) if c == end => closing = None,
+            (Some(_), _) => {}
+        }
+    }
+    out.trim().to_string()
+}
+
+/// Separates table cells and tab-separated text in extracted documents
+const CELL_SEPARATOR: char = '\u{1f}';
+
+/// Element names of a document format
+struct DocumentTags {
+    /// Text is only taken from inside these, or from any paragraph if empty
+    text: &'static [&'static str],
+    paragraph: &'static [&'static str],
+    tab: &'static str,
+    line_break: &'static str,
+    /// Run of spaces, with the count in the `c` attribute
+    spaces: &'static str,
+    table: &'static str,
+    row: &'static str,
+    cell: &'static str,
+    /// Elements whose content is not part of the text
+    skip: &'static [&'static str],
+}
+
+const DOCX_TAGS: DocumentTags = DocumentTags {
+    text: &["w:t"],
+    paragraph: &["w:p"],
+    tab: "w:tab",
+    line_break: "w:br",
+    spaces: "",
+    table: "w:tbl",
+    row: "w:tr",
+    cell: "w:tc",
+    // Paragraph properties hold tab stop definitions, also called w:tab
+    skip: &["w:pPr", "w:instrText", "w:delText"],
+};
+
+const ODT_TAGS: DocumentTags = DocumentTags {
+    text: &[],
+    paragraph: &["text:p", "text:h"],
+    tab: "text:tab",
+    line_break: "text:line-break",
+    spaces: "text:s",
+    table: "table:table",
+    row: "table:table-row",
+    cell: "table:table-cell",
+    skip: &["text:note", "office:annotation"],
+};
+
+/// Extracts the body text of a .docx or .odt file, one paragraph or table
+/// row per line, cells separated by `CELL_SEPARATOR`
+fn document_text(path: &Path) -> std::result::Result<String, String> {
+    let (entry, tags) = match path.extension().and_then(|e| e.to_str()) {
+        Some(extension) if extension.eq_ignore_ascii_case("odt") => ("content.xml", &ODT_TAGS),
+        _ => ("word/document.xml", &DOCX_TAGS),
+    };
+
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let mut archive = zip::ZipArchive::new(file).map_err(|e| e.to_string())?;
+    let mut xml = String::new();
+    archive
+        .by_name(entry)
+        .map_err(|_| format!("{} not found, the file may be damaged", entry))?
+        .read_to_string(&mut xml)
+        .map_err(|e| e.to_string())?;
+
+    let separator = CELL_SEPARATOR.to_string();
+    Ok(document_rows(&xml, tags)
+        .iter()
+        .map(|row| row.join(&separator).replace(['\n', '\r'], " "))
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
+
+fn document_rows(xml: &str, tags: &DocumentTags) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut cells = Vec::new();
+    let mut text = String::new();
+    let mut table_depth = 0usize;
+    let mut paragraph_depth = 0usize;
+    let mut skip_depth = 0usize;
+    let mut in_text = false;
+
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        let collecting = if tags.text.is_empty() {
+            paragraph_depth > 0
+        } else {
+            in_text
+        };
+        if skip_depth == 0 && collecting {
+            text.push_str(&unescape(&rest[..start]));
+        }
+
+        let Some(length) = rest[start..].find('>') else {
+            break;
+        };
+        let tag = &rest[start + 1..start + length];
+        rest = &rest[start + length + 1..];
+        if tag.starts_with('?') || tag.starts_with('!') {
+            continue;
+        }
+
+        let closing = tag.starts_with('/');
+        let self_closing = tag.ends_with('/');
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or("");
+
+        if tags.skip.contains(&name) {
+            if closing {
+                skip_depth = skip_depth.saturating_sub(1);
+            } else if !self_closing {
+                skip_depth += 1;
+            }
+            continue;
+        }
+        if skip_depth > 0 {
+            continue;
+        }
+
+        // A new row outside tables, a space inside a cell
+        let mut end_line = |text: &mut String, cells: &mut Vec<String>| {
+            if table_depth > 0 {
+                text.push(' ');
+            } else {
+                cells.push(mem::take(text));
+                rows.push(mem::take(cells));
+            }
+        };
+
+        if tags.text.contains(&name) {
+            in_text = !closing && !self_closing;
+        } else if tags.paragraph.contains(&name) {
+            if closing {
+                paragraph_depth = paragraph_depth.saturating_sub(1);
+            } else if !self_closing {
+                paragraph_depth += 1;
+            }
+            if closing || self_closing {
+                end_line(&mut text, &mut cells);
+            }
+        } else if name == tags.line_break {
+            end_line(&mut text, &mut cells);
+        } else if name == tags.tab {
+            if table_depth > 0 {
+                text.push(' ');
+            } else {
+                cells.push(mem::take(&mut text));
+            }
+        } else if name == tags.spaces {
+            let count = attribute(tag, "text:c")
+                .and_then(|c| c.parse().ok())
+                .unwrap_or(1);
+            text.push_str(&" ".repeat(count));
+        } else if name == tags.table {
+            if closing {
+                table_depth = table_depth.saturating_sub(1);
+            } else if !self_closing {
+                table_depth += 1;
+            }
+        } else if name == tags.cell && (closing || self_closing) {
+            cells.push(mem::take(&mut text));
+        } else if name == tags.row && closing {
+            rows.push(mem::take(&mut cells));
+        }
+    }
+
+    rows
+}
+
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let value = &tag[tag.find(&format!("{}=\"", name))? + name.len() + 2..];
+    Some(&value[..value.find('"')?])
+}
+
+fn unescape(text: &str) -> String {
+    if !text.contains('&') {
+        return text.to_string();
+    }
+
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find(';') else {
+            break;
+        };
+
+        let entity = &rest[1..end];
+        let decoded = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(str::parse))
+                .and_then(|code| code.ok())
+                .and_then(char::from_u32),
+        };
+        match decoded {
+            Some(c) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+impl MyApp {
+    /// Asks for a script and opens the preview, with its first column going
+    /// to `first_track`
+    pub fn open_text_import(&mut self, first_track: usize) {
+        let Some(path) = rfd::FileDialog::new()
+            .add_filter("Text", SUPPORTED_EXTENSIONS)
+            .set_title("Import Text")
+            .pick_file()
+        else {
+            return;
+        };
+
+        match TextImport::open(&path, first_track, self.tracks.len()) {
+            Ok(import) => {
+                self.text_import = Some(import);
+                self.project.error = None;
+            }
+            Err(e) => self.project.error = Some(e),
+        }
+    }
+
+    /// Fills the tracks from the previewed file, adding tracks as needed
+    fn apply_text_import(&mut self, import: &TextImport) {
+        for (column, target) in import.targets.iter().enumerate() {
+            let index = match *target {
+                ImportTarget::Skip => continue,
+                ImportTarget::Track(index) if index < self.tracks.len() => index,
+                ImportTarget::Track(_) | ImportTarget::NewTrack => {
+                    self.tracks.push(Track::new(self.tracks.len()));
+                    self.tracks.len() - 1
+                }
+            };
+
+            let track = &mut self.tracks[index];
+            track.text = import.column_text(column);
+            if let Some(header) = import.header(column)
+                && (track.label.trim().is_empty() || track.label == Track::new(index).label)
+            {
+                track.label = header.to_string();
+            }
+        }
+    }
+
+    /// Preview window of the file being imported
+    pub fn render_text_import(&mut self, ctx: &egui::Context) {
+        let Some(import) = &mut self.text_import else {
+            return;
+        };
+
+        let mut open = true;
+        let mut confirmed = false;
+        let mut cancelled = false;
+        let track_labels: Vec<String> = self.tracks.iter().map(|t| t.label.clone()).collect();
+
+        egui::Window::new("Import Text")
+            .open(&mut open)
+            .collapsible(false)
+            .default_width(600.0)
+            .show(ctx, |ui| {
+                ui.label(format!(
+                    "{} ({})",
+                    import
+                        .path
+                        .file_name()
+                        .unwrap_or_default()
+                        .to_string_lossy(),
+                    import.format.label()
+                ));
+
+                ui.horizontal(|ui| {
+                    if import.format != TextFormat::Document {
+                        ui.label("Encoding:");
+                        let mut encoding = import.encoding;
+                        egui::ComboBox::from_id_source("import_encoding")
+                            .selected_text(encoding.name())
+                            .show_ui(ui, |ui| {
+                                for &option in ENCODINGS {
+                                    let mut label = option.name().to_string();
+                                    if option == import.detected_encoding {
+                                        label.push_str(" (detected)");
+                                    }
+                                    ui.selectable_value(&mut encoding, option, label);
+                                }
+                            });
+                        import.set_encoding(encoding);
+                    }
+
+                    if import.column_count() > 1 {
+                        ui.checkbox(&mut import.first_row_is_header, "First row is a header");
+                    }
+                });
+
+                ui.separator();
+                egui::Grid::new("import_targets")
+                    .num_columns(3)
+                    .show(ui, |ui| {
+                        for column in 0..import.column_count() {
+                            let name = import
+                                .header(column)
+                                .map_or_else(|| format!("Column {}", column + 1), str::to_string);
+                            ui.label(name);
+
+                            let target = &mut import.targets[column];
+                            let target_label = |target: ImportTarget| match target {
+                                ImportTarget::Skip => "Skip".to_string(),
+                                ImportTarget::Track(index) => track_labels
+                                    .get(index)
+                                    .map_or_else(|| format!("Track {}", index + 1), Clone::clone),
+                                ImportTarget::NewTrack => "New track".to_string(),
+                            };
+                            egui::ComboBox::from_id_source(("import_target", column))
+                                .selected_text(target_label(*target))
+                                .show_ui(ui, |ui| {
+                                    let options = (0..track_labels.len())
+                                        .map(ImportTarget::Track)
+                                        .chain([ImportTarget::NewTrack, ImportTarget::Skip]);
+                                    for option in options {
+                                        ui.selectable_value(target, option, target_label(option));
+                                    }
+                                });
+
+                            let lines = import
+                                .body()
+                                .iter()
+                                .filter(|row| row.get(column).is_some_and(|c| !c.is_empty()))
+                                .count();
+                            ui.label(format!("{} sentences", lines));
+                            ui.end_row();
+                        }
+                    });
+
+                ui.separator();
+                ui.label("Preview:");
+                egui::ScrollArea::both().max_height(300.0).show(ui, |ui| {
+                    egui::Grid::new("import_preview")
+                        .striped(true)
+                        .num_columns(import.column_count() + 1)
+                        .show(ui, |ui| {
+                            for (i, row) in import.body().iter().take(PREVIEW_ROWS).enumerate() {
+                                ui.weak(format!("{}", i + 1));
+                                for column in 0..import.column_count() {
+                                    ui.label(row.get(column).map_or("", String::as_str));
+                                }
+                                ui.end_row();
+                            }
+                        });
+                    if import.body().len() > PREVIEW_ROWS {
+                        ui.weak(format!("… and {} more", import.body().len() - PREVIEW_ROWS));
+                    }
+                });
+
+                ui.separator();
+                ui.horizontal(|ui| {
+                    let any_target = import.targets.iter().any(|t| *t != ImportTarget::Skip);
+                    if ui
+                        .add_enabled(any_target, egui::Button::new("Import"))
+                        .on_hover_text("Replaces the text of the chosen tracks")
+                        .clicked()
+                    {
+                        confirmed = true;
+                    }
+                    if ui.button("Cancel").clicked() {
+                        cancelled = true;
+                    }
+                });
+            });
+
+        if confirmed {
+            if let Some(import) = self.text_import.take() {
+                self.apply_text_import(&import);
+            }
+        } else if cancelled || !open {
+            self.text_import = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Rows with the cells trimmed, as the import does with every sentence
+    fn rows(xml: &str, tags: &DocumentTags) -> Vec<Vec<String>> {
+        document_rows(xml, tags)
+            .into_iter()
+            .map(|row| row.iter().map(|cell| cell.trim().to_string()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn reads_docx_paragraphs_tabs_breaks_and_tables() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<w:document><w:body>
+<w:p><w:pPr><w:tabs><w:tab w:val="left" w:pos="720"/></w:tabs></w:pPr><w:r><w:t>Bonġu</w:t></w:r><w:r><w:tab/><w:t>Good morning</w:t></w:r></w:p>
+<w:p><w:r><w:t xml:space="preserve">Ħobż &amp; ġobon &lt;3 &#x126;&#295;</w:t></w:r></w:p>
+<w:p><w:r><w:t>One</w:t><w:br/><w:t>Two</w:t></w:r></w:p>
+<w:p><w:r><w:delText>deleted</w:delText></w:r></w:p>
+<w:tbl><w:tr>
+<w:tc><w:p><w:r><w:t>Kif int?</w:t></w:r></w:p></w:tc>
+<w:tc><w:p><w:r><w:t>How</w:t></w:r></w:p><w:p><w:r><w:t>are you?</w:t></w:r></w:p></w:tc>
+</w:tr></w:tbl>
+</w:body></w:document>"#;
+
+        assert_eq!(
+            rows(xml, &DOCX_TAGS),
+            [
+                vec!["Bonġu", "Good morning"],
+                vec!["Ħobż & ġobon <3 Ħħ"],
+                vec!["One"],
+                vec!["Two"],
+                vec![""],
+                vec!["Kif int?", "How are you?"],
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_odt_paragraphs_spaces_and_tables() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<office:document-content><office:body><office:text>
+<text:h text:outline-level="1">Lezzjoni&#160;1</text:h>
+<text:p>Il-<text:s text:c="2"/>kelb<text:tab/>The dog</text:p>
+<text:p>Line one<text:line-break/>line two<text:note><text:note-body><text:p>footnote</text:p></text:note-body></text:note></text:p>
+<table:table><table:table-row>
+<table:table-cell><text:p>Iva</text:p></table:table-cell>
+<table:table-cell><text:p>Yes</text:p></table:table-cell>
+</table:table-row></table:table>
+</office:text></office:body></office:document-content>"#;
+
+        assert_eq!(
+            rows(xml, &ODT_TAGS),
+            [
+                vec!["Lezzjoni\u{a0}1"],
+                vec!["Il-  kelb", "The dog"],
+                vec!["Line one"],
+                vec!["line two"],
+                vec!["Iva", "Yes"],
+            ]
+        );
+    }
+
+    #[test]
+    fn unescapes_entities_and_keeps_unknown_ones() {
+        assert_eq!(unescape("a &amp;&quot;b&apos; &#65;&#x42;"), "a &\"b' AB");
+        assert_eq!(unescape("R&D &nbsp; &"), "R&D &nbsp; &");
+    }
+
+    #[test]
+    fn reads_srt_cues_without_numbers_timings_or_tags() {
+        let srt = "1\r\n00:00:01,000 --> 00:00:02,000\r\n<i>Bonġu</i>\r\n\r\n\
+                   2\r\n00:00:03,000 --> 00:00:04,000\r\n{\\an8}Kif int?\r\n<b>Tajjeb.</b>\r\n";
+        assert_eq!(
+            subtitle_rows(srt),
+            [
+                vec!["Bonġu".to_string()],
+                vec!["Kif int? Tajjeb.".to_string()]
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_vtt_cues_and_skips_header_and_notes() {
+        let vtt = "WEBVTT\n\nNOTE written by hand\n\nintro\n\
+                   00:01.000 --> 00:02.000 align:start\n<v Marija>Saħħa</v>\n\n\
+                   00:03.000 --> 00:04.000\nGrazzi\n";
+        assert_eq!(
+            subtitle_rows(vtt),
+            [vec!["Saħħa".to_string()], vec!["Grazzi".to_string()]]
+        );
+    }
+
+    #[test]
+    fn detects_maltese_latin3() {
+        let text = "Il-ħobż u l-ġobon ta' Għawdex huma tajbin ħafna.\nŻ Ġ Ċ ċ";
+        let (bytes, _, unmappable) = ISO_8859_3.encode(text);
+        assert!(!unmappable);
+
+        let encoding = detect_encoding(&bytes);
+        assert_eq!(encoding, ISO_8859_3);
+        assert_eq!(decode(&bytes, encoding), text);
+    }
+
+    #[test]
+    fn keeps_central_european_guesses_for_other_languages() {
+        let text = "Zażółć gęślą jaźń, powiedział książę.";
+        let (bytes, _, _) = encoding_rs::ISO_8859_2.encode(text);
+        assert_ne!(detect_encoding(&bytes), ISO_8859_3);
+    }
+
+    #[test]
+    fn detects_utf8_and_byte_order_marks() {
+        let text = "Il-ħobż";
+        assert_eq!(detect_encoding(text.as_bytes()), UTF_8);
+
+        let mut utf16: Vec<u8> = vec![0xFF, 0xFE];
+        utf16.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+        assert_eq!(detect_encoding(&utf16), UTF_16LE);
+        assert_eq!(decode(&utf16, UTF_16LE), text);
+    }
+}
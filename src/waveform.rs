@@ -0,0 +1,360 @@
+use std::sync::Arc;
+
+use egui::{Align2, CursorIcon, FontId, Pos2, Rect, Sense, Stroke, vec2};
+
+use crate::{TrackAnalysis, alignment::ClipEdit, decode::DecodedAudio, silence::Segment};
+
+/// Frames summarised by one precomputed peak
+const PEAK_BUCKET: usize = 256;
+/// How close the pointer has to be to a boundary to grab it, in points
+const HANDLE_GRAB_DISTANCE: f32 = 5.0;
+const WAVEFORM_HEIGHT: f32 = 110.0;
+const MAX_ZOOM: f64 = 2000.0;
+/// Shortest clip a drag may leave, in seconds
+const MIN_CLIP_LENGTH: f64 = 0.05;
+
+/// Smallest and largest sample of every `PEAK_BUCKET` frames, all channels
+/// mixed, so a whole recording can be drawn without touching every sample
+#[derive(Debug)]
+pub struct Peaks {
+    min: Vec<f32>,
+    max: Vec<f32>,
+}
+
+impl Peaks {
+    pub fn new(audio: &DecodedAudio) -> Self {
+        let channels = audio.channels.max(1) as usize;
+        let buckets = audio.frame_count().div_ceil(PEAK_BUCKET);
+        let mut min = Vec::with_capacity(buckets);
+        let mut max = Vec::with_capacity(buckets);
+
+        for bucket in audio.samples.chunks(PEAK_BUCKET * channels) {
+            let (low, high) = frame_range(bucket, channels);
+            min.push(low);
+            max.push(high);
+        }
+        Self { min, max }
+    }
+}
+
+/// Lowest and highest value of the frames in `samples`, between -1 and 1
+fn frame_range(samples: &[i16], channels: usize) -> (f32, f32) {
+    samples
+        .chunks(channels)
+        .map(|frame| frame.iter().map(|&s| s as f32).sum::<f32>() / (channels as f32 * 32768.0))
+        .fold((0.0f32, 0.0f32), |(low, high), value| {
+            (low.min(value), high.max(value))
+        })
+}
+
+/// Which end of a segment a handle moves
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Edge {
+    Start,
+    End,
+}
+
+/// Zoom, scroll position and the drag in progress of one track's waveform
+#[derive(Debug, Default)]
+pub struct WaveformState {
+    peaks: Option<Arc<Peaks>>,
+    /// 1 shows the whole recording
+    zoom: f64,
+    /// Time at the left edge of the view, in seconds
+    view_start: f64,
+    /// Segment, edge and where the handle is being dragged to
+    drag: Option<(usize, Edge, f64)>,
+}
+
+/// What the user did in the waveform
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WaveformAction {
+    Edit(ClipEdit),
+    /// Play the source recording between two times, in seconds
+    Play {
+        from: f64,
+        to: f64,
+    },
+}
+
+/// Draws the source recording of a track with its segments on top. Segment
+/// edges can be dragged, a click plays from that point to the end of the
+/// segment under it, and the view zooms with Ctrl + scroll. `playhead` is
+/// where the player is in this recording, if it is playing it.
+pub(crate) fn render_waveform(
+    ui: &mut egui::Ui,
+    track: &mut TrackAnalysis,
+    playhead: Option<f64>,
+) -> Option<WaveformAction> {
+    let source = track.source.clone()?;
+    let duration = source.duration();
+    if duration <= 0.0 {
+        return None;
+    }
+
+    let state = &mut track.waveform;
+    let peaks = state
+        .peaks
+        .get_or_insert_with(|| Arc::new(Peaks::new(&source)))
+        .clone();
+    if state.zoom < 1.0 {
+        state.zoom = 1.0;
+    }
+
+    let mut action = None;
+
+    ui.horizontal(|ui| {
+        ui.label(&track.label);
+        if ui.small_button("−").on_hover_text("Zoom out").clicked() {
+            state.zoom = (state.zoom / 2.0).max(1.0);
+        }
+        if ui.small_button("+").on_hover_text("Zoom in").clicked() {
+            state.zoom = (state.zoom * 2.0).min(MAX_ZOOM);
+        }
+        if ui.small_button("Fit").clicked() {
+            state.zoom = 1.0;
+            state.view_start = 0.0;
+        }
+
+        let view_length = duration / state.zoom;
+        if state.zoom > 1.0 {
+            ui.add(
+                egui::Slider::new(&mut state.view_start, 0.0..=duration - view_length)
+                    .show_value(false),
+            );
+        }
+        ui.weak(format!(
+            "{:.2}s – {:.2}s of {:.2}s",
+            state.view_start,
+            state.view_start + view_length,
+            duration
+        ));
+    });
+
+    let (rect, response) = ui.allocate_exact_size(
+        vec2(ui.available_width(), WAVEFORM_HEIGHT),
+        Sense::click_and_drag(),
+    );
+
+    // Ctrl + scroll zooms around the pointer, sideways scrolling pans
+    if let Some(pointer) = response.hover_pos() {
+        let (zoom_delta, scroll_delta) = ui.input(|i| (i.zoom_delta(), i.scroll_delta));
+        let anchor = time_at(state, duration, rect, pointer.x);
+        if zoom_delta != 1.0 {
+            state.zoom = (state.zoom * zoom_delta as f64).clamp(1.0, MAX_ZOOM);
+            let fraction = ((pointer.x - rect.left()) / rect.width()) as f64;
+            state.view_start = anchor - fraction * duration / state.zoom;
+        } else if state.zoom > 1.0 && scroll_delta.x != 0.0 {
+            state.view_start -= scroll_delta.x as f64 / rect.width() as f64 * duration / state.zoom;
+        }
+    }
+    let view_length = duration / state.zoom;
+    state.view_start = state.view_start.clamp(0.0, duration - view_length);
+
+    let x_at = |state: &WaveformState, time: f64| {
+        rect.left() + ((time - state.view_start) / view_length) as f32 * rect.width()
+    };
+
+    // Nearest edge within reach of the pointer
+    let handle_at = |state: &WaveformState, x: f32| {
+        track
+            .segments
+            .iter()
+            .enumerate()
+            .flat_map(|(i, segment)| [(i, Edge::Start, segment.start), (i, Edge::End, segment.end)])
+            .map(|(i, edge, time)| (i, edge, (x_at(state, time) - x).abs()))
+            .filter(|(_, _, distance)| *distance <= HANDLE_GRAB_DISTANCE)
+            .min_by(|a, b| a.2.total_cmp(&b.2))
+            .map(|(i, edge, _)| (i, edge))
+    };
+
+    if let Some(pointer) = response.hover_pos()
+        && (state.drag.is_some() || handle_at(state, pointer.x).is_some())
+    {
+        ui.ctx().set_cursor_icon(CursorIcon::ResizeHorizontal);
+    }
+
+    if response.drag_started()
+        && let Some(pointer) = response.interact_pointer_pos()
+        && let Some((index, edge)) = handle_at(state, pointer.x)
+    {
+        let time = match edge {
+            Edge::Start => track.segments[index].start,
+            Edge::End => track.segments[index].end,
+        };
+        state.drag = Some((index, edge, time));
+    }
+
+    let pointer_time = response
+        .interact_pointer_pos()
+        .map(|pointer| time_at(state, duration, rect, pointer.x));
+    if let Some((index, edge, time)) = &mut state.drag {
+        if let Some(wanted) = pointer_time {
+            *time = clamp_edge(&track.segments, *index, *edge, wanted, duration);
+        }
+
+        if response.drag_released() {
+            let segment = track.segments[*index];
+            let (start, end) = match edge {
+                Edge::Start => (*time, segment.end),
+                Edge::End => (segment.start, *time),
+            };
+            action = Some(WaveformAction::Edit(ClipEdit::Resize {
+                index: *index,
+                start,
+                end,
+            }));
+            state.drag = None;
+        }
+    } else if response.clicked()
+        && let Some(from) = pointer_time
+    {
+        let to = track
+            .segments
+            .iter()
+            .find(|segment| segment.start <= from && from < segment.end)
+            .map_or((from + 5.0).min(duration), |segment| segment.end);
+        action = Some(WaveformAction::Play { from, to });
+    }
+
+    // Drawing
+    let painter = ui.painter_at(rect);
+    let visuals = ui.visuals();
+    painter.rect_filled(rect, 2.0, visuals.extreme_bg_color);
+
+    let segment_fill = visuals.selection.bg_fill.linear_multiply(0.35);
+    let handle_color = visuals.selection.stroke.color;
+    let drag_color = visuals.warn_fg_color;
+    for (i, segment) in track.segments.iter().enumerate() {
+        let mut segment = *segment;
+        if let Some((index, edge, time)) = state.drag
+            && index == i
+        {
+            match edge {
+                Edge::Start => segment.start = time,
+                Edge::End => segment.end = time,
+            }
+        }
+
+        let left = x_at(state, segment.start);
+        let right = x_at(state, segment.end);
+        if right < rect.left() || left > rect.right() {
+            continue;
+        }
+
+        painter.rect_filled(
+            Rect::from_x_y_ranges(left..=right, rect.y_range()),
+            0.0,
+            segment_fill,
+        );
+        for (edge, x) in [(Edge::Start, left), (Edge::End, right)] {
+            let dragged = state
+                .drag
+                .is_some_and(|(index, e, _)| index == i && e == edge);
+            painter.line_segment(
+                [Pos2::new(x, rect.top()), Pos2::new(x, rect.bottom())],
+                Stroke::new(
+                    if dragged { 2.0 } else { 1.0 },
+                    if dragged { drag_color } else { handle_color },
+                ),
+            );
+        }
+        painter.text(
+            Pos2::new(left + 3.0, rect.top() + 2.0),
+            Align2::LEFT_TOP,
+            format!("{}", i + 1),
+            FontId::proportional(11.0),
+            visuals.text_color(),
+        );
+    }
+
+    // One vertical line per point from the lowest to the highest sample under it
+    let wave_color = visuals.text_color().linear_multiply(0.8);
+    let channels = source.channels.max(1) as usize;
+    let frames_per_point = view_length * source.sample_rate as f64 / rect.width() as f64;
+    let mid = rect.center().y;
+    let half = rect.height() / 2.0 - 2.0;
+    for column in 0..rect.width().ceil() as usize {
+        let first = ((state.view_start * source.sample_rate as f64
+            + column as f64 * frames_per_point) as usize)
+            .min(source.frame_count());
+        let last = ((first as f64 + frames_per_point.max(1.0)) as usize).min(source.frame_count());
+        if first >= last {
+            continue;
+        }
+
+        let (low, high) = if frames_per_point >= PEAK_BUCKET as f64 {
+            let buckets = first / PEAK_BUCKET..last.div_ceil(PEAK_BUCKET).min(peaks.min.len());
+            (
+                peaks.min[buckets.clone()]
+                    .iter()
+                    .copied()
+                    .fold(0.0, f32::min),
+                peaks.max[buckets].iter().copied().fold(0.0, f32::max),
+            )
+        } else {
+            frame_range(&source.samples[first * channels..last * channels], channels)
+        };
+
+        let x = rect.left() + column as f32 + 0.5;
+        painter.line_segment(
+            [
+                Pos2::new(x, mid - high * half),
+                Pos2::new(x, mid - low * half + 1.0),
+            ],
+            Stroke::new(1.0, wave_color),
+        );
+    }
+
+    if let Some(time) = playhead
+        && (state.view_start..=state.view_start + view_length).contains(&time)
+    {
+        let x = x_at(state, time);
+        painter.line_segment(
+            [Pos2::new(x, rect.top()), Pos2::new(x, rect.bottom())],
+            Stroke::new(2.0, visuals.hyperlink_color),
+        );
+    }
+
+    if let Some(pointer) = response.hover_pos() {
+        response
+            .on_hover_text_at_pointer(format!("{:.2}s", time_at(state, duration, rect, pointer.x)));
+    }
+
+    action
+}
+
+/// Time under the x coordinate, within the recording
+fn time_at(state: &WaveformState, duration: f64, rect: Rect, x: f32) -> f64 {
+    let view_length = duration / state.zoom.max(1.0);
+    let fraction = ((x - rect.left()) / rect.width()).clamp(0.0, 1.0) as f64;
+    (state.view_start + fraction * view_length).clamp(0.0, duration)
+}
+
+/// Keeps a dragged edge inside the recording, off its neighbours and at
+/// least `MIN_CLIP_LENGTH` away from the segment's other edge. Neighbours
+/// are found by time, as reordered entries take their segments along.
+fn clamp_edge(segments: &[Segment], index: usize, edge: Edge, time: f64, duration: f64) -> f64 {
+    let segment = segments[index];
+    let others = segments
+        .iter()
+        .enumerate()
+        .filter(|&(i, _)| i != index)
+        .map(|(_, other)| other);
+    match edge {
+        Edge::Start => {
+            let floor = others
+                .map(|other| other.end)
+                .filter(|&end| end <= segment.start)
+                .fold(0.0, f64::max);
+            time.clamp(floor, (segment.end - MIN_CLIP_LENGTH).max(floor))
+        }
+        Edge::End => {
+            let ceiling = others
+                .map(|other| other.start)
+                .filter(|&start| start >= segment.end)
+                .fold(duration, f64::min);
+            time.clamp((segment.start + MIN_CLIP_LENGTH).min(ceiling), ceiling)
+        }
+    }
+}